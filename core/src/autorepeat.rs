@@ -0,0 +1,97 @@
+// Key auto-repeat with acceleration
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+const REPEAT_DELAY: u64 = 500;      // ms held before the first repeat
+const REPEAT_INTERVAL: u64 = 150;   // ms between the first repeats
+const REPEAT_INTERVAL_MIN: u64 = 30;
+const REPEAT_ACCEL_PERCENT: u64 = 85; // each repeat comes after 85% of the previous interval
+
+/// Repeat timing of one held key. Time is passed in (ms), so it can be driven
+/// with synthetic sequences.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AutoRepeat {
+    next_at: Option<u64>,
+    interval: u64,
+    count: u32,
+}
+
+impl AutoRepeat {
+    pub fn press(&mut self, now: u64) {
+        self.next_at = Some(now + REPEAT_DELAY);
+        self.interval = REPEAT_INTERVAL;
+        self.count = 0;
+    }
+
+    /// Stop repeating. Returns the number of repeats of this press.
+    pub fn release(&mut self) -> u32 {
+        self.next_at = None;
+        std::mem::take(&mut self.count)
+    }
+
+    /// True if a repeat is due at `now`. At most one per call, so a late
+    /// poll does not produce a burst.
+    pub fn poll(&mut self, now: u64) -> bool {
+        match self.next_at {
+            Some(at) if now >= at => {
+                self.count += 1;
+                self.next_at = Some(now + self.interval);
+                self.interval = (self.interval * REPEAT_ACCEL_PERCENT / 100).max(REPEAT_INTERVAL_MIN);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // times of the repeats while polling every ms from `from` to `until`
+    fn repeats(repeat: &mut AutoRepeat, from: u64, until: u64) -> Vec<u64> {
+        (from..=until).filter(|now| repeat.poll(*now)).collect()
+    }
+
+    #[test]
+    fn first_repeat_after_delay() {
+        let mut repeat = AutoRepeat::default();
+        assert!(!repeat.poll(1000));
+        repeat.press(1000);
+        assert_eq!(repeats(&mut repeat, 1000, 1649), [1500]);
+    }
+
+    #[test]
+    fn repeats_accelerate_to_the_floor() {
+        let mut repeat = AutoRepeat::default();
+        repeat.press(0);
+        let times = repeats(&mut repeat, 0, 1500);
+        let intervals : Vec<u64> = times.windows(2).map(|w| w[1] - w[0]).collect();
+        assert_eq!(intervals[..12], [150, 127, 107, 90, 76, 64, 54, 45, 38, 32, 30, 30]);
+        assert!(intervals[12..].iter().all(|i| *i == REPEAT_INTERVAL_MIN));
+    }
+
+    #[test]
+    fn late_poll_repeats_once() {
+        let mut repeat = AutoRepeat::default();
+        repeat.press(0);
+        assert!(repeat.poll(2000));
+        assert!(!repeat.poll(2000));
+        // the next one counts from the late poll
+        assert!(!repeat.poll(2149));
+        assert!(repeat.poll(2150));
+    }
+
+    #[test]
+    fn release_returns_count() {
+        let mut repeat = AutoRepeat::default();
+        repeat.press(0);
+        assert_eq!(repeats(&mut repeat, 0, 800).len(), 3);
+        assert_eq!(repeat.release(), 3);
+        assert!(!repeat.poll(5000));
+        assert_eq!(repeat.release(), 0);
+        repeat.press(6000);
+        assert_eq!(repeats(&mut repeat, 6000, 6500).len(), 1);
+        assert_eq!(repeat.release(), 1);
+    }
+}
@@ -0,0 +1,185 @@
+// Button to action bindings
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use crate::gesture::Gesture;
+use crate::keys::Key;
+use crate::targets::{self, ALL_TARGETS};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ButtonAction {
+    None,
+    Wake(String),   // target or wake group
+    Sleep(String),  // target or wake group, Sleep-On-LAN
+    ShowStatus,
+    OpenMenu,
+    ForceSleep,     // put the box itself to sleep
+}
+
+impl ButtonAction {
+    /// `none`, `wake:PC1`, `wake:office`, `sleep:PC2`, `status`, `menu` or `sleep`.
+    pub fn parse(value: &str) -> ButtonAction {
+        let value = value.trim();
+        if let Some((kind, name)) = value.split_once(':') {
+            let name = name.trim();
+            if name.is_empty() {
+                return ButtonAction::None;
+            }
+            return match kind.trim().to_lowercase().as_str() {
+                "wake" => ButtonAction::Wake(name.to_string()),
+                "sleep" => ButtonAction::Sleep(name.to_string()),
+                _ => ButtonAction::None,
+            };
+        }
+        match value.to_lowercase().as_str() {
+            "status" => ButtonAction::ShowStatus,
+            "menu" => ButtonAction::OpenMenu,
+            "sleep" => ButtonAction::ForceSleep,
+            _ => ButtonAction::None,
+        }
+    }
+}
+
+/// One configurable binding: config key, menu title and default action.
+pub struct Binding {
+    pub key: &'static str,
+    pub title: &'static str,
+    pub default: &'static str,
+}
+
+const fn binding(key: &'static str, title: &'static str, default: &'static str) -> Binding {
+    Binding { key, title, default }
+}
+
+// Click, double press and long press of each key in `ALL_KEYS` order, then chords.
+// The defaults are the classic layout: Up/Down/Left/Right wake PC1-PC4, Center opens the menu.
+pub const BINDINGS: [Binding; 16] = [
+    binding("BTNUPCLICK", "UP CLICK", "wake:PC1"),
+    binding("BTNUPDOUBLE", "UP DOUBLE", "wake:PC1"),
+    binding("BTNUPLONG", "UP LONG", "wake:PC1"),
+    binding("BTNDOWNCLICK", "DOWN CLICK", "wake:PC2"),
+    binding("BTNDOWNDOUBLE", "DOWN DOUBLE", "wake:PC2"),
+    binding("BTNDOWNLONG", "DOWN LONG", "wake:PC2"),
+    binding("BTNLEFTCLICK", "LEFT CLICK", "wake:PC3"),
+    binding("BTNLEFTDOUBLE", "LEFT DOUBLE", "wake:PC3"),
+    binding("BTNLEFTLONG", "LEFT LONG", "wake:PC3"),
+    binding("BTNRIGHTCLICK", "RIGHT CLICK", "wake:PC4"),
+    binding("BTNRIGHTDOUBLE", "RIGHT DOUBLE", "wake:PC4"),
+    binding("BTNRIGHTLONG", "RIGHT LONG", "wake:PC4"),
+    binding("BTNCENTERCLICK", "CENTER CLICK", "menu"),
+    binding("BTNCENTERDOUBLE", "CENTER DOUBLE", "menu"),
+    binding("BTNCENTERLONG", "CENTER LONG", "menu"),
+    binding("BTNCHORD", "CHORD", "wake:all"),
+];
+
+/// Config key of the binding a gesture triggers. Chords with the Center key are not bound.
+pub fn binding_key(gesture: &Gesture) -> Option<&'static str> {
+    let (key, kind) = match gesture {
+        Gesture::Click(key) => (key, 0),
+        Gesture::DoublePress(key) => (key, 1),
+        Gesture::LongPress(key) => (key, 2),
+        Gesture::Chord(a, b) => {
+            return if *a != Key::Center && *b != Key::Center { Some("BTNCHORD") } else { None };
+        },
+    };
+    Some(BINDINGS[key.index() * 3 + kind].key)
+}
+
+/// Choices for the Buttons menu: every target and wake group, then the other actions.
+pub fn action_items(groups: &str) -> Vec<String> {
+    let mut names : Vec<String> = ALL_TARGETS.iter().map(|t| t.name().to_string()).collect();
+    names.extend(targets::parse_groups(groups).into_iter().map(|g| g.0));
+    let mut items = vec!["none".to_string()];
+    items.extend(names.iter().map(|n| format!("wake:{}", n)));
+    items.extend(names.iter().map(|n| format!("sleep:{}", n)));
+    items.extend(["status", "menu", "sleep"].iter().map(|s| s.to_string()));
+    items
+}
+
+/// Actions of all bindings, built from the (config key, action) entries.
+pub struct ButtonMap {
+    actions: Vec<(&'static str, ButtonAction)>,
+}
+
+impl ButtonMap {
+    pub fn new(entries: &[(String, String)]) -> ButtonMap {
+        let actions = BINDINGS.iter().map(|b| {
+            let value = entries.iter().find(|it| it.0 == b.key).map(|it| it.1.as_str()).unwrap_or(b.default);
+            (b.key, ButtonAction::parse(value))
+        }).collect();
+        ButtonMap { actions }
+    }
+
+    /// Action for a gesture. If nothing is bound to the menu, a Center click
+    /// still opens it, so the settings cannot be locked away.
+    pub fn action(&self, gesture: &Gesture) -> ButtonAction {
+        if *gesture == Gesture::Click(Key::Center) && !self.actions.iter().any(|a| a.1 == ButtonAction::OpenMenu) {
+            return ButtonAction::OpenMenu;
+        }
+        binding_key(gesture)
+            .and_then(|key| self.actions.iter().find(|a| a.0 == key))
+            .map(|a| a.1.clone())
+            .unwrap_or(ButtonAction::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn parse_actions() {
+        assert_eq!(ButtonAction::parse(" wake: office "), ButtonAction::Wake("office".to_string()));
+        assert_eq!(ButtonAction::parse("SLEEP:PC2"), ButtonAction::Sleep("PC2".to_string()));
+        assert_eq!(ButtonAction::parse("sleep"), ButtonAction::ForceSleep);
+        assert_eq!(ButtonAction::parse("Menu"), ButtonAction::OpenMenu);
+        assert_eq!(ButtonAction::parse("status"), ButtonAction::ShowStatus);
+        for value in ["", "none", "wake:", "boot:PC1", "reboot"] {
+            assert_eq!(ButtonAction::parse(value), ButtonAction::None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn binding_keys() {
+        assert_eq!(binding_key(&Gesture::Click(Key::Up)), Some("BTNUPCLICK"));
+        assert_eq!(binding_key(&Gesture::DoublePress(Key::Right)), Some("BTNRIGHTDOUBLE"));
+        assert_eq!(binding_key(&Gesture::LongPress(Key::Center)), Some("BTNCENTERLONG"));
+        assert_eq!(binding_key(&Gesture::Chord(Key::Up, Key::Down)), Some("BTNCHORD"));
+        // a chord with the Center key is not bound
+        assert_eq!(binding_key(&Gesture::Chord(Key::Left, Key::Center)), None);
+        assert_eq!(binding_key(&Gesture::Chord(Key::Center, Key::Right)), None);
+    }
+
+    #[test]
+    fn defaults_and_overrides() {
+        let map = ButtonMap::new(&entries(&[("BTNUPCLICK", "sleep:PC1"), ("BTNCHORD", "none")]));
+        assert_eq!(map.action(&Gesture::Click(Key::Up)), ButtonAction::Sleep("PC1".to_string()));
+        assert_eq!(map.action(&Gesture::Click(Key::Down)), ButtonAction::Wake("PC2".to_string()));
+        assert_eq!(map.action(&Gesture::Chord(Key::Up, Key::Down)), ButtonAction::None);
+        assert_eq!(map.action(&Gesture::Chord(Key::Up, Key::Center)), ButtonAction::None);
+    }
+
+    #[test]
+    fn center_click_opens_menu_when_unbound() {
+        let unbound = entries(&[("BTNCENTERCLICK", "status"), ("BTNCENTERDOUBLE", "none"), ("BTNCENTERLONG", "none")]);
+        assert_eq!(ButtonMap::new(&unbound).action(&Gesture::Click(Key::Center)), ButtonAction::OpenMenu);
+        let mut bound = unbound.clone();
+        bound.push(("BTNUPLONG".to_string(), "menu".to_string()));
+        assert_eq!(ButtonMap::new(&bound).action(&Gesture::Click(Key::Center)), ButtonAction::ShowStatus);
+    }
+
+    #[test]
+    fn menu_items() {
+        let items = action_items("office:PC1+PC2");
+        assert_eq!(items.first().map(|s| s.as_str()), Some("none"));
+        assert!(items.contains(&"wake:office".to_string()));
+        assert!(items.contains(&"wake:all".to_string()));
+        assert!(items.contains(&"sleep:PC4".to_string()));
+        // none, wake and sleep for 4 targets and the groups all and office, 3 others
+        assert_eq!(items.len(), 1 + 6 * 2 + 3);
+    }
+}
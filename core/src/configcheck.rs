@@ -0,0 +1,83 @@
+// Format checks for config values set from outside the menu
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use crate::targets;
+
+// Longest value of a free text key; the webhook template may be longer
+pub const MAX_TEXT_LEN: usize = 128;
+pub const MAX_URL_LEN: usize = 256;
+pub const MAX_TEMPLATE_LEN: usize = 512;
+// The saved config must fit in one NVS string (4000 bytes) with some room to spare
+pub const MAX_CONFIG_SIZE: usize = 3584;
+
+/// Whether `value` has the format `key` needs. Keys without a fixed format
+/// (names, hosts, URLs) only have a length limit. No value may contain control
+/// characters, since the config is saved as TOML.
+pub fn check_config_value(key: &str, value: &str) -> bool {
+    if value.chars().any(|c| c.is_control()) {
+        return false;
+    }
+    match key {
+        "PC1" | "PC2" | "PC3" | "PC4" => targets::parse_mac(value).is_some(),
+        "TIMEZONE" => value.parse::<i32>().map(|v| (-12..=14).contains(&v)).unwrap_or(false),
+        "IDLESLEEP" | "DISPLAYOFFTIME" => value.parse::<u32>().is_ok(),
+        "LONGPRESS" | "DOUBLEPRESS" | "CHORDTIME" => value.parse::<u32>().is_ok(),
+        "INFLUXINTERVAL" => value.parse::<u32>().map(|v| v > 0).unwrap_or(false),
+        "WIFILISTEN" => value.parse::<u16>().map(|v| v > 0).unwrap_or(false),
+        "WIFITXPOWER" => value.parse::<u8>().map(|v| v == 0 || (2..=20).contains(&v)).unwrap_or(false),
+        "PROFILE" => value.split(':').next().and_then(|v| v.trim().parse::<usize>().ok()).map(|v| v >= 1).unwrap_or(false),
+        "WIFIAUTH" => ["psk", "eap"].contains(&value),
+        "WPS" | "HTTPAPI" => ["true", "false"].contains(&value),
+        "WPSMODE" => ["pbc", "pin"].contains(&value),
+        "SLEEPMODE" => ["light", "deep"].contains(&value),
+        "WIFIPS" => ["none", "min", "max"].contains(&value),
+        "SYSLOGLEVEL" => ["error", "warn", "info", "debug"].contains(&value),
+        "WEBHOOKMETHOD" => ["POST", "PUT", "GET"].iter().any(|m| m.eq_ignore_ascii_case(value)),
+        "PINLOCK" => ["off", "menu", "wake", "both"].contains(&value),
+        "OTAURL" | "WEBHOOKURL" | "MQTTURL" => value.len() <= MAX_URL_LEN,
+        "WEBHOOKBODY" => value.len() <= MAX_TEMPLATE_LEN,
+        _ => value.len() <= MAX_TEXT_LEN,
+    }
+}
+
+/// Size of the config saved as TOML (`KEY = "value"` lines, quotes and backslashes escaped)
+pub fn config_size(config: &[(String, String)]) -> usize {
+    config.iter().map(|(key, value)| {
+        key.len() + value.len() + value.chars().filter(|c| *c == '"' || *c == '\\').count() + 6
+    }).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_values() {
+        for (key, value) in [("PC2", "aa:bb:cc:dd:ee:ff"), ("TIMEZONE", "-12"), ("TIMEZONE", "+14"), ("IDLESLEEP", "0"),
+                             ("DISPLAYOFFTIME", "600"), ("SLEEPMODE", "deep"), ("WEBHOOKMETHOD", "put"),
+                             ("WIFITXPOWER", "0"), ("WIFITXPOWER", "20"), ("PROFILE", "2:Office"), ("HOSTNAME", "")] {
+            assert!(check_config_value(key, value), "{} = {}", key, value);
+        }
+    }
+
+    #[test]
+    fn invalid_values() {
+        for (key, value) in [("PC1", ""), ("PC1", "zz"), ("PC1", "00:11:22:33:44:55:66"), ("TIMEZONE", "15"),
+                             ("TIMEZONE", "JST"), ("IDLESLEEP", "-1"), ("IDLESLEEP", "soon"), ("DISPLAYOFFTIME", ""),
+                             ("SLEEPMODE", "hibernate"), ("WIFIPS", "MAX"), ("WIFITXPOWER", "1"), ("WIFILISTEN", "0"),
+                             ("PINLOCK", "on"), ("HTTPAPI", "yes"), ("HOSTNAME", "a\nb")] {
+            assert!(!check_config_value(key, value), "{} = {}", key, value);
+        }
+    }
+
+    #[test]
+    fn text_length_limits() {
+        assert!(check_config_value("HOSTNAME", &"a".repeat(MAX_TEXT_LEN)));
+        assert!(!check_config_value("HOSTNAME", &"a".repeat(MAX_TEXT_LEN + 1)));
+        assert!(check_config_value("WEBHOOKURL", &"a".repeat(MAX_URL_LEN)));
+        assert!(!check_config_value("WEBHOOKURL", &"a".repeat(MAX_URL_LEN + 1)));
+        assert!(check_config_value("WEBHOOKBODY", &"a".repeat(MAX_TEMPLATE_LEN)));
+        assert!(!check_config_value("WEBHOOKBODY", &"a".repeat(MAX_TEMPLATE_LEN + 1)));
+    }
+}
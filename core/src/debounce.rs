@@ -0,0 +1,111 @@
+// Key debouncing from (timestamp, level) samples
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+/// Debounce state of one key. It only knows "pressed or not" at a time, so it
+/// works for any pin (or a synthetic sequence). A new level is accepted once it
+/// has been seen continuously for the debounce window; bounces shorter than the
+/// window are ignored, and since the level is sampled rather than toggled on
+/// edges, a missed edge cannot invert the key.
+#[derive(Debug, Clone, Copy)]
+pub struct Debouncer {
+    window: u64,                    // ms
+    stable: bool,                   // confirmed level, true = pressed
+    candidate: Option<(bool, u64)>, // level seen since the time
+}
+
+impl Debouncer {
+    pub fn new(window: u64, pressed: bool) -> Debouncer {
+        Debouncer { window, stable: pressed, candidate: None }
+    }
+
+    /// Feed a sample taken at `now` (ms). Returns the new level when a change is confirmed.
+    pub fn sample(&mut self, now: u64, pressed: bool) -> Option<bool> {
+        if pressed == self.stable {
+            self.candidate = None;
+            return None;
+        }
+        match self.candidate {
+            Some((level, since)) if level == pressed => {
+                if now.saturating_sub(since) >= self.window {
+                    self.stable = pressed;
+                    self.candidate = None;
+                    return Some(pressed);
+                }
+            },
+            _ => {
+                self.candidate = Some((pressed, now));
+            },
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 20;
+
+    #[test]
+    fn stable_level_reports_nothing() {
+        let mut d = Debouncer::new(WINDOW, false);
+        for now in (0..100).step_by(5) {
+            assert_eq!(d.sample(now, false), None);
+        }
+    }
+
+    #[test]
+    fn short_glitch_ignored() {
+        let mut d = Debouncer::new(WINDOW, false);
+        assert_eq!(d.sample(0, false), None);
+        assert_eq!(d.sample(5, true), None);
+        assert_eq!(d.sample(5 + WINDOW - 1, true), None);
+        assert_eq!(d.sample(5 + WINDOW - 1, false), None);
+        // the glitch does not count toward a later press
+        assert_eq!(d.sample(30, true), None);
+        assert_eq!(d.sample(30 + WINDOW - 1, true), None);
+        assert_eq!(d.sample(30 + WINDOW, true), Some(true));
+    }
+
+    #[test]
+    fn confirmed_at_window_edge() {
+        let mut d = Debouncer::new(WINDOW, false);
+        assert_eq!(d.sample(100, true), None);
+        assert_eq!(d.sample(100 + WINDOW - 1, true), None);
+        assert_eq!(d.sample(100 + WINDOW, true), Some(true));
+        // reported once
+        assert_eq!(d.sample(100 + WINDOW + 5, true), None);
+    }
+
+    #[test]
+    fn chatter_on_release() {
+        let mut d = Debouncer::new(WINDOW, true);
+        // contact bounces for a while after letting go
+        let bounces = [(0, false), (3, true), (6, false), (8, true), (12, false), (15, true)];
+        for (now, level) in bounces {
+            assert_eq!(d.sample(now, level), None);
+        }
+        assert_eq!(d.sample(18, false), None);
+        assert_eq!(d.sample(18 + WINDOW - 1, false), None);
+        assert_eq!(d.sample(18 + WINDOW, false), Some(false));
+    }
+
+    #[test]
+    fn press_and_release() {
+        let mut d = Debouncer::new(WINDOW, false);
+        assert_eq!(d.sample(0, true), None);
+        assert_eq!(d.sample(WINDOW, true), Some(true));
+        assert_eq!(d.sample(200, false), None);
+        assert_eq!(d.sample(200 + WINDOW, false), Some(false));
+    }
+
+    #[test]
+    fn timer_wrap_does_not_confirm() {
+        // a sample older than the candidate saturates to 0 elapsed
+        let mut d = Debouncer::new(WINDOW, false);
+        assert_eq!(d.sample(1000, true), None);
+        assert_eq!(d.sample(10, true), None);
+        assert_eq!(d.sample(1000 + WINDOW, true), Some(true));
+    }
+}
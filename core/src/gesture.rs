@@ -0,0 +1,222 @@
+// Gesture detection on top of key down/up events
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use crate::keys::{Key, ALL_KEYS};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    Click(Key),
+    LongPress(Key),     // fired while the key is still held
+    DoublePress(Key),
+    Chord(Key, Key),    // two keys pressed together, in `ALL_KEYS` order
+}
+
+/// Thresholds in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureConfig {
+    pub long_press: u64,
+    pub double_press: u64,  // max gap between two clicks, 0: no double press (clicks are not delayed)
+    pub chord: u64,         // max gap between the two key downs of a chord
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig { long_press: 800, double_press: 300, chord: 150 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct KeyTrack {
+    pressed_at: Option<u64>,
+    long_fired: bool,
+    in_chord: bool,
+    click_at: Option<u64>,  // release time of a click waiting for a second one
+}
+
+/// Turns timestamped key downs/ups into gestures. Time is passed in, so it can be
+/// driven with synthetic sequences; `poll()` must be called regularly to fire
+/// long presses and single clicks after the double press window.
+pub struct GestureDetector {
+    config: GestureConfig,
+    keys: [KeyTrack; ALL_KEYS.len()],
+}
+
+impl GestureDetector {
+    pub fn new(config: GestureConfig) -> GestureDetector {
+        GestureDetector { config, keys: [KeyTrack::default(); ALL_KEYS.len()] }
+    }
+
+    pub fn set_config(&mut self, config: GestureConfig) {
+        self.config = config;
+    }
+
+    /// Forget keys in progress, e.g. after sleep.
+    pub fn reset(&mut self) {
+        self.keys = [KeyTrack::default(); ALL_KEYS.len()];
+    }
+
+    pub fn key_down(&mut self, key: Key, now: u64) -> Vec<Gesture> {
+        let mut gestures = self.poll(now);
+        let index = key.index();
+        if self.keys[index].pressed_at.is_some() {
+            return gestures;
+        }
+        // a second key going down shortly after another one makes a chord
+        let partner = ALL_KEYS.iter().position(|k| {
+            let t = &self.keys[k.index()];
+            *k != key && !t.in_chord && !t.long_fired
+                && t.pressed_at.map(|at| now.saturating_sub(at) <= self.config.chord).unwrap_or(false)
+        });
+        let track = &mut self.keys[index];
+        track.pressed_at = Some(now);
+        track.long_fired = false;
+        track.in_chord = false;
+        if let Some(partner) = partner {
+            self.keys[index].in_chord = true;
+            self.keys[index].click_at = None;
+            self.keys[partner].in_chord = true;
+            self.keys[partner].click_at = None;
+            let (a, b) = if partner < index { (partner, index) } else { (index, partner) };
+            gestures.push(Gesture::Chord(ALL_KEYS[a], ALL_KEYS[b]));
+        }
+        gestures
+    }
+
+    pub fn key_up(&mut self, key: Key, now: u64) -> Vec<Gesture> {
+        let mut gestures = self.poll(now);
+        let double_press = self.config.double_press;
+        let track = &mut self.keys[key.index()];
+        if track.pressed_at.take().is_none() {
+            return gestures;
+        }
+        if track.in_chord || track.long_fired {
+            track.in_chord = false;
+            track.long_fired = false;
+            return gestures;
+        }
+        if double_press == 0 {
+            gestures.push(Gesture::Click(key));
+        }
+        else if track.click_at.take().is_some() {
+            gestures.push(Gesture::DoublePress(key));
+        }
+        else {
+            track.click_at = Some(now);
+        }
+        gestures
+    }
+
+    /// Fire long presses of held keys and clicks whose double press window has passed.
+    pub fn poll(&mut self, now: u64) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        for key in ALL_KEYS {
+            let track = &mut self.keys[key.index()];
+            if let Some(at) = track.pressed_at {
+                if !track.in_chord && !track.long_fired && now.saturating_sub(at) >= self.config.long_press {
+                    track.long_fired = true;
+                    // a long press after a click is not a double press
+                    if track.click_at.take().is_some() {
+                        gestures.push(Gesture::Click(key));
+                    }
+                    gestures.push(Gesture::LongPress(key));
+                }
+            }
+            else if let Some(at) = track.click_at {
+                if now.saturating_sub(at) > self.config.double_press {
+                    track.click_at = None;
+                    gestures.push(Gesture::Click(key));
+                }
+            }
+        }
+        gestures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> GestureDetector {
+        GestureDetector::new(GestureConfig::default())
+    }
+
+    // key downs/ups at the given times, then polls up to `until`
+    fn run(detector: &mut GestureDetector, events: &[(Key, bool, u64)], until: u64) -> Vec<(Gesture, u64)> {
+        let mut gestures = Vec::new();
+        let mut now = 0;
+        for (key, down, at) in events {
+            while now < *at {
+                gestures.extend(detector.poll(now).into_iter().map(|g| (g, now)));
+                now += 10;
+            }
+            let fired = if *down { detector.key_down(*key, *at) } else { detector.key_up(*key, *at) };
+            gestures.extend(fired.into_iter().map(|g| (g, *at)));
+        }
+        while now <= until {
+            gestures.extend(detector.poll(now).into_iter().map(|g| (g, now)));
+            now += 10;
+        }
+        gestures
+    }
+
+    #[test]
+    fn click_fires_after_double_press_window() {
+        let gestures = run(&mut detector(), &[(Key::Up, true, 0), (Key::Up, false, 100)], 1000);
+        assert_eq!(gestures, [(Gesture::Click(Key::Up), 410)]);
+    }
+
+    #[test]
+    fn double_press() {
+        let events = [(Key::Up, true, 0), (Key::Up, false, 100), (Key::Up, true, 300), (Key::Up, false, 400)];
+        assert_eq!(run(&mut detector(), &events, 1000), [(Gesture::DoublePress(Key::Up), 400)]);
+    }
+
+    #[test]
+    fn slow_second_click_is_two_clicks() {
+        let events = [(Key::Up, true, 0), (Key::Up, false, 100), (Key::Up, true, 500), (Key::Up, false, 600)];
+        assert_eq!(run(&mut detector(), &events, 1000), [(Gesture::Click(Key::Up), 410), (Gesture::Click(Key::Up), 910)]);
+    }
+
+    #[test]
+    fn long_press_fires_while_held() {
+        let gestures = run(&mut detector(), &[(Key::Down, true, 0), (Key::Down, false, 2000)], 3000);
+        assert_eq!(gestures, [(Gesture::LongPress(Key::Down), 800)]);
+    }
+
+    #[test]
+    fn long_press_after_click() {
+        // the pending click is reported first, not merged into a double press
+        let events = [(Key::Left, true, 0), (Key::Left, false, 100), (Key::Left, true, 200), (Key::Left, false, 1500)];
+        assert_eq!(run(&mut detector(), &events, 2000), [(Gesture::Click(Key::Left), 1000), (Gesture::LongPress(Key::Left), 1000)]);
+    }
+
+    #[test]
+    fn chord_within_window() {
+        let events = [(Key::Right, true, 0), (Key::Up, true, 100), (Key::Right, false, 1200), (Key::Up, false, 1300)];
+        // no click or long press for the keys of a chord
+        assert_eq!(run(&mut detector(), &events, 2000), [(Gesture::Chord(Key::Up, Key::Right), 100)]);
+    }
+
+    #[test]
+    fn keys_too_far_apart_are_no_chord() {
+        let events = [(Key::Up, true, 0), (Key::Down, true, 200), (Key::Up, false, 300), (Key::Down, false, 350)];
+        assert_eq!(run(&mut detector(), &events, 1000), [(Gesture::Click(Key::Up), 610), (Gesture::Click(Key::Down), 660)]);
+    }
+
+    #[test]
+    fn no_double_press_clicks_at_once() {
+        let mut detector = GestureDetector::new(GestureConfig { double_press: 0, ..GestureConfig::default() });
+        let events = [(Key::Center, true, 0), (Key::Center, false, 100), (Key::Center, true, 200), (Key::Center, false, 300)];
+        assert_eq!(run(&mut detector, &events, 1000), [(Gesture::Click(Key::Center), 100), (Gesture::Click(Key::Center), 300)]);
+    }
+
+    #[test]
+    fn reset_forgets_keys_in_progress() {
+        let mut detector = detector();
+        detector.key_down(Key::Up, 0);
+        detector.reset();
+        assert!(detector.key_up(Key::Up, 100).is_empty());
+        assert!(detector.poll(2000).is_empty());
+    }
+}
@@ -0,0 +1,57 @@
+// Keys of the box and their down/up events
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Center,
+}
+
+pub const ALL_KEYS: [Key; 5] = [Key::Up, Key::Down, Key::Left, Key::Right, Key::Center];
+
+impl Key {
+    pub fn index(&self) -> usize {
+        match self {
+            Key::Up => 0,
+            Key::Down => 1,
+            Key::Left => 2,
+            Key::Right => 3,
+            Key::Center => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyEvent {
+    UpKeyDown,
+    UpKeyUp,
+    DownKeyDown,
+    DownKeyUp,
+    LeftKeyDown,
+    LeftKeyUp,
+    RightKeyDown,
+    RightKeyUp,
+    CenterKeyDown,
+    CenterKeyUp,
+}
+
+impl KeyEvent {
+    pub fn new(key: Key, down: bool) -> KeyEvent {
+        match (key, down) {
+            (Key::Up, true) => KeyEvent::UpKeyDown,
+            (Key::Up, false) => KeyEvent::UpKeyUp,
+            (Key::Down, true) => KeyEvent::DownKeyDown,
+            (Key::Down, false) => KeyEvent::DownKeyUp,
+            (Key::Left, true) => KeyEvent::LeftKeyDown,
+            (Key::Left, false) => KeyEvent::LeftKeyUp,
+            (Key::Right, true) => KeyEvent::RightKeyDown,
+            (Key::Right, false) => KeyEvent::RightKeyUp,
+            (Key::Center, true) => KeyEvent::CenterKeyDown,
+            (Key::Center, false) => KeyEvent::CenterKeyUp,
+        }
+    }
+}
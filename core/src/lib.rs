@@ -0,0 +1,21 @@
+// WakePCBox logic without ESP-IDF dependencies, shared with the firmware
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+pub mod autorepeat;
+pub mod buttons;
+pub mod configcheck;
+pub mod control;
+pub mod debounce;
+pub mod gesture;
+pub mod keys;
+pub mod metrics;
+pub mod pinlock;
+pub mod provision;
+pub mod securewake;
+pub mod shell;
+pub mod syslog;
+pub mod targets;
+pub mod timesvc;
+pub mod wifimgr;
+pub mod webhook;
@@ -0,0 +1,155 @@
+// Metrics formats: Prometheus text and InfluxDB line protocol
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use crate::control::DeviceStatus;
+use crate::targets::ALL_TARGETS;
+
+/// One sample of everything exported.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    pub battery_voltage: f32,
+    pub rssi: i32,
+    pub uptime_secs: u64,
+    pub free_heap: u32,
+    pub reconnect_count: u32,
+    pub send_count: u32,
+    pub targets: Vec<(&'static str, u32, u32)>,     // name, wakes, failures
+}
+
+impl Metrics {
+    pub fn from_status(status: &DeviceStatus, uptime_secs: u64, free_heap: u32) -> Metrics {
+        Metrics {
+            battery_voltage: status.battery_voltage,
+            rssi: status.rssi,
+            uptime_secs,
+            free_heap,
+            reconnect_count: status.reconnect_count,
+            send_count: status.send_count,
+            targets: ALL_TARGETS.iter().enumerate()
+                .map(|(i, t)| (t.name(), status.targets[i].wake_count, status.targets[i].fail_count))
+                .collect(),
+        }
+    }
+}
+
+/// Prometheus text exposition format (version 0.0.4).
+pub fn prometheus_text(metrics: &Metrics) -> String {
+    let mut out = String::new();
+    let mut gauge = |name: &str, kind: &str, help: &str, value: String| {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+    };
+    gauge("wakepcbox_battery_voltage_volts", "gauge", "Battery voltage.", format!("{:.3}", metrics.battery_voltage));
+    gauge("wakepcbox_wifi_rssi_dbm", "gauge", "Wi-Fi signal strength.", metrics.rssi.to_string());
+    gauge("wakepcbox_uptime_seconds", "counter", "Time since boot.", metrics.uptime_secs.to_string());
+    gauge("wakepcbox_free_heap_bytes", "gauge", "Free heap memory.", metrics.free_heap.to_string());
+    gauge("wakepcbox_wifi_reconnects_total", "counter", "Wi-Fi reconnects since boot.", metrics.reconnect_count.to_string());
+    gauge("wakepcbox_packets_sent_total", "counter", "Magic packets sent since boot.", metrics.send_count.to_string());
+    out.push_str("# HELP wakepcbox_wakes_total Successful wakes per target since boot.\n# TYPE wakepcbox_wakes_total counter\n");
+    for (name, wakes, _) in &metrics.targets {
+        out.push_str(&format!("wakepcbox_wakes_total{{target=\"{}\"}} {}\n", name, wakes));
+    }
+    out.push_str("# HELP wakepcbox_wake_failures_total Failed wakes per target since boot.\n# TYPE wakepcbox_wake_failures_total counter\n");
+    for (name, _, failures) in &metrics.targets {
+        out.push_str(&format!("wakepcbox_wake_failures_total{{target=\"{}\"}} {}\n", name, failures));
+    }
+    out
+}
+
+/// InfluxDB line protocol, without timestamps (the server assigns them).
+pub fn influx_lines(metrics: &Metrics, host: &str) -> String {
+    let host = influx_tag(host);
+    let mut out = format!(
+        "wakepcbox,host={} battery_voltage={:.3},rssi={}i,uptime={}i,free_heap={}i,reconnects={}i,packets_sent={}i\n",
+        host, metrics.battery_voltage, metrics.rssi, metrics.uptime_secs, metrics.free_heap,
+        metrics.reconnect_count, metrics.send_count);
+    for (name, wakes, failures) in &metrics.targets {
+        out.push_str(&format!("wakepcbox_target,host={},target={} wakes={}i,failures={}i\n", host, name, wakes, failures));
+    }
+    out
+}
+
+// Tag values escape comma, equals sign and space
+fn influx_tag(value: &str) -> String {
+    value.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::{Control, TriggerSource};
+    use crate::targets::TargetDevice;
+
+    fn sample() -> Metrics {
+        let control = Control::new();
+        control.set_battery_voltage(3.95);
+        control.set_rssi(-61);
+        control.set_send_count(5);
+        control.set_reconnect_count(2);
+        control.record_wake(TargetDevice::Device1, TriggerSource::Button, true);
+        control.record_wake(TargetDevice::Device1, TriggerSource::Mqtt, true);
+        control.record_wake(TargetDevice::Device3, TriggerSource::Shell, false);
+        Metrics::from_status(&control.get_status(), 3600, 120000)
+    }
+
+    #[test]
+    fn from_status() {
+        let metrics = sample();
+        assert_eq!(metrics.uptime_secs, 3600);
+        assert_eq!(metrics.targets, [("PC1", 2, 0), ("PC2", 0, 0), ("PC3", 0, 1), ("PC4", 0, 0)]);
+    }
+
+    #[test]
+    fn prometheus_format() {
+        assert_eq!(prometheus_text(&sample()), "\
+# HELP wakepcbox_battery_voltage_volts Battery voltage.
+# TYPE wakepcbox_battery_voltage_volts gauge
+wakepcbox_battery_voltage_volts 3.950
+# HELP wakepcbox_wifi_rssi_dbm Wi-Fi signal strength.
+# TYPE wakepcbox_wifi_rssi_dbm gauge
+wakepcbox_wifi_rssi_dbm -61
+# HELP wakepcbox_uptime_seconds Time since boot.
+# TYPE wakepcbox_uptime_seconds counter
+wakepcbox_uptime_seconds 3600
+# HELP wakepcbox_free_heap_bytes Free heap memory.
+# TYPE wakepcbox_free_heap_bytes gauge
+wakepcbox_free_heap_bytes 120000
+# HELP wakepcbox_wifi_reconnects_total Wi-Fi reconnects since boot.
+# TYPE wakepcbox_wifi_reconnects_total counter
+wakepcbox_wifi_reconnects_total 2
+# HELP wakepcbox_packets_sent_total Magic packets sent since boot.
+# TYPE wakepcbox_packets_sent_total counter
+wakepcbox_packets_sent_total 5
+# HELP wakepcbox_wakes_total Successful wakes per target since boot.
+# TYPE wakepcbox_wakes_total counter
+wakepcbox_wakes_total{target=\"PC1\"} 2
+wakepcbox_wakes_total{target=\"PC2\"} 0
+wakepcbox_wakes_total{target=\"PC3\"} 0
+wakepcbox_wakes_total{target=\"PC4\"} 0
+# HELP wakepcbox_wake_failures_total Failed wakes per target since boot.
+# TYPE wakepcbox_wake_failures_total counter
+wakepcbox_wake_failures_total{target=\"PC1\"} 0
+wakepcbox_wake_failures_total{target=\"PC2\"} 0
+wakepcbox_wake_failures_total{target=\"PC3\"} 1
+wakepcbox_wake_failures_total{target=\"PC4\"} 0
+");
+    }
+
+    #[test]
+    fn influx_format() {
+        assert_eq!(influx_lines(&sample(), "wakepcbox-a1b2c3"), "\
+wakepcbox,host=wakepcbox-a1b2c3 battery_voltage=3.950,rssi=-61i,uptime=3600i,free_heap=120000i,reconnects=2i,packets_sent=5i
+wakepcbox_target,host=wakepcbox-a1b2c3,target=PC1 wakes=2i,failures=0i
+wakepcbox_target,host=wakepcbox-a1b2c3,target=PC2 wakes=0i,failures=0i
+wakepcbox_target,host=wakepcbox-a1b2c3,target=PC3 wakes=0i,failures=1i
+wakepcbox_target,host=wakepcbox-a1b2c3,target=PC4 wakes=0i,failures=0i
+");
+    }
+
+    #[test]
+    fn influx_tag_escaping() {
+        assert_eq!(influx_tag("my box,a=b"), "my\\ box\\,a\\=b");
+        let lines = influx_lines(&sample(), "living room");
+        assert!(lines.starts_with("wakepcbox,host=living\\ room battery_voltage="));
+    }
+}
@@ -0,0 +1,375 @@
+// PIN lock for the settings menu and for wakes of selected targets
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+//
+// The PIN guards the keys of the box only. Wakes through the HTTP API, MQTT, the
+// shell and signed UDP are outside its scope; they have their own credentials.
+
+use log::*;
+use sha2::{Digest, Sha256};
+
+use crate::keys::KeyEvent;
+use crate::securewake::{constant_time_eq, hex};
+use crate::targets::{self, TargetDevice, ALL_TARGETS};
+
+pub const PIN_MIN_LEN: usize = 4;
+pub const PIN_MAX_LEN: usize = 8;
+const MAX_ATTEMPTS: u32 = 3;        // wrong PINs before a lockout
+const LOCKOUT_TIME: u64 = 30;       // seconds, doubled with each further lockout
+const MAX_LOCKOUT_TIME: u64 = 3600;
+const HASH_ROUNDS: u32 = 1000;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PinMode {
+    Off,
+    Menu,
+    Wake,
+    Both,
+}
+
+impl PinMode {
+    /// `off`, `menu`, `wake` or `both`.
+    pub fn parse(value: &str) -> PinMode {
+        match value.trim().to_lowercase().as_str() {
+            "menu" => PinMode::Menu,
+            "wake" => PinMode::Wake,
+            "both" => PinMode::Both,
+            _ => PinMode::Off,
+        }
+    }
+
+    pub fn for_menu(&self) -> bool {
+        *self == PinMode::Menu || *self == PinMode::Both
+    }
+
+    pub fn for_wake(&self) -> bool {
+        *self == PinMode::Wake || *self == PinMode::Both
+    }
+}
+
+fn hash_with_salt(pin: &str, salt: &[u8]) -> [u8; 32] {
+    let mut hash : [u8; 32] = Sha256::new().chain_update(salt).chain_update(pin.as_bytes()).finalize().into();
+    for _ in 1..HASH_ROUNDS {
+        hash = Sha256::new().chain_update(salt).chain_update(hash).finalize().into();
+    }
+    hash
+}
+
+fn unhex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len()).step_by(2).map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Salted hash stored in NVS: `<salt hex>:<hash hex>`.
+pub fn hash_pin_with_salt(pin: &str, salt: &[u8]) -> String {
+    format!("{}:{}", hex(salt), hex(&hash_with_salt(pin, salt)))
+}
+
+pub fn verify_pin(pin: &str, stored: &str) -> bool {
+    let (salt, hash) = match stored.split_once(':') {
+        Some((salt, hash)) => (unhex(salt), unhex(hash)),
+        None => return false,
+    };
+    match (salt, hash) {
+        (Some(salt), Some(hash)) => constant_time_eq(&hash_with_salt(pin, &salt), &hash),
+        _ => false,
+    }
+}
+
+/// Whether menu edits change the PIN, `PINLOCK` or `PINTARGETS` compared to `current`.
+pub fn changes_pin_settings(current: &[(String, String)], menu_values: &[(String, String)]) -> bool {
+    menu_values.iter().any(|(key, value)| match key.as_str() {
+        "PIN" => !value.trim().is_empty(),
+        "PINLOCK" | "PINTARGETS" => current.iter().find(|it| it.0 == *key).map(|it| it.1.trim() != value.trim()).unwrap_or(true),
+        _ => false,
+    })
+}
+
+/// Replace wake group names in `PINTARGETS` by their targets (from `GROUPS` in the
+/// same config), so redefining a group later cannot take a target out of the lock.
+pub fn expand_pin_targets(config: &mut [(String, String)]) {
+    let groups = config.iter().find(|it| it.0 == "GROUPS").map(|it| it.1.clone()).unwrap_or_default();
+    if let Some(it) = config.iter_mut().find(|it| it.0 == "PINTARGETS") {
+        if it.1.trim().is_empty() {
+            return;
+        }
+        let mut listed = Vec::new();
+        for name in it.1.split('+') {
+            match TargetDevice::from_name(name) {
+                TargetDevice::None => match targets::find_group(&groups, name) {
+                    Some(group) => listed.extend(group),
+                    None => { info!("[PIN] Unknown target {}", name.trim()); },
+                },
+                target => listed.push(target),
+            }
+        }
+        let names : Vec<&str> = ALL_TARGETS.iter().filter(|t| listed.contains(t)).map(|t| t.name()).collect();
+        it.1 = names.join("+");
+    }
+}
+
+/// Targets that need the PIN to wake, from `PINTARGETS`: empty means all targets.
+/// A name that is not a target (e.g. a group saved by older firmware) also
+/// locks all targets, since groups can be changed over the network.
+pub fn pin_protected_targets(pin_targets: &str) -> Vec<TargetDevice> {
+    if pin_targets.trim().is_empty() {
+        return ALL_TARGETS.to_vec();
+    }
+    let mut protected = Vec::new();
+    for name in pin_targets.split('+') {
+        match TargetDevice::from_name(name) {
+            TargetDevice::None => {
+                info!("[PIN] {} is not a target, locking all targets", name.trim());
+                return ALL_TARGETS.to_vec();
+            },
+            target => protected.push(target),
+        }
+    }
+    protected
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PinResult {
+    Accepted,
+    Wrong(u32),     // attempts left before a lockout
+    Locked(u64),    // seconds until the next attempt
+}
+
+/// Failed attempts and lockout. Time (uptime seconds) is passed in.
+pub struct PinLock {
+    failures: u32,
+    locked_until: Option<u64>,
+}
+
+impl PinLock {
+    /// `failures` carried over from before a restart; a pending lockout starts over.
+    pub fn new(failures: u32, now: u64) -> PinLock {
+        let mut lock = PinLock { failures, locked_until: None };
+        if failures > 0 && failures.is_multiple_of(MAX_ATTEMPTS) {
+            lock.locked_until = Some(now + lock.lockout_time());
+        }
+        lock
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Seconds left of a lockout, if any.
+    pub fn locked_for(&self, now: u64) -> Option<u64> {
+        self.locked_until.filter(|until| *until > now).map(|until| until - now)
+    }
+
+    pub fn check(&mut self, pin: &str, stored: &str, now: u64) -> PinResult {
+        if let Some(secs) = self.locked_for(now) {
+            return PinResult::Locked(secs);
+        }
+        if verify_pin(pin, stored) {
+            self.failures = 0;
+            self.locked_until = None;
+            return PinResult::Accepted;
+        }
+        self.failures += 1;
+        info!("[PIN] Wrong PIN ({} failures)", self.failures);
+        if self.failures.is_multiple_of(MAX_ATTEMPTS) {
+            let secs = self.lockout_time();
+            self.locked_until = Some(now + secs);
+            return PinResult::Locked(secs);
+        }
+        PinResult::Wrong(MAX_ATTEMPTS - self.failures % MAX_ATTEMPTS)
+    }
+
+    fn lockout_time(&self) -> u64 {
+        let lockouts = (self.failures / MAX_ATTEMPTS).clamp(1, 8);
+        (LOCKOUT_TIME << (lockouts - 1)).min(MAX_LOCKOUT_TIME)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryState {
+    Editing,
+    Done(String),
+    Cancelled,
+}
+
+/// PIN typed with the keys: Up/Down change the digit, Right adds a digit,
+/// Left removes one (or cancels when empty), Center submits.
+pub struct PinEntry {
+    digits: Vec<u8>,
+}
+
+impl Default for PinEntry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PinEntry {
+    pub fn new() -> PinEntry {
+        PinEntry { digits: vec![0] }
+    }
+
+    pub fn input(&mut self, event: KeyEvent) -> EntryState {
+        let last = self.digits.len() - 1;
+        match event {
+            KeyEvent::UpKeyUp => { self.digits[last] = (self.digits[last] + 1) % 10; },
+            KeyEvent::DownKeyUp => { self.digits[last] = (self.digits[last] + 9) % 10; },
+            KeyEvent::RightKeyUp if self.digits.len() < PIN_MAX_LEN => { self.digits.push(0); },
+            KeyEvent::LeftKeyUp if self.digits.len() == 1 => { return EntryState::Cancelled; },
+            KeyEvent::LeftKeyUp => { self.digits.pop(); },
+            KeyEvent::CenterKeyUp => {
+                return EntryState::Done(self.digits.iter().map(|d| char::from(b'0' + d)).collect());
+            },
+            _ => {},
+        }
+        EntryState::Editing
+    }
+
+    /// Entered digits masked, the one being edited shown, e.g. `**7`.
+    pub fn display_text(&self) -> String {
+        let last = self.digits.len() - 1;
+        let mut text = "*".repeat(last);
+        text.push(char::from(b'0' + self.digits[last]));
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    fn entries(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn enter(entry: &mut PinEntry, events: &[KeyEvent]) -> EntryState {
+        let mut state = EntryState::Editing;
+        for event in events {
+            state = entry.input(*event);
+        }
+        state
+    }
+
+    #[test]
+    fn pin_hash_verifies() {
+        let stored = hash_pin_with_salt("1234", &SALT);
+        assert!(stored.starts_with("0102030405060708:"));
+        assert!(verify_pin("1234", &stored));
+        assert!(!verify_pin("1235", &stored));
+        assert!(!verify_pin("1234", &hash_pin_with_salt("1234", &[0; 8]).replace("0000000000000000", "0102030405060708")));
+    }
+
+    #[test]
+    fn malformed_hash_never_matches() {
+        for stored in ["", "1234", "0102:", ":abcd", "0g:00", "010:00"] {
+            assert!(!verify_pin("1234", stored), "{}", stored);
+        }
+    }
+
+    #[test]
+    fn lockout_after_three_failures() {
+        let stored = hash_pin_with_salt("1234", &SALT);
+        let mut lock = PinLock::new(0, 0);
+        assert_eq!(lock.check("0000", &stored, 0), PinResult::Wrong(2));
+        assert_eq!(lock.check("0000", &stored, 1), PinResult::Wrong(1));
+        assert_eq!(lock.check("0000", &stored, 2), PinResult::Locked(30));
+        // even the right PIN is refused while locked
+        assert_eq!(lock.check("1234", &stored, 12), PinResult::Locked(20));
+        assert_eq!(lock.locked_for(31), Some(1));
+        assert_eq!(lock.locked_for(32), None);
+        assert_eq!(lock.check("1234", &stored, 32), PinResult::Accepted);
+        assert_eq!(lock.failures(), 0);
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_an_hour() {
+        let stored = hash_pin_with_salt("1234", &SALT);
+        let mut lock = PinLock::new(0, 0);
+        let mut now = 0;
+        let mut lockouts = Vec::new();
+        for _ in 0..9 {
+            let mut result = PinResult::Accepted;
+            for _ in 0..3 {
+                result = lock.check("0000", &stored, now);
+            }
+            if let PinResult::Locked(secs) = result {
+                lockouts.push(secs);
+                now += secs;
+            }
+        }
+        assert_eq!(lockouts, [30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+    }
+
+    #[test]
+    fn failures_carried_over() {
+        let stored = hash_pin_with_salt("1234", &SALT);
+        // restarted during a lockout: it starts over
+        let lock = PinLock::new(3, 100);
+        assert_eq!(lock.locked_for(100), Some(30));
+        assert_eq!(PinLock::new(6, 0).locked_for(0), Some(60));
+        // restarted after two failures: one more locks
+        let mut lock = PinLock::new(2, 0);
+        assert_eq!(lock.locked_for(0), None);
+        assert_eq!(lock.check("0000", &stored, 0), PinResult::Locked(30));
+        let mut lock = PinLock::new(4, 0);
+        assert_eq!(lock.check("0000", &stored, 0), PinResult::Wrong(1));
+        assert_eq!(lock.failures(), 5);
+    }
+
+    #[test]
+    fn digit_entry() {
+        let mut entry = PinEntry::new();
+        assert_eq!(entry.display_text(), "0");
+        assert_eq!(enter(&mut entry, &[KeyEvent::UpKeyUp, KeyEvent::UpKeyUp]), EntryState::Editing);
+        assert_eq!(entry.display_text(), "2");
+        enter(&mut entry, &[KeyEvent::RightKeyUp, KeyEvent::DownKeyUp]);
+        assert_eq!(entry.display_text(), "*9");
+        // key downs and other events are ignored
+        enter(&mut entry, &[KeyEvent::UpKeyDown, KeyEvent::CenterKeyDown, KeyEvent::UpKeyUp]);
+        assert_eq!(entry.display_text(), "*0");
+        enter(&mut entry, &[KeyEvent::RightKeyUp, KeyEvent::UpKeyUp, KeyEvent::LeftKeyUp, KeyEvent::RightKeyUp]);
+        assert_eq!(enter(&mut entry, &[KeyEvent::UpKeyUp, KeyEvent::CenterKeyUp]), EntryState::Done("201".to_string()));
+    }
+
+    #[test]
+    fn digit_entry_limits() {
+        let mut entry = PinEntry::new();
+        for _ in 0..PIN_MAX_LEN + 3 {
+            entry.input(KeyEvent::RightKeyUp);
+        }
+        assert_eq!(entry.input(KeyEvent::CenterKeyUp), EntryState::Done("0".repeat(PIN_MAX_LEN)));
+        let mut entry = PinEntry::new();
+        assert_eq!(enter(&mut entry, &[KeyEvent::RightKeyUp, KeyEvent::LeftKeyUp]), EntryState::Editing);
+        assert_eq!(entry.input(KeyEvent::LeftKeyUp), EntryState::Cancelled);
+    }
+
+    #[test]
+    fn pin_settings_changes() {
+        let current = entries(&[("PINLOCK", "wake"), ("PINTARGETS", "PC1"), ("HOSTNAME", "box")]);
+        assert!(!changes_pin_settings(&current, &entries(&[("PIN", " "), ("PINLOCK", "wake"), ("PINTARGETS", "PC1"), ("HOSTNAME", "x")])));
+        assert!(changes_pin_settings(&current, &entries(&[("PIN", "1234")])));
+        assert!(changes_pin_settings(&current, &entries(&[("PINLOCK", "off")])));
+        assert!(changes_pin_settings(&current, &entries(&[("PINTARGETS", "")])));
+    }
+
+    #[test]
+    fn pin_targets_expanded() {
+        let mut config = entries(&[("GROUPS", "lab:PC3+PC4,office:PC1"), ("PINTARGETS", "lab+2+PC3+nosuch")]);
+        expand_pin_targets(&mut config);
+        assert_eq!(config[1].1, "PC2+PC3+PC4");
+        let mut config = entries(&[("GROUPS", "lab:PC3"), ("PINTARGETS", "")]);
+        expand_pin_targets(&mut config);
+        assert_eq!(config[1].1, "");
+    }
+
+    #[test]
+    fn protected_targets() {
+        assert_eq!(pin_protected_targets(""), ALL_TARGETS.to_vec());
+        assert_eq!(pin_protected_targets("PC1+3"), vec![TargetDevice::Device1, TargetDevice::Device3]);
+        // a group name from an older config locks everything
+        assert_eq!(pin_protected_targets("PC1+lab"), ALL_TARGETS.to_vec());
+    }
+}
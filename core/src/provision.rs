@@ -0,0 +1,300 @@
+// Captive portal form and DNS answers for first-time setup
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::net::Ipv4Addr;
+
+use crate::configcheck::check_config_value;
+
+// Form fields are named after the config keys, so the result can be merged
+// into `ConfigData::to_config_entries()` directly.
+pub const FORM_KEYS: [&str; 11] = ["SSID", "PSK", "WIFIAUTH", "EAPID", "EAPUSER", "EAPPASS", "PC1", "PC2", "PC3", "PC4", "TIMEZONE"];
+// Fields that keep their current (or default) value when left blank
+const KEEP_IF_BLANK: [(&str, &str); 5] = [
+    ("PC1", "00:00:00:00:00:00"),
+    ("PC2", "00:00:00:00:00:00"),
+    ("PC3", "00:00:00:00:00:00"),
+    ("PC4", "00:00:00:00:00:00"),
+    ("TIMEZONE", "0"),
+];
+
+/// `entries` with the submitted form values, without any checks (to show the form again).
+pub fn merge_form(entries: &[(String, String)], form: &[(String, String)]) -> Vec<(String, String)> {
+    let mut new_entries = entries.to_vec();
+    for it in new_entries.iter_mut() {
+        if !FORM_KEYS.contains(&it.0.as_str()) {
+            continue;
+        }
+        if let Some(value) = form.iter().find(|f| f.0 == it.0) {
+            it.1 = value.1.trim().to_string();
+        }
+    }
+    new_entries
+}
+
+/// Apply a submitted setup form to `entries`. Blank MAC and timezone fields keep
+/// their current value (or the default if that is not valid either). Returns the
+/// keys with invalid values if there are any.
+pub fn apply_form(entries: &[(String, String)], form: &[(String, String)]) -> Result<Vec<(String, String)>, Vec<String>> {
+    let mut new_entries = merge_form(entries, form);
+    let mut rejected = Vec::new();
+    for (it, old) in new_entries.iter_mut().zip(entries.iter()) {
+        if !FORM_KEYS.contains(&it.0.as_str()) {
+            continue;
+        }
+        if it.1.is_empty() {
+            if let Some((_, default)) = KEEP_IF_BLANK.iter().find(|k| k.0 == it.0) {
+                it.1 = if check_config_value(&old.0, old.1.trim()) { old.1.trim().to_string() } else { default.to_string() };
+            }
+        }
+        if !check_config_value(&it.0, &it.1) {
+            rejected.push(it.0.clone());
+        }
+    }
+    if rejected.is_empty() { Ok(new_entries) } else { Err(rejected) }
+}
+
+/// The setup form filled with `entries`, with `error` shown above it if not empty.
+pub fn setup_form(entries: &[(String, String)], error: &str) -> String {
+    let mut fields = String::new();
+    for key in FORM_KEYS {
+        let value = entries.iter().find(|it| it.0 == key).map(|it| it.1.as_str()).unwrap_or("");
+        let input_type = if key == "PSK" || key == "EAPPASS" { "password" } else { "text" };
+        fields.push_str(&format!(
+            "<label>{}<br><input type=\"{}\" name=\"{}\" value=\"{}\"></label><br>\n",
+            key, input_type, key, html_escape(value)));
+    }
+    let error = if error.is_empty() { String::new() } else { format!("<p style=\"color:red\">{}</p>\n", html_escape(error)) };
+    page("WakePCBox Setup",
+        &format!("{}<form method=\"post\" action=\"/save\">\n{}<input type=\"submit\" value=\"Save\"></form>", error, fields))
+}
+
+pub fn page(title: &str, body: &str) -> String {
+    format!("<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+        <title>{}</title></head><body><h2>{}</h2>{}</body></html>", title, title, body)
+}
+
+pub fn html_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Answer to a DNS query: every A question gets `ip`, other types an empty answer.
+/// Returns None for responses and malformed or truncated queries.
+pub fn dns_reply(query: &[u8], ip: Ipv4Addr) -> Option<Vec<u8>> {
+    // header(12) + at least one question
+    if query.len() < 17 || query[2] & 0x80 != 0 {
+        return None;
+    }
+    // skip QNAME labels; a compression pointer has no place in a question
+    let mut pos = 12;
+    while pos < query.len() && query[pos] != 0 {
+        if query[pos] & 0xc0 != 0 {
+            return None;
+        }
+        pos += query[pos] as usize + 1;
+    }
+    let question_end = pos + 5;
+    if question_end > query.len() {
+        return None;
+    }
+    let qtype = u16::from_be_bytes([query[pos + 1], query[pos + 2]]);
+    let answer = qtype == 1;
+
+    let mut reply = Vec::with_capacity(question_end + 16);
+    reply.extend_from_slice(&query[0..2]);              // ID
+    reply.extend_from_slice(&[0x81, 0x80]);             // response, recursion available
+    reply.extend_from_slice(&[0x00, 0x01]);             // QDCOUNT
+    reply.extend_from_slice(&[0x00, if answer { 1 } else { 0 }]); // ANCOUNT
+    reply.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]); // NSCOUNT, ARCOUNT
+    reply.extend_from_slice(&query[12..question_end]);
+    if answer {
+        reply.extend_from_slice(&[0xc0, 0x0c]);             // pointer to QNAME
+        reply.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]); // type A, class IN
+        reply.extend_from_slice(&60u32.to_be_bytes());      // TTL
+        reply.extend_from_slice(&[0x00, 0x04]);
+        reply.extend_from_slice(&ip.octets());
+    }
+    Some(reply)
+}
+
+// application/x-www-form-urlencoded
+pub fn parse_form(body: &str) -> Vec<(String, String)> {
+    body.split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let mut it = pair.splitn(2, '=');
+            let key = url_decode(it.next()?);
+            let value = url_decode(it.next().unwrap_or(""));
+            Some((key, value))
+        })
+        .collect()
+}
+
+pub fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                match hex {
+                    Some(b) => { out.push(b); i += 2; },
+                    None => out.push(b'%'),
+                }
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 1);
+
+    fn entries() -> Vec<(String, String)> {
+        [("SSID", ""), ("PSK", ""), ("WIFIAUTH", "psk"), ("PC1", ""), ("PC2", "00:11:22:33:44:55"),
+         ("PC3", ""), ("PC4", ""), ("TIMEZONE", "9"), ("HOSTNAME", "box")]
+            .iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn value<'a>(entries: &'a [(String, String)], key: &str) -> &'a str {
+        entries.iter().find(|it| it.0 == key).map(|it| it.1.as_str()).unwrap()
+    }
+
+    // query for `name` with the given QTYPE
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut q = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            q.push(label.len() as u8);
+            q.extend_from_slice(label.as_bytes());
+        }
+        q.push(0);
+        q.extend_from_slice(&qtype.to_be_bytes());
+        q.extend_from_slice(&[0x00, 0x01]);
+        q
+    }
+
+    #[test]
+    fn form_applied() {
+        let form = parse_form("SSID=Home+Net&PSK=p%40ss&PC1=aa%3Abb%3Acc%3Add%3Aee%3Aff&TIMEZONE=-5&HOSTNAME=evil");
+        let new_entries = apply_form(&entries(), &form).unwrap();
+        assert_eq!(value(&new_entries, "SSID"), "Home Net");
+        assert_eq!(value(&new_entries, "PSK"), "p@ss");
+        assert_eq!(value(&new_entries, "PC1"), "aa:bb:cc:dd:ee:ff");
+        assert_eq!(value(&new_entries, "TIMEZONE"), "-5");
+        // only the form fields are taken over
+        assert_eq!(value(&new_entries, "HOSTNAME"), "box");
+    }
+
+    #[test]
+    fn blank_fields_keep_value() {
+        let form = parse_form("SSID=Home&PC1=&PC2=+&PC3=&PC4=&TIMEZONE=");
+        let new_entries = apply_form(&entries(), &form).unwrap();
+        assert_eq!(value(&new_entries, "PC1"), "00:00:00:00:00:00");
+        assert_eq!(value(&new_entries, "PC2"), "00:11:22:33:44:55");
+        assert_eq!(value(&new_entries, "TIMEZONE"), "9");
+    }
+
+    #[test]
+    fn invalid_fields_rejected() {
+        let form = parse_form("SSID=Home&PC1=zz&PC2=00:11:22:33:44&TIMEZONE=JST&WIFIAUTH=wep");
+        assert_eq!(apply_form(&entries(), &form),
+            Err(vec!["WIFIAUTH".to_string(), "PC1".to_string(), "PC2".to_string(), "TIMEZONE".to_string()]));
+        // shown again as entered
+        let shown = merge_form(&entries(), &form);
+        assert_eq!(value(&shown, "PC1"), "zz");
+        let html = setup_form(&shown, "Invalid: PC1");
+        assert!(html.contains("<p style=\"color:red\">Invalid: PC1</p>"));
+        assert!(html.contains("name=\"PC1\" value=\"zz\""));
+    }
+
+    #[test]
+    fn form_values_escaped() {
+        let entries = vec![("SSID".to_string(), "\"><script>&".to_string())];
+        assert!(setup_form(&entries, "").contains("value=\"&quot;&gt;&lt;script&gt;&amp;\""));
+    }
+
+    #[test]
+    fn url_decoding() {
+        assert_eq!(url_decode("a+b%20c"), "a b c");
+        assert_eq!(url_decode("%2B%2b"), "++");
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_decode("1%4"), "1%4");
+        assert_eq!(url_decode("%zz%+4"), "%zz% 4");
+        assert_eq!(url_decode("%E3%81%82"), "\u{3042}");
+        assert_eq!(url_decode("%ff"), "\u{fffd}");
+    }
+
+    #[test]
+    fn form_parsing() {
+        assert_eq!(parse_form("a=1&b&c=x=y&&%41=%"), vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), String::new()),
+            ("c".to_string(), "x=y".to_string()),
+            ("A".to_string(), "%".to_string()),
+        ]);
+        assert!(parse_form("").is_empty());
+    }
+
+    #[test]
+    fn dns_a_query_answered() {
+        let q = query("connectivitycheck.gstatic.com", 1);
+        let reply = dns_reply(&q, IP).unwrap();
+        assert_eq!(&reply[0..2], &[0x12, 0x34]);
+        assert_eq!(&reply[2..12], &[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(&reply[12..q.len()], &q[12..]);
+        assert_eq!(&reply[q.len()..], &[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 168, 71, 1]);
+    }
+
+    #[test]
+    fn dns_other_query_empty_answer() {
+        let q = query("example.com", 28);
+        let reply = dns_reply(&q, IP).unwrap();
+        assert_eq!(&reply[6..8], &[0, 0]);
+        assert_eq!(reply.len(), q.len());
+    }
+
+    #[test]
+    fn dns_trailing_data_not_echoed() {
+        let mut q = query("example.com", 1);
+        let len = q.len();
+        q.extend_from_slice(&[0, 0, 0x29, 0x10, 0, 0, 0, 0, 0, 0, 0]);  // EDNS record
+        let reply = dns_reply(&q, IP).unwrap();
+        assert_eq!(&reply[12..len], &q[12..len]);
+        assert_eq!(reply.len(), len + 16);
+    }
+
+    #[test]
+    fn dns_malformed_queries() {
+        let q = query("example.com", 1);
+        // truncated anywhere
+        for len in 0..q.len() {
+            assert_eq!(dns_reply(&q[..len], IP), None, "length {}", len);
+        }
+        // a response
+        let mut response = q.clone();
+        response[2] |= 0x80;
+        assert_eq!(dns_reply(&response, IP), None);
+        // label length past the end
+        let mut long_label = q.clone();
+        long_label[12] = 63;
+        assert_eq!(dns_reply(&long_label, IP), None);
+    }
+
+    #[test]
+    fn dns_compressed_name_rejected() {
+        let mut q = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        q.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(dns_reply(&q, IP), None);
+        let mut q = query("a.example.com", 1);
+        q[14] = 0xc0;
+        assert_eq!(dns_reply(&q, IP), None);
+    }
+}
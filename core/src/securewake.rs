@@ -0,0 +1,251 @@
+// Signed wake request verification (HMAC-SHA256 with timestamp and nonce)
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+//
+// Request (one UDP datagram, ASCII):
+//   WPB1 <timestamp> <nonce> <target> <hmac>
+// <timestamp> is Unix time in seconds, <nonce> 16 to 64 hex characters,
+// <target> a target or wake group name, and <hmac> the lower case hex
+// HMAC-SHA256 of "WPB1 <timestamp> <nonce> <target>" with the shared secret.
+// `tools/signed_wake.py` builds the same packet on a PC.
+
+use std::collections::VecDeque;
+
+use sha2::{Digest, Sha256};
+
+pub const SECURE_WAKE_PORT: u16 = 9199;
+const PROTOCOL_ID: &str = "WPB1";
+const TIME_WINDOW: u64 = 60;        // seconds of allowed clock difference
+const MAX_SEEN_NONCES: usize = 64;
+const MIN_NONCE_LEN: usize = 16;
+const MAX_NONCE_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerifyError {
+    Format,
+    Signature,
+    Expired,    // timestamp outside the window
+    Replay,     // nonce already used
+    Busy,       // too many recent requests to remember
+}
+
+/// Checks requests and remembers the nonces seen within the time window.
+pub struct WakeVerifier {
+    secret: Vec<u8>,
+    seen: VecDeque<(String, u64)>,
+}
+
+impl WakeVerifier {
+    pub fn new(secret: &str) -> WakeVerifier {
+        WakeVerifier { secret: secret.as_bytes().to_vec(), seen: VecDeque::new() }
+    }
+
+    /// Verify `packet` at time `now` (epoch seconds) and return the target name.
+    /// The signature is checked first, so only signed requests use nonce memory.
+    pub fn verify(&mut self, packet: &str, now: u64) -> Result<String, VerifyError> {
+        let fields : Vec<&str> = packet.trim().split(' ').collect();
+        if fields.len() != 5 || fields[0] != PROTOCOL_ID {
+            return Err(VerifyError::Format);
+        }
+        let (timestamp, nonce, target, mac) = (fields[1], fields[2], fields[3], fields[4]);
+        let timestamp = timestamp.parse::<u64>().map_err(|_| VerifyError::Format)?;
+        if nonce.len() < MIN_NONCE_LEN || nonce.len() > MAX_NONCE_LEN
+            || !nonce.chars().all(|c| c.is_ascii_hexdigit()) || target.is_empty() {
+            return Err(VerifyError::Format);
+        }
+        let expected = hex(&hmac_sha256(&self.secret, signed_text(timestamp, nonce, target).as_bytes()));
+        if !constant_time_eq(expected.as_bytes(), mac.to_ascii_lowercase().as_bytes()) {
+            return Err(VerifyError::Signature);
+        }
+        if timestamp.abs_diff(now) > TIME_WINDOW {
+            return Err(VerifyError::Expired);
+        }
+        // forget nonces whose timestamp can no longer pass the window check
+        self.seen.retain(|(_, ts)| ts.saturating_add(TIME_WINDOW) >= now);
+        let nonce = nonce.to_ascii_lowercase();
+        if self.seen.iter().any(|(n, _)| *n == nonce) {
+            return Err(VerifyError::Replay);
+        }
+        if self.seen.len() >= MAX_SEEN_NONCES {
+            return Err(VerifyError::Busy);
+        }
+        self.seen.push_back((nonce, timestamp));
+        Ok(target.to_string())
+    }
+}
+
+fn signed_text(timestamp: u64, nonce: &str, target: &str) -> String {
+    format!("{} {} {} {}", PROTOCOL_ID, timestamp, nonce, target)
+}
+
+/// HMAC-SHA256 (RFC 2104).
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    }
+    else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let ipad : Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    let opad : Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    let inner = Sha256::new().chain_update(&ipad).chain_update(message).finalize();
+    Sha256::new().chain_update(&opad).chain_update(inner).finalize().into()
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "correct horse battery";
+    const NOW: u64 = 1_700_000_000;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn sign(timestamp: u64, nonce: &str, target: &str) -> String {
+        let text = signed_text(timestamp, nonce, target);
+        format!("{} {}", text, hex(&hmac_sha256(SECRET.as_bytes(), text.as_bytes())))
+    }
+
+    // RFC 4231 test cases 1-4, 6 and 7 (5 is truncated output)
+    #[test]
+    fn hmac_rfc4231() {
+        let cases: [(Vec<u8>, Vec<u8>, &str); 6] = [
+            (vec![0x0b; 20], b"Hi There".to_vec(),
+             "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            (b"Jefe".to_vec(), b"what do ya want for nothing?".to_vec(),
+             "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+            (vec![0xaa; 20], vec![0xdd; 50],
+             "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+            (unhex("0102030405060708090a0b0c0d0e0f10111213141516171819"), vec![0xcd; 50],
+             "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"),
+            (vec![0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First".to_vec(),
+             "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"),
+            (vec![0xaa; 131],
+             b"This is a test using a larger than block-size key and a larger than block-size data. \
+               The key needs to be hashed before being used by the HMAC algorithm.".to_vec(),
+             "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"),
+        ];
+        for (key, data, mac) in cases.iter() {
+            assert_eq!(hex(&hmac_sha256(key, data)), *mac);
+        }
+    }
+
+    #[test]
+    fn packet_from_signed_wake_py() {
+        // python3 -c "from signed_wake import sign_request; print(sign_request('correct horse battery',
+        //   1700000000, '00112233445566778899aabbccddeeff', 'PC2'))"
+        let packet = "WPB1 1700000000 00112233445566778899aabbccddeeff PC2 \
+                      9278bfb270d435eab4631838257095a8ebc124931182db873b0ead302e03af91\n";
+        let mut verifier = WakeVerifier::new(SECRET);
+        assert_eq!(verifier.verify(packet, NOW + 30), Ok("PC2".to_string()));
+        assert_eq!(verifier.verify(packet, NOW + 30), Err(VerifyError::Replay));
+    }
+
+    #[test]
+    fn upper_case_mac_accepted() {
+        let packet = sign(NOW, "0123456789abcdef", "office");
+        let (text, mac) = packet.rsplit_once(' ').unwrap();
+        let mut verifier = WakeVerifier::new(SECRET);
+        assert_eq!(verifier.verify(&format!("{} {}", text, mac.to_ascii_uppercase()), NOW), Ok("office".to_string()));
+    }
+
+    #[test]
+    fn wrong_secret_or_changed_field() {
+        let packet = sign(NOW, "0123456789abcdef", "PC1");
+        assert_eq!(WakeVerifier::new("other").verify(&packet, NOW), Err(VerifyError::Signature));
+        let changed = packet.replacen("PC1", "PC2", 1);
+        assert_eq!(WakeVerifier::new(SECRET).verify(&changed, NOW), Err(VerifyError::Signature));
+    }
+
+    #[test]
+    fn expired_timestamp() {
+        let mut verifier = WakeVerifier::new(SECRET);
+        assert_eq!(verifier.verify(&sign(NOW - TIME_WINDOW - 1, "0123456789abcdef", "PC1"), NOW), Err(VerifyError::Expired));
+        assert_eq!(verifier.verify(&sign(NOW + TIME_WINDOW + 1, "0123456789abcdef", "PC1"), NOW), Err(VerifyError::Expired));
+        assert_eq!(verifier.verify(&sign(NOW - TIME_WINDOW, "0123456789abcdef", "PC1"), NOW), Ok("PC1".to_string()));
+        assert_eq!(verifier.verify(&sign(NOW + TIME_WINDOW, "1123456789abcdef", "PC1"), NOW), Ok("PC1".to_string()));
+    }
+
+    #[test]
+    fn replayed_nonce() {
+        let mut verifier = WakeVerifier::new(SECRET);
+        assert!(verifier.verify(&sign(NOW, "abcdef0123456789", "PC1"), NOW).is_ok());
+        // same nonce, newly signed with another timestamp and target
+        assert_eq!(verifier.verify(&sign(NOW + 5, "abcdef0123456789", "PC2"), NOW + 5), Err(VerifyError::Replay));
+    }
+
+    #[test]
+    fn replayed_nonce_case_folded() {
+        // both packets from signed_wake.py, the second with the nonce in upper case
+        let first = "WPB1 1700000000 abcdef0123456789 PC1 7701c257c973609f627694cb4771c0f486b4f73e1b89ab8defd843626ecb96c9";
+        let second = "WPB1 1700000005 ABCDEF0123456789 PC1 70250aca002db818d7871f47d72bcd576c10f1386c2533b6aabd4ab9035dc62c";
+        let mut verifier = WakeVerifier::new(SECRET);
+        assert_eq!(verifier.verify(first, NOW + 5), Ok("PC1".to_string()));
+        assert_eq!(verifier.verify(second, NOW + 5), Err(VerifyError::Replay));
+        assert_eq!(WakeVerifier::new(SECRET).verify(second, NOW + 5), Ok("PC1".to_string()));
+    }
+
+    #[test]
+    fn nonce_forgotten_after_window() {
+        let mut verifier = WakeVerifier::new(SECRET);
+        assert!(verifier.verify(&sign(NOW, "abcdef0123456789", "PC1"), NOW).is_ok());
+        // the old packet itself is expired by now, so forgetting its nonce is safe
+        let later = NOW + 2 * TIME_WINDOW;
+        assert_eq!(verifier.verify(&sign(NOW, "abcdef0123456789", "PC1"), later), Err(VerifyError::Expired));
+        assert!(verifier.verify(&sign(later, "abcdef0123456789", "PC1"), later).is_ok());
+        assert_eq!(verifier.seen.len(), 1);
+    }
+
+    #[test]
+    fn bad_nonce_length() {
+        let mut verifier = WakeVerifier::new(SECRET);
+        let short = "0".repeat(MIN_NONCE_LEN - 1);
+        let long = "0".repeat(MAX_NONCE_LEN + 1);
+        assert_eq!(verifier.verify(&sign(NOW, &short, "PC1"), NOW), Err(VerifyError::Format));
+        assert_eq!(verifier.verify(&sign(NOW, &long, "PC1"), NOW), Err(VerifyError::Format));
+        assert!(verifier.verify(&sign(NOW, &"1".repeat(MIN_NONCE_LEN), "PC1"), NOW).is_ok());
+        assert!(verifier.verify(&sign(NOW, &"2".repeat(MAX_NONCE_LEN), "PC1"), NOW).is_ok());
+        assert_eq!(verifier.verify(&sign(NOW, "0123456789abcdeg", "PC1"), NOW), Err(VerifyError::Format));
+    }
+
+    #[test]
+    fn malformed_packets() {
+        let mut verifier = WakeVerifier::new(SECRET);
+        let packet = sign(NOW, "0123456789abcdef", "PC1");
+        for bad in ["", "WPB1", &packet.replacen("WPB1", "WPB2", 1), &packet.replacen("1700000000", "17e8", 1),
+                    &format!("{} extra", packet)] {
+            assert_eq!(verifier.verify(bad, NOW), Err(VerifyError::Format), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn busy_when_nonce_memory_full() {
+        let mut verifier = WakeVerifier::new(SECRET);
+        for n in 0..MAX_SEEN_NONCES {
+            assert!(verifier.verify(&sign(NOW, &format!("{:016x}", n), "PC1"), NOW).is_ok());
+        }
+        assert_eq!(verifier.verify(&sign(NOW, "ffffffffffffffff", "PC1"), NOW), Err(VerifyError::Busy));
+    }
+
+    #[test]
+    fn constant_time_eq_lengths() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+}
@@ -0,0 +1,615 @@
+// Line-oriented TCP command shell
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::{thread, time::{Duration, Instant}};
+
+use anyhow::Result;
+use log::*;
+
+use crate::control::{config_value, is_network_writable, Control, TriggerSource};
+use crate::targets::{self, ALL_TARGETS};
+
+pub const SHELL_PORT: u16 = 2323;
+const SHELL_STACK_SIZE: usize = 6144;
+const MAX_LINE_LEN: usize = 256;
+const MAX_AUTH_ATTEMPTS: u32 = 3;
+const AUTH_FAIL_DELAY: u64 = 1000;  // ms
+const IDLE_TIMEOUT: u64 = 300;      // seconds
+const LOGIN_TIMEOUT: u64 = 30;      // seconds to enter the password
+const DEFAULT_TAIL_LINES: usize = 20;
+
+const HELP_TEXT: &str = "\
+wake <target|group>      queue a wake (PC1..PC4, 1..4 or a group name)
+status                   battery, RSSI, IP and send count
+targets                  targets, MAC addresses and wake statistics
+config get [KEY]         show the config (secrets masked)
+config set KEY VALUE     change a config value (restarts the box, not secrets)
+log tail [N]             last N log lines
+reboot                   restart the box
+quit                     close the session";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    Wake(String),
+    Status,
+    Targets,
+    ConfigGet(Option<String>),
+    ConfigSet(String, String),
+    LogTail(usize),
+    Reboot,
+    Quit,
+}
+
+/// Text to send back, and whether the connection is closed afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub text: String,
+    pub close: bool,
+}
+
+impl Reply {
+    fn text(text: String) -> Reply {
+        Reply { text, close: false }
+    }
+
+    fn close(text: String) -> Reply {
+        Reply { text, close: true }
+    }
+}
+
+/// Parse one command line. Keywords are case insensitive; the value of
+/// `config set` is the rest of the line, or a "quoted" string as `config get` prints it.
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    let (word, rest) = split_word(line);
+    match word.to_ascii_lowercase().as_str() {
+        "help" | "?" => Ok(Command::Help),
+        "wake" => {
+            let (target, extra) = split_word(rest);
+            if target.is_empty() || !extra.is_empty() {
+                return Err("usage: wake <target|group>".to_string());
+            }
+            Ok(Command::Wake(target.to_string()))
+        },
+        "status" => Ok(Command::Status),
+        "targets" => Ok(Command::Targets),
+        "config" => {
+            let (sub, rest) = split_word(rest);
+            match sub.to_ascii_lowercase().as_str() {
+                "get" => {
+                    let (key, extra) = split_word(rest);
+                    if !extra.is_empty() {
+                        return Err("usage: config get [KEY]".to_string());
+                    }
+                    Ok(Command::ConfigGet(if key.is_empty() { None } else { Some(key.to_ascii_uppercase()) }))
+                },
+                "set" => {
+                    let (key, value) = split_word(rest);
+                    if key.is_empty() {
+                        return Err("usage: config set KEY VALUE".to_string());
+                    }
+                    Ok(Command::ConfigSet(key.to_ascii_uppercase(), unquote(value)?))
+                },
+                _ => Err("usage: config get [KEY] | config set KEY VALUE".to_string()),
+            }
+        },
+        "log" => {
+            let (sub, rest) = split_word(rest);
+            if !sub.eq_ignore_ascii_case("tail") {
+                return Err("usage: log tail [N]".to_string());
+            }
+            if rest.is_empty() {
+                return Ok(Command::LogTail(DEFAULT_TAIL_LINES));
+            }
+            rest.parse::<usize>().map(Command::LogTail).map_err(|_| "usage: log tail [N]".to_string())
+        },
+        "reboot" => Ok(Command::Reboot),
+        "quit" | "exit" => Ok(Command::Quit),
+        "" => Err(String::new()),
+        other => Err(format!("unknown command '{}', try 'help'", other)),
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// `"..."` with `\"` and `\\` escapes is unquoted; anything else is taken as is
+fn unquote(value: &str) -> Result<String, String> {
+    let inner = match value.strip_prefix('"') {
+        Some(inner) => inner,
+        None => return Ok(value.to_string()),
+    };
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if chars.as_str().is_empty() => return Ok(out),
+            '"' => return Err("text after closing quote".to_string()),
+            '\\' => match chars.next() {
+                Some(escaped) => out.push(escaped),
+                None => break,
+            },
+            c => out.push(c),
+        }
+    }
+    Err("missing closing quote".to_string())
+}
+
+fn split_word(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (line, ""),
+    }
+}
+
+/// Run a command through the shared `Control` handlers.
+/// `log_tail` returns the last N log lines (`syslog::tail` on the device).
+pub fn execute(command: &Command, control: &Control, log_tail: fn(usize) -> Vec<String>) -> Reply {
+    match command {
+        Command::Help => Reply::text(HELP_TEXT.to_string()),
+        Command::Wake(name) => {
+            match control.request_wake(name, TriggerSource::Shell) {
+                Some(targets) => {
+                    info!("[SHELL] Wake request: {:?}", targets);
+                    let names : Vec<&str> = targets.iter().map(|t| t.name()).collect();
+                    Reply::text(format!("queued {}", names.join(" ")))
+                },
+                None => Reply::text(format!("error: unknown target or group '{}'", name)),
+            }
+        },
+        Command::Status => {
+            let status = control.get_status();
+            Reply::text(format!("battery {:.2}V\nrssi {}dBm\nip {}\nsend_count {}",
+                status.battery_voltage, status.rssi, status.ip_addr, status.send_count))
+        },
+        Command::Targets => {
+            let config = control.get_config_entries();
+            let status = control.get_status();
+            let mut lines : Vec<String> = ALL_TARGETS.iter().enumerate().map(|(i, t)| {
+                let stats = &status.targets[i];
+                let last = match stats.last_wake {
+                    Some(result) => format!(" last={}@{}", if result.success { "ok" } else { "failed" }, result.time),
+                    None => String::new(),
+                };
+                format!("{} {} wakes={} failures={}{}",
+                    t.name(), config_value(&config, t.name()), stats.wake_count, stats.fail_count, last)
+            }).collect();
+            for (name, targets) in targets::parse_groups(&config_value(&config, "GROUPS")) {
+                let names : Vec<&str> = targets.iter().map(|t| t.name()).collect();
+                lines.push(format!("group {} {}", name, names.join("+")));
+            }
+            Reply::text(lines.join("\n"))
+        },
+        Command::ConfigGet(key) => {
+            let config = control.get_public_config_entries();
+            match key {
+                Some(key) => match config.iter().find(|it| it.0 == *key) {
+                    Some(it) => Reply::text(format!("{} = {}", it.0, quote(&it.1))),
+                    None => Reply::text(format!("error: unknown key '{}'", key)),
+                },
+                None => Reply::text(config.iter().map(|it| format!("{} = {}", it.0, quote(&it.1))).collect::<Vec<String>>().join("\n")),
+            }
+        },
+        Command::ConfigSet(key, value) => {
+            if !is_network_writable(key) {
+                return Reply::text(format!("error: {} can only be changed on the box", key));
+            }
+            match control.request_public_config_update(&[(key.clone(), value.clone())]) {
+                Ok(updated) if updated.is_empty() => Reply::text(format!("error: unknown key '{}'", key)),
+                Ok(updated) => {
+                    info!("[SHELL] Config update: {:?}", updated);
+                    Reply::close(format!("{} updated, restarting", key))
+                },
+                Err(_) => Reply::text(format!("error: invalid value for {}", key)),
+            }
+        },
+        Command::LogTail(count) => Reply::text(log_tail(*count).join("\n")),
+        Command::Reboot => {
+            info!("[SHELL] Reboot request");
+            control.request_restart();
+            Reply::close("restarting".to_string())
+        },
+        Command::Quit => Reply::close("bye".to_string()),
+    }
+}
+
+/// One connection: password first, then commands.
+pub struct Session {
+    password: String,
+    authenticated: bool,
+    failures: u32,
+}
+
+impl Session {
+    pub fn new(password: &str) -> Session {
+        Session { password: password.to_string(), authenticated: false, failures: 0 }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.authenticated { "> " } else { "Password: " }
+    }
+
+    /// Handle one received line (without the line ending).
+    pub fn handle_line(&mut self, line: &str, control: &Control, log_tail: fn(usize) -> Vec<String>) -> Reply {
+        if !self.authenticated {
+            if password_matches(line.trim(), &self.password) {
+                self.authenticated = true;
+                return Reply::text("WakePCBox shell, 'help' for commands".to_string());
+            }
+            self.failures += 1;
+            if self.failures >= MAX_AUTH_ATTEMPTS {
+                return Reply::close("authentication failed".to_string());
+            }
+            return Reply::text("wrong password".to_string());
+        }
+        match parse_command(line) {
+            Ok(command) => execute(&command, control, log_tail),
+            Err(e) => Reply::text(e),
+        }
+    }
+}
+
+// Compare without an early exit on the first differing byte
+fn password_matches(input: &str, password: &str) -> bool {
+    let (a, b) = (input.as_bytes(), password.as_bytes());
+    let mut diff = a.len() ^ b.len();
+    for i in 0..a.len().max(b.len()) {
+        diff |= (a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0)) as usize;
+    }
+    !password.is_empty() && diff == 0
+}
+
+/// Listen on `SHELL_PORT`. One client is served at a time.
+pub fn start(password: &str, control: Control, log_tail: fn(usize) -> Vec<String>) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", SHELL_PORT))?;
+    let password = password.to_string();
+    let _th = thread::Builder::new().stack_size(SHELL_STACK_SIZE).spawn(move || {
+        info!("Start Shell Thread. port {}", SHELL_PORT);
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = serve(stream, &password, &control, log_tail) {
+                        info!("[SHELL] Connection closed: {:?}", e);
+                    }
+                },
+                Err(e) => {
+                    info!("[SHELL] Accept failed: {:?}", e);
+                    thread::sleep(Duration::from_millis(AUTH_FAIL_DELAY));
+                },
+            }
+        }
+    })?;
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum LineRead {
+    Line(String),
+    TooLong,
+    Eof,
+}
+
+/// Read up to the next newline. A line longer than `max_len` is consumed
+/// whole and reported as `TooLong`, so its tail is not taken as a command.
+/// `before_read` runs before each read from the socket, e.g. to set a timeout.
+fn read_line_limited<R: BufRead>(reader: &mut R, max_len: usize, mut before_read: impl FnMut(&R) -> io::Result<()>) -> io::Result<LineRead> {
+    let mut line = Vec::new();
+    let mut too_long = false;
+    loop {
+        before_read(reader)?;
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            if line.is_empty() && !too_long {
+                return Ok(LineRead::Eof);
+            }
+            break;
+        }
+        let (used, found) = match buf.iter().position(|b| *b == b'\n') {
+            Some(pos) => (pos + 1, true),
+            None => (buf.len(), false),
+        };
+        let data = &buf[..if found { used - 1 } else { used }];
+        if line.len() + data.len() > max_len {
+            too_long = true;
+        } else if !too_long {
+            line.extend_from_slice(data);
+        }
+        reader.consume(used);
+        if found {
+            break;
+        }
+    }
+    if too_long {
+        return Ok(LineRead::TooLong);
+    }
+    Ok(LineRead::Line(String::from_utf8_lossy(&line).trim_end_matches('\r').to_string()))
+}
+
+fn serve(stream: TcpStream, password: &str, control: &Control, log_tail: fn(usize) -> Vec<String>) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    info!("[SHELL] Connection from {}", peer);
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut session = Session::new(password);
+    // the password has to arrive within LOGIN_TIMEOUT, however slowly it is sent
+    let login_deadline = Instant::now() + Duration::from_secs(LOGIN_TIMEOUT);
+    writer.write_all(session.prompt().as_bytes())?;
+    loop {
+        let authenticated = session.is_authenticated();
+        let read = read_line_limited(&mut reader, MAX_LINE_LEN, |r| {
+            let timeout = if authenticated {
+                Duration::from_secs(IDLE_TIMEOUT)
+            } else {
+                match login_deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => left,
+                    _ => return Err(io::Error::new(io::ErrorKind::TimedOut, "login timeout")),
+                }
+            };
+            r.get_ref().set_read_timeout(Some(timeout))
+        })?;
+        let line = match read {
+            LineRead::Line(line) => line,
+            LineRead::TooLong => {
+                writer.write_all(format!("error: line longer than {} bytes\r\n", MAX_LINE_LEN).as_bytes())?;
+                writer.write_all(session.prompt().as_bytes())?;
+                continue;
+            },
+            LineRead::Eof => break,
+        };
+        let was_authenticated = session.is_authenticated();
+        let reply = session.handle_line(&line, control, log_tail);
+        if !was_authenticated && !session.is_authenticated() {
+            info!("[SHELL] Wrong password from {}", peer);
+            thread::sleep(Duration::from_millis(AUTH_FAIL_DELAY));
+        }
+        if !reply.text.is_empty() {
+            writer.write_all(reply.text.replace('\n', "\r\n").as_bytes())?;
+            writer.write_all(b"\r\n")?;
+        }
+        if reply.close {
+            break;
+        }
+        writer.write_all(session.prompt().as_bytes())?;
+    }
+    info!("[SHELL] {} disconnected", peer);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_lines(input: &[u8], max_len: usize) -> Vec<LineRead> {
+        // a tiny buffer, so lines arrive in several reads like from a socket
+        let mut reader = BufReader::with_capacity(4, input);
+        let mut lines = Vec::new();
+        loop {
+            let line = read_line_limited(&mut reader, max_len, |_| Ok(())).unwrap();
+            if line == LineRead::Eof {
+                return lines;
+            }
+            lines.push(line);
+        }
+    }
+
+    fn no_log(_count: usize) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn control() -> Control {
+        let control = Control::new();
+        control.set_config_entries(vec![
+            ("PC1".to_string(), "00:11:22:33:44:55".to_string()),
+            ("GROUPS".to_string(), "office:PC1+PC2".to_string()),
+            ("HOSTNAME".to_string(), "box".to_string()),
+            ("PSK".to_string(), "wifi-secret".to_string()),
+            ("PINLOCK".to_string(), "both".to_string()),
+        ]);
+        control
+    }
+
+    fn logged_in(control: &Control) -> Session {
+        let mut session = Session::new("letmein");
+        session.handle_line("letmein", control, no_log);
+        assert!(session.is_authenticated());
+        session
+    }
+
+    #[test]
+    fn password_accepted() {
+        let control = control();
+        let mut session = Session::new("letmein");
+        assert_eq!(session.prompt(), "Password: ");
+        let reply = session.handle_line("  letmein ", &control, no_log);
+        assert!(session.is_authenticated());
+        assert!(!reply.close);
+        assert_eq!(session.prompt(), "> ");
+    }
+
+    #[test]
+    fn wrong_password_closes_after_three_attempts() {
+        let control = control();
+        let mut session = Session::new("letmein");
+        assert_eq!(session.handle_line("letme", &control, no_log), Reply::text("wrong password".to_string()));
+        assert_eq!(session.handle_line("letmein2", &control, no_log), Reply::text("wrong password".to_string()));
+        assert_eq!(session.handle_line("LETMEIN", &control, no_log), Reply::close("authentication failed".to_string()));
+        assert!(!session.is_authenticated());
+    }
+
+    #[test]
+    fn commands_need_login() {
+        let control = control();
+        let mut session = Session::new("letmein");
+        session.handle_line("wake PC1", &control, no_log);
+        assert!(control.take_wake_requests().is_empty());
+    }
+
+    #[test]
+    fn empty_password_never_matches() {
+        let control = control();
+        let mut session = Session::new("");
+        session.handle_line("", &control, no_log);
+        assert!(!session.is_authenticated());
+    }
+
+    #[test]
+    fn unknown_command() {
+        let control = control();
+        let mut session = logged_in(&control);
+        let reply = session.handle_line("frobnicate now", &control, no_log);
+        assert_eq!(reply.text, "unknown command 'frobnicate', try 'help'");
+        assert!(!reply.close);
+        assert_eq!(session.handle_line("", &control, no_log).text, "");
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive() {
+        assert_eq!(parse_command("WAKE pc1"), Ok(Command::Wake("pc1".to_string())));
+        assert_eq!(parse_command("Config Get hostname"), Ok(Command::ConfigGet(Some("HOSTNAME".to_string()))));
+        assert_eq!(parse_command("LOG TAIL 5"), Ok(Command::LogTail(5)));
+        assert_eq!(parse_command("log tail"), Ok(Command::LogTail(DEFAULT_TAIL_LINES)));
+        assert_eq!(parse_command("exit"), Ok(Command::Quit));
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert!(parse_command("wake").is_err());
+        assert!(parse_command("wake PC1 PC2").is_err());
+        assert!(parse_command("config set").is_err());
+        assert!(parse_command("config get A B").is_err());
+        assert!(parse_command("log tail many").is_err());
+    }
+
+    #[test]
+    fn config_set_value_is_rest_of_line() {
+        assert_eq!(parse_command("config set webhookbody {\"a\": \"b c\"}"),
+            Ok(Command::ConfigSet("WEBHOOKBODY".to_string(), "{\"a\": \"b c\"}".to_string())));
+        assert_eq!(parse_command("config set GROUPS"), Ok(Command::ConfigSet("GROUPS".to_string(), String::new())));
+    }
+
+    #[test]
+    fn config_set_quoted_value() {
+        assert_eq!(parse_command("config set HOSTNAME \"my box\""),
+            Ok(Command::ConfigSet("HOSTNAME".to_string(), "my box".to_string())));
+        assert_eq!(parse_command(r#"config set WEBHOOKBODY "{\"t\":\"{target}\"} \\o/""#),
+            Ok(Command::ConfigSet("WEBHOOKBODY".to_string(), r#"{"t":"{target}"} \o/"#.to_string())));
+        assert_eq!(parse_command("config set HOSTNAME \"\""), Ok(Command::ConfigSet("HOSTNAME".to_string(), String::new())));
+        assert!(parse_command("config set HOSTNAME \"my box").is_err());
+        assert!(parse_command("config set HOSTNAME \"my\" box").is_err());
+    }
+
+    #[test]
+    fn config_get_quotes_like_config_set() {
+        let control = control();
+        control.set_config_entries(vec![("WEBHOOKBODY".to_string(), r#"{"a":"\n"}"#.to_string())]);
+        let mut session = logged_in(&control);
+        let reply = session.handle_line("config get WEBHOOKBODY", &control, no_log);
+        assert_eq!(reply.text, r#"WEBHOOKBODY = "{\"a\":\"\\n\"}""#);
+        let value = reply.text.split_once(" = ").unwrap().1;
+        assert_eq!(unquote(value), Ok(r#"{"a":"\n"}"#.to_string()));
+    }
+
+    #[test]
+    fn config_get_masks_secrets() {
+        let control = control();
+        let mut session = logged_in(&control);
+        let reply = session.handle_line("config get psk", &control, no_log);
+        assert_eq!(reply.text, "PSK = \"********\"");
+        assert!(!session.handle_line("config get", &control, no_log).text.contains("wifi-secret"));
+    }
+
+    #[test]
+    fn config_set_updates_and_closes() {
+        let control = control();
+        let mut session = logged_in(&control);
+        let reply = session.handle_line("config set hostname \"new box\"", &control, no_log);
+        assert_eq!(reply, Reply::close("HOSTNAME updated, restarting".to_string()));
+        let update = control.take_config_update().unwrap();
+        assert!(update.contains(&("HOSTNAME".to_string(), "new box".to_string())));
+    }
+
+    #[test]
+    fn config_set_refuses_secret_keys() {
+        let control = control();
+        let mut session = logged_in(&control);
+        for line in ["config set PSK hacked", "config set psk ********", "config set PINHASH 00:11", "config set PINLOCK off"] {
+            let reply = session.handle_line(line, &control, no_log);
+            assert!(reply.text.ends_with("can only be changed on the box"), "{}: {}", line, reply.text);
+            assert!(!reply.close);
+        }
+        assert_eq!(control.take_config_update(), None);
+    }
+
+    #[test]
+    fn config_set_unknown_key() {
+        let control = control();
+        let mut session = logged_in(&control);
+        assert_eq!(session.handle_line("config set NOSUCH 1", &control, no_log).text, "error: unknown key 'NOSUCH'");
+        assert_eq!(control.take_config_update(), None);
+    }
+
+    #[test]
+    fn config_set_invalid_value() {
+        let control = control();
+        let mut session = logged_in(&control);
+        for line in ["config set PC1 \"\"", "config set pc1 zz", "config set PC1 00:11:22:33:44:55:66"] {
+            let reply = session.handle_line(line, &control, no_log);
+            assert_eq!(reply, Reply::text("error: invalid value for PC1".to_string()), "{}", line);
+        }
+        assert_eq!(control.take_config_update(), None);
+    }
+
+    #[test]
+    fn wake_target_and_group() {
+        let control = control();
+        let mut session = logged_in(&control);
+        assert_eq!(session.handle_line("wake 1", &control, no_log).text, "queued PC1");
+        assert_eq!(session.handle_line("wake OFFICE", &control, no_log).text, "queued PC1 PC2");
+        assert_eq!(session.handle_line("wake lab", &control, no_log).text, "error: unknown target or group 'lab'");
+        let requests = control.take_wake_requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].targets, vec![crate::targets::TargetDevice::Device1, crate::targets::TargetDevice::Device2]);
+        assert_eq!(requests[0].source, TriggerSource::Shell);
+    }
+
+    #[test]
+    fn quit_and_reboot_close() {
+        let control = control();
+        let mut session = logged_in(&control);
+        assert!(session.handle_line("quit", &control, no_log).close);
+        assert!(session.handle_line("reboot", &control, no_log).close);
+        assert!(control.take_restart_request());
+    }
+
+    #[test]
+    fn long_line_is_discarded_whole() {
+        let input = format!("status\r\n{}reboot\nquit", "x".repeat(20));
+        assert_eq!(read_lines(input.as_bytes(), 16), [
+            LineRead::Line("status".to_string()),
+            LineRead::TooLong,
+            LineRead::Line("quit".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn line_at_the_limit_is_kept() {
+        let input = format!("{}\n{}\n", "a".repeat(16), "b".repeat(17));
+        assert_eq!(read_lines(input.as_bytes(), 16), [LineRead::Line("a".repeat(16)), LineRead::TooLong]);
+    }
+
+    #[test]
+    fn read_error_is_returned() {
+        let mut reader = BufReader::new(&b"status\n"[..]);
+        let result = read_line_limited(&mut reader, 16, |_| Err(io::Error::new(io::ErrorKind::TimedOut, "login timeout")));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+}
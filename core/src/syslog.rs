@@ -0,0 +1,109 @@
+// RFC 5424 message format for the syslog backend
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use log::{Level, LevelFilter};
+
+const MAX_MSG_LEN: usize = 480;
+const FACILITY_LOCAL0: u8 = 16;
+const APP_NAME: &str = "wakepcbox";
+
+pub fn parse_level(level: &str) -> LevelFilter {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "error" => LevelFilter::Error,
+        "warn" => LevelFilter::Warn,
+        "debug" => LevelFilter::Debug,
+        "trace" => LevelFilter::Trace,
+        _ => LevelFilter::Info,
+    }
+}
+
+/// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+pub fn format_rfc5424(level: Level, time: Option<SystemTime>, hostname: &str, target: &str, message: &str) -> String {
+    let severity = match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+    let pri = FACILITY_LOCAL0 * 8 + severity;
+    let timestamp = match time {
+        Some(time) => {
+            let dt : DateTime<Utc> = time.into();
+            dt.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+        },
+        None => "-".to_string(),
+    };
+    let mut message : String = message.chars().filter(|c| !c.is_control()).collect();
+    if message.len() > MAX_MSG_LEN {
+        let mut end = MAX_MSG_LEN;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+    }
+    format!("<{}>1 {} {} {} - {} - {}",
+        pri, timestamp, header_field(hostname, 255), APP_NAME, header_field(target, 32), message)
+}
+
+// Header fields are printable US-ASCII without spaces; empty is the NILVALUE "-".
+fn header_field(value: &str, max_len: usize) -> String {
+    let field : String = value.chars().filter(|c| c.is_ascii_graphic()).take(max_len).collect();
+    if field.is_empty() { "-".to_string() } else { field }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn priority_from_level() {
+        // local0 (16) * 8 + severity
+        for (level, pri) in [(Level::Error, 131), (Level::Warn, 132), (Level::Info, 134), (Level::Debug, 135), (Level::Trace, 135)] {
+            assert!(format_rfc5424(level, None, "box", "main", "x").starts_with(&format!("<{}>1 ", pri)), "{:?}", level);
+        }
+    }
+
+    #[test]
+    fn timestamp_after_sync() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_718_000_000_123);
+        assert_eq!(format_rfc5424(Level::Info, Some(time), "box", "wakepcbox::wifi", "connected"),
+            "<134>1 2024-06-10T06:13:20.123Z box wakepcbox - wakepcbox::wifi - connected");
+    }
+
+    #[test]
+    fn nil_timestamp_before_sync() {
+        assert_eq!(format_rfc5424(Level::Warn, None, "box", "main", "no time"), "<132>1 - box wakepcbox - main - no time");
+    }
+
+    #[test]
+    fn header_fields() {
+        // spaces and non-ASCII are dropped, empty fields become the NILVALUE
+        assert_eq!(format_rfc5424(Level::Info, None, "my box\u{e9}", "", "m"), "<134>1 - mybox wakepcbox - - - m");
+        assert_eq!(format_rfc5424(Level::Info, None, " ", "a b", "m"), "<134>1 - - wakepcbox - ab - m");
+        let long = "x".repeat(300);
+        let line = format_rfc5424(Level::Info, None, &long, &long, "m");
+        assert_eq!(line, format!("<134>1 - {} wakepcbox - {} - m", "x".repeat(255), "x".repeat(32)));
+    }
+
+    #[test]
+    fn message_cleaned_and_truncated() {
+        assert!(format_rfc5424(Level::Info, None, "box", "main", "a\r\nb\tc").ends_with(" - abc"));
+        let message = "\u{3042}".repeat(200);
+        let line = format_rfc5424(Level::Info, None, "box", "main", &message);
+        let msg = line.rsplit(" - ").next().unwrap();
+        assert_eq!(msg.len(), 480);
+        assert_eq!(msg.chars().count(), 160);
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(parse_level(" WARN "), LevelFilter::Warn);
+        assert_eq!(parse_level("debug"), LevelFilter::Debug);
+        assert_eq!(parse_level("verbose"), LevelFilter::Info);
+    }
+}
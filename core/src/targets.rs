@@ -0,0 +1,118 @@
+// Wake targets and wake groups
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+pub const NUM_OF_MAX_TARGET: usize = 4;
+
+#[derive (Debug, Clone, Copy, PartialEq)]
+pub enum TargetDevice {
+    None,
+    Device1,
+    Device2,
+    Device3,
+    Device4,
+}
+
+pub const ALL_TARGETS: [TargetDevice; NUM_OF_MAX_TARGET] = [
+    TargetDevice::Device1,
+    TargetDevice::Device2,
+    TargetDevice::Device3,
+    TargetDevice::Device4,
+];
+
+impl TargetDevice {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TargetDevice::Device1 => "PC1",
+            TargetDevice::Device2 => "PC2",
+            TargetDevice::Device3 => "PC3",
+            TargetDevice::Device4 => "PC4",
+            TargetDevice::None => "",
+        }
+    }
+
+    pub fn from_name(name: &str) -> TargetDevice {
+        match name.trim().to_uppercase().as_str() {
+            "PC1" | "1" => TargetDevice::Device1,
+            "PC2" | "2" => TargetDevice::Device2,
+            "PC3" | "3" => TargetDevice::Device3,
+            "PC4" | "4" => TargetDevice::Device4,
+            _ => TargetDevice::None,
+        }
+    }
+}
+
+/// Parse a MAC address like `00:11:22:aa:bb:cc`.
+pub fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut mac_address = [0u8; 6];
+    let parts : Vec<&str> = mac.trim().split(':').collect();
+    if parts.len() != mac_address.len() {
+        return None;
+    }
+    for (i, part) in parts.iter().enumerate() {
+        if part.is_empty() || part.len() > 2 || !part.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        mac_address[i] = u8::from_str_radix(part, 16).ok()?;
+    }
+    Some(mac_address)
+}
+
+/// Parse wake groups like `office:PC1+PC2,lab:PC3`.
+/// The group `all` is always available.
+pub fn parse_groups(groups: &str) -> Vec<(String, Vec<TargetDevice>)> {
+    let mut ret = vec![("all".to_string(), ALL_TARGETS.to_vec())];
+    for group in groups.split(',') {
+        let mut it = group.splitn(2, ':');
+        let name = it.next().unwrap_or("").trim();
+        let members = it.next().unwrap_or("");
+        if name.is_empty() {
+            continue;
+        }
+        let targets : Vec<TargetDevice> = members.split('+')
+            .map(TargetDevice::from_name)
+            .filter(|t| *t != TargetDevice::None)
+            .collect();
+        if !targets.is_empty() {
+            ret.push((name.to_string(), targets));
+        }
+    }
+    ret
+}
+
+/// Look up a group by name (case insensitive).
+pub fn find_group(groups: &str, name: &str) -> Option<Vec<TargetDevice>> {
+    parse_groups(groups).into_iter()
+        .find(|g| g.0.eq_ignore_ascii_case(name.trim()))
+        .map(|g| g.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mac_address() {
+        assert_eq!(parse_mac("00:11:22:aa:BB:cc"), Some([0x00, 0x11, 0x22, 0xaa, 0xbb, 0xcc]));
+        assert_eq!(parse_mac(" 0:1:2:3:4:5 "), Some([0, 1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn bad_mac_address() {
+        for mac in ["", "zz", "00:11:22:33:44", "00:11:22:33:44:55:66", "00:11:22:33:44:", "000:11:22:33:44:55",
+                    "+0:11:22:33:44:55", "00-11-22-33-44-55"] {
+            assert_eq!(parse_mac(mac), None, "{}", mac);
+        }
+    }
+
+    #[test]
+    fn groups() {
+        let groups = parse_groups("office:PC1+PC2, lab:3+bogus,empty:,:PC4");
+        let names : Vec<&str> = groups.iter().map(|g| g.0.as_str()).collect();
+        assert_eq!(names, ["all", "office", "lab"]);
+        assert_eq!(groups[2].1, vec![TargetDevice::Device3]);
+        assert_eq!(find_group("office:PC1+PC2", " OFFICE "), Some(vec![TargetDevice::Device1, TargetDevice::Device2]));
+        assert_eq!(find_group("", "all"), Some(ALL_TARGETS.to_vec()));
+        assert_eq!(find_group("", "lab"), None);
+    }
+}
@@ -0,0 +1,297 @@
+// Time service: SNTP sync state, last sync, and restore after a restart or deep sleep
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::*;
+
+const RESYNC_TIMEOUT: u64 = 7200;       // seconds without a sync before SNTP is restarted
+const MAX_RESTORED_AGE: u64 = 86400;    // a restored clock is trusted for one day after the last sync
+
+// Readable from any thread (logger, network services)
+static TIME_VALID: AtomicBool = AtomicBool::new(false);
+
+/// True once the wall clock can be trusted (synced, or restored after a restart or deep sleep).
+pub fn is_time_valid() -> bool {
+    TIME_VALID.load(Ordering::Acquire)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncState {
+    NotSynced,
+    Restored,   // kept by the RTC across a restart or deep sleep, not synced since boot
+    Synced,
+}
+
+/// Source of time. `SystemClock` on the device; tests can drive a fake one.
+pub trait Clock {
+    /// Wall clock, Unix time in seconds (meaningless until synced).
+    fn epoch_secs(&self) -> u64;
+    /// Monotonic time since boot in seconds.
+    fn uptime_secs(&self) -> u64;
+}
+
+/// What is kept in RTC memory across a restart or deep sleep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PersistedTime {
+    pub last_sync: u64,     // epoch seconds of the last successful sync
+    pub last_known: u64,    // epoch seconds when it was saved
+}
+
+pub struct TimeService<C: Clock> {
+    clock: C,
+    state: SyncState,
+    last_sync: Option<u64>,         // epoch seconds
+    last_sync_uptime: Option<u64>,
+    last_attempt_uptime: u64,
+}
+
+impl<C: Clock> TimeService<C> {
+    /// `persisted` is the record saved at the last sync or before deep sleep, if any. The clock is
+    /// trusted again only if it kept running since then and the last sync is recent.
+    pub fn new(clock: C, persisted: Option<PersistedTime>) -> TimeService<C> {
+        let now = clock.epoch_secs();
+        let uptime = clock.uptime_secs();
+        let mut svc = TimeService { clock, state: SyncState::NotSynced, last_sync: None, last_sync_uptime: None, last_attempt_uptime: uptime };
+        if let Some(p) = persisted {
+            if now >= p.last_known && p.last_known >= p.last_sync && now - p.last_sync <= MAX_RESTORED_AGE {
+                info!("[TIME] Clock restored, last sync {} s ago", now - p.last_sync);
+                svc.state = SyncState::Restored;
+                svc.last_sync = Some(p.last_sync);
+            }
+        }
+        svc.publish();
+        svc
+    }
+
+    /// Feed the SNTP result once per loop. `sync_completed` is true when a sync
+    /// finished since the last call. Returns true when SNTP should be restarted.
+    pub fn update(&mut self, sync_completed: bool) -> bool {
+        let uptime = self.clock.uptime_secs();
+        if sync_completed {
+            let now = self.clock.epoch_secs();
+            info!("[TIME] Synced: {}", now);
+            self.state = SyncState::Synced;
+            self.last_sync = Some(now);
+            self.last_sync_uptime = Some(uptime);
+            self.last_attempt_uptime = uptime;
+            self.publish();
+            return false;
+        }
+        let since = self.last_sync_uptime.unwrap_or(0).max(self.last_attempt_uptime);
+        if uptime.saturating_sub(since) >= RESYNC_TIMEOUT {
+            info!("[TIME] No sync for {} s, restarting SNTP", uptime - since);
+            self.last_attempt_uptime = uptime;
+            return true;
+        }
+        false
+    }
+
+    pub fn state(&self) -> SyncState {
+        self.state
+    }
+
+    pub fn is_time_valid(&self) -> bool {
+        self.state != SyncState::NotSynced
+    }
+
+    /// Epoch seconds of the last successful sync (possibly before a restart or deep sleep).
+    pub fn last_sync(&self) -> Option<u64> {
+        self.last_sync
+    }
+
+    /// Record to save after a sync and before deep sleep, or None if there is nothing worth keeping.
+    pub fn persisted(&self) -> Option<PersistedTime> {
+        self.last_sync.map(|last_sync| PersistedTime { last_sync, last_known: self.clock.epoch_secs() })
+    }
+
+    /// Short text for the Diagnostics page, e.g. `synced 12m ago`.
+    pub fn status_text(&self) -> String {
+        let ago = |t: u64| {
+            let secs = self.clock.epoch_secs().saturating_sub(t);
+            if secs < 3600 { format!("{}m ago", secs / 60) } else { format!("{}h ago", secs / 3600) }
+        };
+        match (self.state, self.last_sync) {
+            (SyncState::Synced, Some(t)) => format!("synced {}", ago(t)),
+            (SyncState::Restored, Some(t)) => format!("restored {}", ago(t)),
+            _ => "not synced".to_string(),
+        }
+    }
+
+    fn publish(&self) {
+        TIME_VALID.store(self.is_time_valid(), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const NOW: u64 = 1_700_000_000;
+
+    // Wall clock and uptime set by the test; clones share the same time
+    #[derive(Clone)]
+    struct FakeClock {
+        epoch: Rc<Cell<u64>>,
+        uptime: Rc<Cell<u64>>,
+    }
+
+    impl FakeClock {
+        fn new(epoch: u64) -> FakeClock {
+            FakeClock { epoch: Rc::new(Cell::new(epoch)), uptime: Rc::new(Cell::new(0)) }
+        }
+
+        fn advance(&self, secs: u64) {
+            self.epoch.set(self.epoch.get() + secs);
+            self.uptime.set(self.uptime.get() + secs);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn epoch_secs(&self) -> u64 {
+            self.epoch.get()
+        }
+
+        fn uptime_secs(&self) -> u64 {
+            self.uptime.get()
+        }
+    }
+
+    #[test]
+    fn invalid_until_first_sync() {
+        // unsynced clock after power-on: epoch starts near 0
+        let clock = FakeClock::new(0);
+        let mut svc = TimeService::new(clock.clone(), None);
+        assert_eq!(svc.state(), SyncState::NotSynced);
+        assert!(!svc.is_time_valid());
+        assert_eq!(svc.last_sync(), None);
+        assert_eq!(svc.persisted(), None);
+        assert_eq!(svc.status_text(), "not synced");
+        clock.advance(30);
+        assert!(!svc.update(false));
+        assert!(!svc.is_time_valid());
+        clock.epoch.set(NOW);
+        svc.update(true);
+        assert_eq!(svc.state(), SyncState::Synced);
+        assert!(svc.is_time_valid());
+    }
+
+    #[test]
+    fn restore_after_deep_sleep() {
+        let clock = FakeClock::new(NOW);
+        let persisted = PersistedTime { last_sync: NOW - 3 * 3600, last_known: NOW - 600 };
+        let svc = TimeService::new(clock, Some(persisted));
+        assert_eq!(svc.state(), SyncState::Restored);
+        assert!(svc.is_time_valid());
+        assert_eq!(svc.last_sync(), Some(NOW - 3 * 3600));
+        assert_eq!(svc.status_text(), "restored 3h ago");
+    }
+
+    #[test]
+    fn restore_rejected_when_clock_was_reset() {
+        // power loss: the wall clock starts over and is behind the saved time
+        let svc = TimeService::new(FakeClock::new(5), Some(PersistedTime { last_sync: NOW - 60, last_known: NOW }));
+        assert_eq!(svc.state(), SyncState::NotSynced);
+        assert_eq!(svc.last_sync(), None);
+    }
+
+    #[test]
+    fn restore_rejected_when_sync_too_old() {
+        let persisted = PersistedTime { last_sync: NOW - MAX_RESTORED_AGE - 1, last_known: NOW - 10 };
+        let svc = TimeService::new(FakeClock::new(NOW), Some(persisted));
+        assert!(!svc.is_time_valid());
+        let persisted = PersistedTime { last_sync: NOW - MAX_RESTORED_AGE, last_known: NOW - 10 };
+        assert!(TimeService::new(FakeClock::new(NOW), Some(persisted)).is_time_valid());
+    }
+
+    #[test]
+    fn restore_rejected_when_record_inconsistent() {
+        // saved before the sync it claims: garbage in RTC memory
+        let persisted = PersistedTime { last_sync: NOW - 10, last_known: NOW - 100 };
+        assert!(!TimeService::new(FakeClock::new(NOW), Some(persisted)).is_time_valid());
+    }
+
+    #[test]
+    fn last_sync_tracking() {
+        let clock = FakeClock::new(NOW);
+        let mut svc = TimeService::new(clock.clone(), None);
+        svc.update(true);
+        assert_eq!(svc.last_sync(), Some(NOW));
+        assert_eq!(svc.status_text(), "synced 0m ago");
+        clock.advance(25 * 60);
+        svc.update(false);
+        assert_eq!(svc.last_sync(), Some(NOW));
+        assert_eq!(svc.status_text(), "synced 25m ago");
+        clock.advance(2 * 3600);
+        assert_eq!(svc.status_text(), "synced 2h ago");
+        svc.update(true);
+        assert_eq!(svc.last_sync(), Some(NOW + 25 * 60 + 2 * 3600));
+    }
+
+    #[test]
+    fn sync_replaces_restored_state() {
+        let clock = FakeClock::new(NOW);
+        let mut svc = TimeService::new(clock.clone(), Some(PersistedTime { last_sync: NOW - 100, last_known: NOW - 50 }));
+        assert_eq!(svc.state(), SyncState::Restored);
+        clock.advance(10);
+        svc.update(true);
+        assert_eq!(svc.state(), SyncState::Synced);
+        assert_eq!(svc.last_sync(), Some(NOW + 10));
+    }
+
+    #[test]
+    fn persisted_round_trip() {
+        let clock = FakeClock::new(NOW);
+        let mut svc = TimeService::new(clock.clone(), None);
+        svc.update(true);
+        clock.advance(500);
+        let persisted = svc.persisted().unwrap();
+        assert_eq!(persisted, PersistedTime { last_sync: NOW, last_known: NOW + 500 });
+        // next boot: the RTC kept the clock running
+        clock.advance(1000);
+        let restored = TimeService::new(clock, Some(persisted));
+        assert_eq!(restored.state(), SyncState::Restored);
+        assert_eq!(restored.last_sync(), Some(NOW));
+    }
+
+    #[test]
+    fn resync_after_timeout() {
+        let clock = FakeClock::new(NOW);
+        let mut svc = TimeService::new(clock.clone(), None);
+        svc.update(true);
+        clock.advance(RESYNC_TIMEOUT - 1);
+        assert!(!svc.update(false));
+        clock.advance(1);
+        assert!(svc.update(false));
+        // restarted: the next restart waits another timeout
+        assert!(!svc.update(false));
+        clock.advance(RESYNC_TIMEOUT - 1);
+        assert!(!svc.update(false));
+        clock.advance(1);
+        assert!(svc.update(false));
+    }
+
+    #[test]
+    fn resync_when_never_synced() {
+        let clock = FakeClock::new(0);
+        let mut svc = TimeService::new(clock.clone(), None);
+        clock.advance(RESYNC_TIMEOUT);
+        assert!(svc.update(false));
+        assert!(!svc.is_time_valid());
+    }
+
+    #[test]
+    fn sync_resets_resync_timer() {
+        let clock = FakeClock::new(NOW);
+        let mut svc = TimeService::new(clock.clone(), None);
+        clock.advance(RESYNC_TIMEOUT - 10);
+        assert!(!svc.update(true));
+        clock.advance(RESYNC_TIMEOUT - 1);
+        assert!(!svc.update(false));
+        clock.advance(1);
+        assert!(svc.update(false));
+    }
+}
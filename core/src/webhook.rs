@@ -0,0 +1,119 @@
+// Webhook payload and retry schedule
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::time::Duration;
+
+use crate::control::json_escape;
+
+pub const MAX_ATTEMPTS: u32 = 10;
+const RETRY_DELAY_MIN: u64 = 5;     // seconds, doubled on every failure
+const RETRY_DELAY_MAX: u64 = 300;
+
+/// Used when no template is configured.
+pub const DEFAULT_TEMPLATE: &str =
+    "{\"target\":\"{target}\",\"mac\":\"{mac}\",\"source\":\"{source}\",\"result\":\"{result}\",\"battery\":{battery},\"time\":{time}}";
+
+/// One wake attempt, as reported to the webhook.
+#[derive(Debug, Clone)]
+pub struct WakeEvent {
+    pub target: String,
+    pub mac: String,
+    pub source: String,
+    pub success: bool,
+    pub battery_voltage: f32,
+    pub time: u64,  // epoch seconds
+}
+
+/// Replace `{target}`, `{mac}`, `{source}`, `{result}`, `{battery}` and `{time}`.
+/// String values are JSON escaped, so the template may quote them.
+pub fn render_template(template: &str, event: &WakeEvent) -> String {
+    let template = if template.trim().is_empty() { DEFAULT_TEMPLATE } else { template };
+    template
+        .replace("{target}", &json_escape(&event.target))
+        .replace("{mac}", &json_escape(&event.mac))
+        .replace("{source}", &json_escape(&event.source))
+        .replace("{result}", if event.success { "ok" } else { "failed" })
+        .replace("{battery}", &format!("{:.2}", event.battery_voltage))
+        .replace("{time}", &event.time.to_string())
+}
+
+/// Retry delay after `attempts` failed deliveries.
+pub fn retry_delay(attempts: u32) -> Duration {
+    let secs = RETRY_DELAY_MIN.saturating_mul(1 << attempts.saturating_sub(1).min(16));
+    Duration::from_secs(secs.min(RETRY_DELAY_MAX))
+}
+
+/// Delay before the next delivery after `attempts` failures, or None to give up.
+pub fn retry_after(attempts: u32) -> Option<Duration> {
+    if attempts >= MAX_ATTEMPTS {
+        None
+    }
+    else {
+        Some(retry_delay(attempts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(target: &str, mac: &str, source: &str) -> WakeEvent {
+        WakeEvent {
+            target: target.to_string(),
+            mac: mac.to_string(),
+            source: source.to_string(),
+            success: true,
+            battery_voltage: 3.912,
+            time: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn default_template() {
+        let body = render_template("", &event("PC1", "00:11:22:33:44:55", "key"));
+        assert_eq!(body, "{\"target\":\"PC1\",\"mac\":\"00:11:22:33:44:55\",\"source\":\"key\",\
+            \"result\":\"ok\",\"battery\":3.91,\"time\":1700000000}");
+    }
+
+    #[test]
+    fn blank_template_uses_default() {
+        let e = event("PC1", "00:11:22:33:44:55", "key");
+        assert_eq!(render_template("  ", &e), render_template(DEFAULT_TEMPLATE, &e));
+    }
+
+    #[test]
+    fn strings_are_json_escaped() {
+        let e = event("my \"PC\"\\1", "aa\nbb", "http\t\u{1}");
+        let body = render_template("{\"t\":\"{target}\",\"m\":\"{mac}\",\"s\":\"{source}\"}", &e);
+        assert_eq!(body, "{\"t\":\"my \\\"PC\\\"\\\\1\",\"m\":\"aa\\nbb\",\"s\":\"http\\t\\u0001\"}");
+    }
+
+    #[test]
+    fn escaped_value_cannot_break_out() {
+        let e = event("x\",\"result\":\"ok", "m", "s");
+        let body = render_template("{\"target\":\"{target}\",\"result\":\"{result}\"}", &WakeEvent { success: false, ..e });
+        assert_eq!(body, "{\"target\":\"x\\\",\\\"result\\\":\\\"ok\",\"result\":\"failed\"}");
+    }
+
+    #[test]
+    fn retry_delay_grows() {
+        let delays: Vec<u64> = (1..=8).map(|n| retry_delay(n).as_secs()).collect();
+        assert_eq!(delays, [5, 10, 20, 40, 80, 160, 300, 300]);
+    }
+
+    #[test]
+    fn retry_delay_capped() {
+        assert_eq!(retry_delay(0), Duration::from_secs(5));
+        assert_eq!(retry_delay(30), Duration::from_secs(300));
+        assert_eq!(retry_delay(u32::MAX), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let sent = (1..).take_while(|&n| retry_after(n).is_some()).count() + 1;
+        assert_eq!(sent, 10);
+        assert_eq!(retry_after(MAX_ATTEMPTS - 1), Some(Duration::from_secs(300)));
+        assert_eq!(retry_after(MAX_ATTEMPTS), None);
+    }
+}
@@ -0,0 +1,333 @@
+// Wi-Fi reconnection state machine with exponential backoff
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::net::Ipv4Addr;
+
+use log::*;
+
+const BACKOFF_BASE_MS: u64 = 2000;
+const BACKOFF_MAX_MS: u64 = 300_000;
+const CONNECT_TIMEOUT_MS: u64 = 30_000;
+const DHCP_TIMEOUT_MS: u64 = 15_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WifiState {
+    Stopped,        // Wi-Fi stopped for sleep
+    Connecting,     // connect() issued, waiting for association
+    Associated,     // associated, waiting for DHCP
+    Connected,      // got IP
+    Backoff,        // waiting before the next attempt
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NetEvent {
+    StaConnected,
+    StaDisconnected(u16),   // reason code
+    GotIp(Ipv4Addr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WifiAction {
+    None,
+    Connect,
+    RestartDhcp,
+}
+
+/// Reconnection state machine. It only decides *what* to do; `WifiManager::poll`
+/// performs the actions on the driver.
+pub struct WifiStateMachine {
+    state: WifiState,
+    since_ms: u64,
+    retry: u32,
+    next_attempt_ms: u64,
+    dhcp_restarted: bool,
+    last_reason: Option<u16>,
+    ip_addr: Ipv4Addr,
+    connected_once: bool,
+    reconnect_count: u32,
+}
+
+impl WifiStateMachine {
+    pub fn new(now_ms: u64) -> WifiStateMachine {
+        WifiStateMachine {
+            state: WifiState::Connecting,
+            since_ms: now_ms,
+            retry: 0,
+            next_attempt_ms: now_ms,
+            dhcp_restarted: false,
+            last_reason: None,
+            ip_addr: Ipv4Addr::UNSPECIFIED,
+            connected_once: false,
+            reconnect_count: 0,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: NetEvent, now_ms: u64) {
+        if self.state == WifiState::Stopped {
+            return;
+        }
+        match event {
+            NetEvent::StaConnected => {
+                self.set_state(WifiState::Associated, now_ms);
+                self.dhcp_restarted = false;
+            },
+            NetEvent::StaDisconnected(reason) => {
+                self.last_reason = Some(reason);
+                self.ip_addr = Ipv4Addr::UNSPECIFIED;
+                self.start_backoff(now_ms);
+            },
+            NetEvent::GotIp(ip) => {
+                if self.state != WifiState::Connected && self.connected_once {
+                    self.reconnect_count += 1;
+                }
+                self.connected_once = true;
+                self.ip_addr = ip;
+                self.retry = 0;
+                self.last_reason = None;
+                self.set_state(WifiState::Connected, now_ms);
+            },
+        }
+    }
+
+    /// Next action to perform on the driver.
+    pub fn next_action(&mut self, now_ms: u64) -> WifiAction {
+        match self.state {
+            WifiState::Backoff => {
+                if now_ms >= self.next_attempt_ms {
+                    self.set_state(WifiState::Connecting, now_ms);
+                    return WifiAction::Connect;
+                }
+            },
+            WifiState::Connecting => {
+                if now_ms - self.since_ms >= CONNECT_TIMEOUT_MS {
+                    info!("[WIFI] Connect timeout");
+                    self.start_backoff(now_ms);
+                }
+            },
+            WifiState::Associated => {
+                if now_ms - self.since_ms >= DHCP_TIMEOUT_MS {
+                    if self.dhcp_restarted {
+                        info!("[WIFI] DHCP timeout");
+                        self.start_backoff(now_ms);
+                    }
+                    else {
+                        self.dhcp_restarted = true;
+                        self.since_ms = now_ms;
+                        return WifiAction::RestartDhcp;
+                    }
+                }
+            },
+            WifiState::Connected | WifiState::Stopped => {},
+        }
+        WifiAction::None
+    }
+
+    /// Wi-Fi is stopped (sleep). Events are ignored until `resume`.
+    pub fn suspend(&mut self, now_ms: u64) {
+        self.ip_addr = Ipv4Addr::UNSPECIFIED;
+        self.set_state(WifiState::Stopped, now_ms);
+    }
+
+    /// Wi-Fi may be started again; connect immediately.
+    pub fn resume(&mut self, now_ms: u64) {
+        self.retry = 0;
+        self.next_attempt_ms = now_ms;
+        self.set_state(WifiState::Backoff, now_ms);
+    }
+
+    pub fn get_state(&self) -> WifiState {
+        self.state
+    }
+
+    pub fn get_last_reason(&self) -> Option<u16> {
+        self.last_reason
+    }
+
+    pub fn get_ip_addr(&self) -> Ipv4Addr {
+        self.ip_addr
+    }
+
+    pub fn get_reconnect_count(&self) -> u32 {
+        self.reconnect_count
+    }
+
+    /// Delay before the next attempt after `retry` failures: 2 s, 4 s, 8 s ... up to 5 min.
+    pub fn backoff_delay_ms(retry: u32) -> u64 {
+        BACKOFF_BASE_MS.saturating_mul(1u64 << retry.min(16)).min(BACKOFF_MAX_MS)
+    }
+
+    fn start_backoff(&mut self, now_ms: u64) {
+        let delay = Self::backoff_delay_ms(self.retry);
+        self.retry = self.retry.saturating_add(1);
+        self.next_attempt_ms = now_ms + delay;
+        info!("[WIFI] Retry in {} ms (reason {:?})", delay, self.last_reason);
+        self.set_state(WifiState::Backoff, now_ms);
+    }
+
+    fn set_state(&mut self, state: WifiState, now_ms: u64) {
+        if self.state != state {
+            info!("[WIFI] {:?} -> {:?}", self.state, state);
+        }
+        self.state = state;
+        self.since_ms = now_ms;
+    }
+}
+
+/// Name of a `wifi_err_reason_t` value.
+pub fn disconnect_reason_name(reason: u16) -> &'static str {
+    match reason {
+        1 => "UNSPECIFIED",
+        2 => "AUTH_EXPIRE",
+        3 => "AUTH_LEAVE",
+        4 => "ASSOC_EXPIRE",
+        5 => "ASSOC_TOOMANY",
+        6 => "NOT_AUTHED",
+        7 => "NOT_ASSOCED",
+        8 => "ASSOC_LEAVE",
+        15 => "4WAY_TIMEOUT",
+        200 => "BEACON_TIMEOUT",
+        201 => "NO_AP_FOUND",
+        202 => "AUTH_FAIL",
+        203 => "ASSOC_FAIL",
+        204 => "HANDSHAKE_TIMEOUT",
+        205 => "CONNECTION_FAIL",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 50);
+
+    // Fail one attempt with `reason` and return the delay until the next Connect.
+    fn fail_attempt(sm: &mut WifiStateMachine, now: &mut u64, reason: u16) -> u64 {
+        sm.handle_event(NetEvent::StaDisconnected(reason), *now);
+        assert_eq!(sm.get_state(), WifiState::Backoff);
+        let start = *now;
+        while sm.next_action(*now) != WifiAction::Connect {
+            *now += 100;
+        }
+        assert_eq!(sm.get_state(), WifiState::Connecting);
+        *now - start
+    }
+
+    #[test]
+    fn backoff_sequence() {
+        let delays: Vec<u64> = (0..10).map(WifiStateMachine::backoff_delay_ms).collect();
+        assert_eq!(delays, [2000, 4000, 8000, 16000, 32000, 64000, 128000, 256000, 300000, 300000]);
+        assert_eq!(WifiStateMachine::backoff_delay_ms(u32::MAX), BACKOFF_MAX_MS);
+    }
+
+    #[test]
+    fn retries_follow_backoff() {
+        let mut now = 0;
+        let mut sm = WifiStateMachine::new(now);
+        let delays: Vec<u64> = (0..10).map(|_| fail_attempt(&mut sm, &mut now, 201)).collect();
+        assert_eq!(delays, [2000, 4000, 8000, 16000, 32000, 64000, 128000, 256000, 300000, 300000]);
+    }
+
+    #[test]
+    fn connect_resets_backoff() {
+        let mut now = 0;
+        let mut sm = WifiStateMachine::new(now);
+        for _ in 0..5 {
+            fail_attempt(&mut sm, &mut now, 201);
+        }
+        sm.handle_event(NetEvent::StaConnected, now);
+        // association alone does not count as connected
+        assert_eq!(fail_attempt(&mut sm, &mut now, 8), 64000);
+        sm.handle_event(NetEvent::StaConnected, now);
+        sm.handle_event(NetEvent::GotIp(IP), now);
+        assert_eq!(sm.get_state(), WifiState::Connected);
+        assert_eq!(sm.get_ip_addr(), IP);
+        assert_eq!(sm.get_last_reason(), None);
+        assert_eq!(fail_attempt(&mut sm, &mut now, 200), 2000);
+        assert_eq!(fail_attempt(&mut sm, &mut now, 200), 4000);
+    }
+
+    #[test]
+    fn disconnect_reason_leads_to_retry() {
+        // every reason is retried with backoff; the reason is kept for the Status screen
+        for reason in [0, 1, 2, 8, 15, 200, 201, 202, 203, 204, 205, 999] {
+            let mut now = 0;
+            let mut sm = WifiStateMachine::new(now);
+            sm.handle_event(NetEvent::GotIp(IP), now);
+            sm.handle_event(NetEvent::StaDisconnected(reason), now);
+            assert_eq!(sm.get_state(), WifiState::Backoff);
+            assert_eq!(sm.get_last_reason(), Some(reason));
+            assert_eq!(sm.get_ip_addr(), Ipv4Addr::UNSPECIFIED);
+            assert_eq!(sm.next_action(now + 1999), WifiAction::None);
+            now += 2000;
+            assert_eq!(sm.next_action(now), WifiAction::Connect);
+        }
+    }
+
+    #[test]
+    fn reason_names() {
+        assert_eq!(disconnect_reason_name(2), "AUTH_EXPIRE");
+        assert_eq!(disconnect_reason_name(15), "4WAY_TIMEOUT");
+        assert_eq!(disconnect_reason_name(200), "BEACON_TIMEOUT");
+        assert_eq!(disconnect_reason_name(201), "NO_AP_FOUND");
+        assert_eq!(disconnect_reason_name(202), "AUTH_FAIL");
+        assert_eq!(disconnect_reason_name(205), "CONNECTION_FAIL");
+        assert_eq!(disconnect_reason_name(0), "");
+        assert_eq!(disconnect_reason_name(999), "");
+    }
+
+    #[test]
+    fn connect_timeout_backs_off() {
+        let mut sm = WifiStateMachine::new(0);
+        assert_eq!(sm.next_action(CONNECT_TIMEOUT_MS - 1), WifiAction::None);
+        assert_eq!(sm.get_state(), WifiState::Connecting);
+        assert_eq!(sm.next_action(CONNECT_TIMEOUT_MS), WifiAction::None);
+        assert_eq!(sm.get_state(), WifiState::Backoff);
+        assert_eq!(sm.next_action(CONNECT_TIMEOUT_MS + 2000), WifiAction::Connect);
+    }
+
+    #[test]
+    fn dhcp_restarted_once_then_backs_off() {
+        let mut sm = WifiStateMachine::new(0);
+        sm.handle_event(NetEvent::StaConnected, 0);
+        assert_eq!(sm.get_state(), WifiState::Associated);
+        assert_eq!(sm.next_action(DHCP_TIMEOUT_MS - 1), WifiAction::None);
+        assert_eq!(sm.next_action(DHCP_TIMEOUT_MS), WifiAction::RestartDhcp);
+        assert_eq!(sm.next_action(2 * DHCP_TIMEOUT_MS - 1), WifiAction::None);
+        assert_eq!(sm.next_action(2 * DHCP_TIMEOUT_MS), WifiAction::None);
+        assert_eq!(sm.get_state(), WifiState::Backoff);
+    }
+
+    #[test]
+    fn reconnect_count() {
+        let mut sm = WifiStateMachine::new(0);
+        sm.handle_event(NetEvent::GotIp(IP), 0);
+        assert_eq!(sm.get_reconnect_count(), 0);
+        // DHCP renewal while connected is not a reconnect
+        sm.handle_event(NetEvent::GotIp(IP), 10);
+        assert_eq!(sm.get_reconnect_count(), 0);
+        sm.handle_event(NetEvent::StaDisconnected(200), 20);
+        sm.handle_event(NetEvent::GotIp(IP), 30);
+        assert_eq!(sm.get_reconnect_count(), 1);
+    }
+
+    #[test]
+    fn suspend_and_resume() {
+        let mut sm = WifiStateMachine::new(0);
+        for n in 0..4 {
+            sm.handle_event(NetEvent::StaDisconnected(201), n * 1000);
+        }
+        sm.suspend(5000);
+        assert_eq!(sm.get_state(), WifiState::Stopped);
+        // events while stopped are ignored
+        sm.handle_event(NetEvent::StaDisconnected(8), 5100);
+        assert_eq!(sm.get_state(), WifiState::Stopped);
+        assert_eq!(sm.next_action(600_000), WifiAction::None);
+        sm.resume(600_000);
+        assert_eq!(sm.next_action(600_000), WifiAction::Connect);
+        sm.handle_event(NetEvent::StaDisconnected(201), 600_100);
+        assert_eq!(sm.next_action(602_099), WifiAction::None);
+        assert_eq!(sm.next_action(602_100), WifiAction::Connect);
+    }
+}
@@ -1,14 +1,28 @@
 use config::{File, FileFormat, Config as NvsConfig};
 use std::collections::HashMap;
 
+use crate::buttons::BINDINGS;
+
 #[toml_cfg::toml_config]
 pub struct Config {
     #[default("")]
     wifi_ssid: &'static str,
     #[default("")]
     wifi_psk: &'static str,
+    #[default("psk")]
+    wifi_auth: &'static str,    // psk or eap (WPA2-Enterprise)
+    #[default("")]
+    eap_identity: &'static str, // outer identity, empty: same as eap_username
+    #[default("")]
+    eap_username: &'static str,
+    #[default("")]
+    eap_password: &'static str,
+    #[default("")]
+    eap_ca_cert: &'static str,  // PEM file in the storage partition, e.g. /storage/ca.pem
     #[default("false")]
     wps_enable: &'static str,
+    #[default("pbc")]
+    wps_mode: &'static str,     // pbc or pin
     #[default("")]
     target_mac_address1: &'static str,
     #[default("")]
@@ -25,11 +39,73 @@ pub struct Config {
     sleep_mode: &'static str,   // light or deep
     #[default("30")]
     display_off_time: &'static str, // 0: always on, 1-: display off time in seconds
+    #[default("Default")]
+    profile_name: &'static str, // name of the first profile
+    #[default("")]
+    wake_groups: &'static str,  // e.g. "office:PC1+PC2,lab:PC3"
+    #[default("min")]
+    wifi_power_save: &'static str,  // none, min or max (modem sleep)
+    #[default("3")]
+    wifi_listen_interval: &'static str, // beacon intervals between wake-ups in max power save
+    #[default("0")]
+    wifi_max_tx_power: &'static str,    // dBm 2-20, 0: driver default
+    #[default("")]
+    hostname: &'static str,     // DHCP/mDNS hostname, empty: wakepcbox-xxxxxx
+    #[default("")]
+    ota_url: &'static str,      // firmware image URL, e.g. http://192.168.1.10/wakepcbox.bin
+    #[default("800")]
+    long_press_time: &'static str,  // ms
+    #[default("300")]
+    double_press_time: &'static str, // ms, 0: no double press
+    #[default("150")]
+    chord_time: &'static str,       // ms between the two key downs
+    #[default("")]
+    syslog_server: &'static str,    // host or host:port (UDP 514), empty: disabled
+    #[default("info")]
+    syslog_level: &'static str,     // error, warn, info or debug
+    #[default("")]
+    shell_password: &'static str,   // TCP command shell, empty: disabled
+    #[default("")]
+    influx_server: &'static str,    // host or host:port (UDP 8089), empty: disabled
+    #[default("60")]
+    influx_interval: &'static str,  // seconds
+    #[default("")]
+    wake_secret: &'static str,      // signed UDP wake, empty: disabled
+    #[default("")]
+    webhook_url: &'static str,      // called after each wake, empty: disabled
+    #[default("POST")]
+    webhook_method: &'static str,   // POST, PUT or GET
+    #[default("")]
+    webhook_template: &'static str, // JSON body, empty: built-in template
+    #[default("off")]
+    pin_lock: &'static str,         // PIN required for: off, menu, wake or both
+    #[default("")]
+    pin_targets: &'static str,      // targets/groups needing the PIN to wake, e.g. "PC1+lab", empty: all
+    #[default("true")]
+    http_api_enable: &'static str,
+    #[default("")]
+    api_token: &'static str,        // needed for HTTP API requests that change something, empty: read only
+    #[default("")]
+    mqtt_url: &'static str,     // e.g. mqtt://192.168.1.10:1883, empty: MQTT disabled
+    #[default("")]
+    mqtt_username: &'static str,
+    #[default("")]
+    mqtt_password: &'static str,
+    #[default("wakepcbox")]
+    mqtt_topic: &'static str,   // base topic
 }
 
+pub const NUM_OF_PROFILES: usize = 3;
+
 const MENU_SSID: &str = "SSID";
 const MENU_PSK: &str = "PSK";
+const MENU_WIFIAUTH: &str = "WIFIAUTH";
+const MENU_EAPID: &str = "EAPID";
+const MENU_EAPUSER: &str = "EAPUSER";
+const MENU_EAPPASS: &str = "EAPPASS";
+const MENU_EAPCACERT: &str = "EAPCACERT";
 const MENU_WPS: &str = "WPS";
+const MENU_WPSMODE: &str = "WPSMODE";
 const MENU_PC1: &str = "PC1";
 const MENU_PC2: &str = "PC2";
 const MENU_PC3: &str = "PC3";
@@ -38,12 +114,54 @@ const MENU_TIMEZONE: &str = "TIMEZONE";
 const MENU_IDLESLEEP: &str = "IDLESLEEP";
 const MENU_SLEEPMODE: &str = "SLEEPMODE";
 const MENU_DISPLAYOFFTIME: &str = "DISPLAYOFFTIME";
+const MENU_PROFILE: &str = "PROFILE";
+const MENU_PROFILENAME: &str = "PROFILENAME";
+const MENU_GROUPS: &str = "GROUPS";
+const MENU_WIFIPS: &str = "WIFIPS";
+const MENU_WIFILISTEN: &str = "WIFILISTEN";
+const MENU_WIFITXPOWER: &str = "WIFITXPOWER";
+const MENU_HOSTNAME: &str = "HOSTNAME";
+const MENU_OTAURL: &str = "OTAURL";
+const MENU_LONGPRESS: &str = "LONGPRESS";
+const MENU_DOUBLEPRESS: &str = "DOUBLEPRESS";
+const MENU_CHORDTIME: &str = "CHORDTIME";
+const MENU_SYSLOGSERVER: &str = "SYSLOGSERVER";
+const MENU_SYSLOGLEVEL: &str = "SYSLOGLEVEL";
+const MENU_SHELLPASS: &str = "SHELLPASS";
+const MENU_INFLUXSERVER: &str = "INFLUXSERVER";
+const MENU_INFLUXINTERVAL: &str = "INFLUXINTERVAL";
+const MENU_WAKESECRET: &str = "WAKESECRET";
+const MENU_WEBHOOKURL: &str = "WEBHOOKURL";
+const MENU_WEBHOOKMETHOD: &str = "WEBHOOKMETHOD";
+const MENU_WEBHOOKBODY: &str = "WEBHOOKBODY";
+const MENU_PINLOCK: &str = "PINLOCK";
+const MENU_PINTARGETS: &str = "PINTARGETS";
+const MENU_PINHASH: &str = "PINHASH";
+const MENU_HTTPAPI: &str = "HTTPAPI";
+const MENU_APITOKEN: &str = "APITOKEN";
+const MENU_MQTTURL: &str = "MQTTURL";
+const MENU_MQTTUSER: &str = "MQTTUSER";
+const MENU_MQTTPASS: &str = "MQTTPASS";
+const MENU_MQTTTOPIC: &str = "MQTTTOPIC";
+
+// Keys stored per profile. All other keys are shared by every profile.
+const PROFILE_KEYS: [&str; 16] = [
+    MENU_PROFILENAME, MENU_SSID, MENU_PSK, MENU_WIFIAUTH, MENU_EAPID, MENU_EAPUSER, MENU_EAPPASS, MENU_EAPCACERT,
+    MENU_WPS, MENU_WPSMODE,
+    MENU_PC1, MENU_PC2, MENU_PC3, MENU_PC4, MENU_TIMEZONE, MENU_GROUPS,
+];
 
 #[derive(Debug)]
 pub struct ConfigData {
     pub wifi_ssid: String,
     pub wifi_psk: String,
+    pub wifi_auth: String,
+    pub eap_identity: String,
+    pub eap_username: String,
+    pub eap_password: String,
+    pub eap_ca_cert: String,
     pub wps_enable: bool,
+    pub wps_mode: String,
     pub target_mac_address1: String,
     pub target_mac_address2: String,
     pub target_mac_address3: String,
@@ -52,6 +170,37 @@ pub struct ConfigData {
     pub idle_in_sleep_time: u32,
     pub sleep_mode: String,
     pub display_off_time: u32,
+    pub profile_index: usize,
+    pub profile_name: String,
+    pub profile_names: Vec<String>,
+    pub wake_groups: String,
+    pub wifi_power_save: String,
+    pub wifi_listen_interval: u16,
+    pub wifi_max_tx_power: u8,
+    pub hostname: String,
+    pub ota_url: String,
+    pub long_press_time: u64,
+    pub double_press_time: u64,
+    pub chord_time: u64,
+    pub syslog_server: String,
+    pub syslog_level: String,
+    pub shell_password: String,
+    pub influx_server: String,
+    pub influx_interval: u64,
+    pub wake_secret: String,
+    pub webhook_url: String,
+    pub webhook_method: String,
+    pub webhook_template: String,
+    pub pin_lock: String,
+    pub pin_targets: String,
+    pub pin_hash: String,       // salted hash, see `pinlock`
+    pub http_api_enable: bool,
+    pub api_token: String,
+    pub mqtt_url: String,
+    pub mqtt_username: String,
+    pub mqtt_password: String,
+    pub mqtt_topic: String,
+    pub button_actions: Vec<(String, String)>,  // (binding key, action), see `buttons`
 }
 
 impl ConfigData {
@@ -59,7 +208,13 @@ impl ConfigData {
         ConfigData {
             wifi_ssid: String::new(),
             wifi_psk: String::new(),
+            wifi_auth: String::from("psk"),
+            eap_identity: String::new(),
+            eap_username: String::new(),
+            eap_password: String::new(),
+            eap_ca_cert: String::new(),
             wps_enable: false,
+            wps_mode: String::from("pbc"),
             target_mac_address1: String::new(),
             target_mac_address2: String::new(),
             target_mac_address3: String::new(),
@@ -68,63 +223,312 @@ impl ConfigData {
             idle_in_sleep_time: 30,
             sleep_mode: String::from("light"),
             display_off_time: 30,
+            profile_index: 0,
+            profile_name: String::new(),
+            profile_names: (0..NUM_OF_PROFILES).map(|i| default_profile_name(i)).collect(),
+            wake_groups: String::new(),
+            wifi_power_save: String::from("min"),
+            wifi_listen_interval: 3,
+            wifi_max_tx_power: 0,
+            hostname: String::new(),
+            ota_url: String::new(),
+            long_press_time: 800,
+            double_press_time: 300,
+            chord_time: 150,
+            syslog_server: String::new(),
+            syslog_level: String::from("info"),
+            shell_password: String::new(),
+            influx_server: String::new(),
+            influx_interval: 60,
+            wake_secret: String::new(),
+            webhook_url: String::new(),
+            webhook_method: String::from("POST"),
+            webhook_template: String::new(),
+            pin_lock: String::from("off"),
+            pin_targets: String::new(),
+            pin_hash: String::new(),
+            http_api_enable: true,
+            api_token: String::new(),
+            mqtt_url: String::new(),
+            mqtt_username: String::new(),
+            mqtt_password: String::new(),
+            mqtt_topic: String::from("wakepcbox"),
+            button_actions: BINDINGS.iter().map(|b| (b.key.to_string(), b.default.to_string())).collect(),
         }
     }
+    /// Load the shared settings from the `config` NVS entry.
+    /// Profile keys found here (written by older firmware) are loaded too, so
+    /// they can be migrated into the first profile.
     pub fn load_config(&mut self, nvs_value: Option<&str>) -> anyhow::Result<()> {
         if nvs_value == None {
             return Err(anyhow::Error::msg("nvs_value is None"));
         }
-        let settings = NvsConfig::builder()
-        .add_source(File::from_str(&nvs_value.unwrap(), FileFormat::Toml))
-        .build()?;
-        let settings_map = settings.try_deserialize::<HashMap<String, String>>()?;
+        let settings_map = parse_settings(nvs_value.unwrap())?;
+        self.idle_in_sleep_time = settings_map.get(MENU_IDLESLEEP).ok_or(anyhow::Error::msg("idle_in_sleep_time not found"))?.parse::<u32>()?;
+        self.sleep_mode = settings_map.get(MENU_SLEEPMODE).ok_or(anyhow::Error::msg("sleep_mode not found"))?.to_string();
+        self.display_off_time = settings_map.get(MENU_DISPLAYOFFTIME).ok_or(anyhow::Error::msg("display_off_time not found"))?.parse::<u32>()?;
+        self.profile_index = settings_map.get(MENU_PROFILE).map(|v| parse_profile_index(v)).unwrap_or(0);
+        self.wifi_power_save = settings_map.get(MENU_WIFIPS).cloned().unwrap_or(String::from("min"));
+        self.wifi_listen_interval = settings_map.get(MENU_WIFILISTEN).and_then(|v| v.parse::<u16>().ok()).unwrap_or(3);
+        self.wifi_max_tx_power = settings_map.get(MENU_WIFITXPOWER).and_then(|v| v.parse::<u8>().ok()).unwrap_or(0);
+        self.hostname = settings_map.get(MENU_HOSTNAME).cloned().unwrap_or_default();
+        self.ota_url = settings_map.get(MENU_OTAURL).cloned().unwrap_or_default();
+        self.long_press_time = settings_map.get(MENU_LONGPRESS).and_then(|v| v.parse::<u64>().ok()).unwrap_or(800);
+        self.double_press_time = settings_map.get(MENU_DOUBLEPRESS).and_then(|v| v.parse::<u64>().ok()).unwrap_or(300);
+        self.chord_time = settings_map.get(MENU_CHORDTIME).and_then(|v| v.parse::<u64>().ok()).unwrap_or(150);
+        self.syslog_server = settings_map.get(MENU_SYSLOGSERVER).cloned().unwrap_or_default();
+        self.syslog_level = settings_map.get(MENU_SYSLOGLEVEL).cloned().unwrap_or(String::from("info"));
+        self.shell_password = settings_map.get(MENU_SHELLPASS).cloned().unwrap_or_default();
+        self.influx_server = settings_map.get(MENU_INFLUXSERVER).cloned().unwrap_or_default();
+        self.influx_interval = settings_map.get(MENU_INFLUXINTERVAL).and_then(|v| v.parse::<u64>().ok()).unwrap_or(60);
+        self.wake_secret = settings_map.get(MENU_WAKESECRET).cloned().unwrap_or_default();
+        self.webhook_url = settings_map.get(MENU_WEBHOOKURL).cloned().unwrap_or_default();
+        self.webhook_method = settings_map.get(MENU_WEBHOOKMETHOD).cloned().unwrap_or(String::from("POST"));
+        self.webhook_template = settings_map.get(MENU_WEBHOOKBODY).cloned().unwrap_or_default();
+        self.pin_lock = settings_map.get(MENU_PINLOCK).cloned().unwrap_or(String::from("off"));
+        self.pin_targets = settings_map.get(MENU_PINTARGETS).cloned().unwrap_or_default();
+        self.pin_hash = settings_map.get(MENU_PINHASH).cloned().unwrap_or_default();
+        self.http_api_enable = settings_map.get(MENU_HTTPAPI).map(|v| v == "true").unwrap_or(true);
+        self.api_token = settings_map.get(MENU_APITOKEN).cloned().unwrap_or_default();
+        self.mqtt_url = settings_map.get(MENU_MQTTURL).cloned().unwrap_or_default();
+        self.mqtt_username = settings_map.get(MENU_MQTTUSER).cloned().unwrap_or_default();
+        self.mqtt_password = settings_map.get(MENU_MQTTPASS).cloned().unwrap_or_default();
+        self.mqtt_topic = settings_map.get(MENU_MQTTTOPIC).cloned().unwrap_or(String::from("wakepcbox"));
+        self.button_actions = BINDINGS.iter()
+            .map(|b| (b.key.to_string(), settings_map.get(b.key).cloned().unwrap_or(b.default.to_string())))
+            .collect();
+        if settings_map.contains_key(MENU_SSID) {
+            self.load_profile_map(&settings_map)?;
+        }
+        Ok(())
+    }
+
+    /// Load the settings of the active profile from its `profileN` NVS entry.
+    pub fn load_profile(&mut self, nvs_value: Option<&str>) -> anyhow::Result<()> {
+        if nvs_value == None {
+            return Err(anyhow::Error::msg("nvs_value is None"));
+        }
+        let settings_map = parse_settings(nvs_value.unwrap())?;
+        self.load_profile_map(&settings_map)
+    }
+
+    fn load_profile_map(&mut self, settings_map: &HashMap<String, String>) -> anyhow::Result<()> {
+        self.profile_name = settings_map.get(MENU_PROFILENAME).cloned().unwrap_or(default_profile_name(self.profile_index));
         self.wifi_ssid = settings_map.get(MENU_SSID).ok_or(anyhow::Error::msg("wifi_ssid not found"))?.to_string();
         self.wifi_psk = settings_map.get(MENU_PSK).ok_or(anyhow::Error::msg("wifi_psk not found"))?.to_string();
+        self.wifi_auth = settings_map.get(MENU_WIFIAUTH).cloned().unwrap_or(String::from("psk"));
+        self.eap_identity = settings_map.get(MENU_EAPID).cloned().unwrap_or_default();
+        self.eap_username = settings_map.get(MENU_EAPUSER).cloned().unwrap_or_default();
+        self.eap_password = settings_map.get(MENU_EAPPASS).cloned().unwrap_or_default();
+        self.eap_ca_cert = settings_map.get(MENU_EAPCACERT).cloned().unwrap_or_default();
         self.wps_enable = settings_map.get(MENU_WPS).map(|v| v == "true").unwrap_or(false);
+        self.wps_mode = settings_map.get(MENU_WPSMODE).cloned().unwrap_or(String::from("pbc"));
         self.target_mac_address1 = settings_map.get(MENU_PC1).ok_or(anyhow::Error::msg("target_mac_address1 not found"))?.to_string();
         self.target_mac_address2 = settings_map.get(MENU_PC2).ok_or(anyhow::Error::msg("target_mac_address2 not found"))?.to_string();
         self.target_mac_address3 = settings_map.get(MENU_PC3).ok_or(anyhow::Error::msg("target_mac_address3 not found"))?.to_string();
         self.target_mac_address4 = settings_map.get(MENU_PC4).ok_or(anyhow::Error::msg("target_mac_address4 not found"))?.to_string();
         self.timezone_offset = settings_map.get(MENU_TIMEZONE).ok_or(anyhow::Error::msg("timezone_offset not found"))?.parse::<i32>()?;
-        self.idle_in_sleep_time = settings_map.get(MENU_IDLESLEEP).ok_or(anyhow::Error::msg("idle_in_sleep_time not found"))?.parse::<u32>()?;
-        self.sleep_mode = settings_map.get(MENU_SLEEPMODE).ok_or(anyhow::Error::msg("sleep_mode not found"))?.to_string();
-        self.display_off_time = settings_map.get(MENU_DISPLAYOFFTIME).ok_or(anyhow::Error::msg("display_off_time not found"))?.parse::<u32>()?;
+        self.wake_groups = settings_map.get(MENU_GROUPS).cloned().unwrap_or_default();
+        if self.profile_index < NUM_OF_PROFILES {
+            self.profile_names[self.profile_index] = self.profile_name.clone();
+        }
         Ok(())
     }
-    
+
+    /// Remember the name of another profile so it can be listed in the menu.
+    pub fn set_profile_name(&mut self, index: usize, nvs_value: Option<&str>) {
+        if index >= NUM_OF_PROFILES {
+            return;
+        }
+        if let Some(value) = nvs_value {
+            if let Ok(settings_map) = parse_settings(value) {
+                if let Some(name) = settings_map.get(MENU_PROFILENAME) {
+                    self.profile_names[index] = name.clone();
+                }
+            }
+        }
+    }
+
+    /// Items for the profile select menu, e.g. `1:Home`.
+    pub fn profile_select_items(&self) -> Vec<String> {
+        self.profile_names.iter().enumerate().map(|(i, name)| format!("{}:{}", i + 1, name)).collect()
+    }
+
+    pub fn profile_select_value(&self) -> String {
+        format!("{}:{}", self.profile_index + 1, self.profile_names[self.profile_index])
+    }
+
     /// Serialize the **current** struct values to a list of (key, value) pairs.
     /// Use this (not `set_default_config`) when you want to persist runtime state such
     /// as WPS-obtained credentials.
     pub fn to_config_entries(&self) -> Vec<(String, String)> {
-        vec![
+        let mut entries = vec![
+            (MENU_PROFILENAME.to_string(),   self.profile_name.clone()),
             (MENU_SSID.to_string(),          self.wifi_ssid.clone()),
             (MENU_PSK.to_string(),           self.wifi_psk.clone()),
+            (MENU_WIFIAUTH.to_string(),      self.wifi_auth.clone()),
+            (MENU_EAPID.to_string(),         self.eap_identity.clone()),
+            (MENU_EAPUSER.to_string(),       self.eap_username.clone()),
+            (MENU_EAPPASS.to_string(),       self.eap_password.clone()),
+            (MENU_EAPCACERT.to_string(),     self.eap_ca_cert.clone()),
             (MENU_WPS.to_string(),           self.wps_enable.to_string()),
+            (MENU_WPSMODE.to_string(),       self.wps_mode.clone()),
             (MENU_PC1.to_string(),           self.target_mac_address1.clone()),
             (MENU_PC2.to_string(),           self.target_mac_address2.clone()),
             (MENU_PC3.to_string(),           self.target_mac_address3.clone()),
             (MENU_PC4.to_string(),           self.target_mac_address4.clone()),
             (MENU_TIMEZONE.to_string(),      self.timezone_offset.to_string()),
+            (MENU_GROUPS.to_string(),        self.wake_groups.clone()),
             (MENU_IDLESLEEP.to_string(),     self.idle_in_sleep_time.to_string()),
             (MENU_SLEEPMODE.to_string(),     self.sleep_mode.clone()),
             (MENU_DISPLAYOFFTIME.to_string(),self.display_off_time.to_string()),
-        ]
+            (MENU_PROFILE.to_string(),       (self.profile_index + 1).to_string()),
+            (MENU_WIFIPS.to_string(),        self.wifi_power_save.clone()),
+            (MENU_WIFILISTEN.to_string(),    self.wifi_listen_interval.to_string()),
+            (MENU_WIFITXPOWER.to_string(),   self.wifi_max_tx_power.to_string()),
+            (MENU_HOSTNAME.to_string(),      self.hostname.clone()),
+            (MENU_OTAURL.to_string(),        self.ota_url.clone()),
+            (MENU_LONGPRESS.to_string(),     self.long_press_time.to_string()),
+            (MENU_DOUBLEPRESS.to_string(),   self.double_press_time.to_string()),
+            (MENU_CHORDTIME.to_string(),     self.chord_time.to_string()),
+            (MENU_SYSLOGSERVER.to_string(),  self.syslog_server.clone()),
+            (MENU_SYSLOGLEVEL.to_string(),   self.syslog_level.clone()),
+            (MENU_SHELLPASS.to_string(),     self.shell_password.clone()),
+            (MENU_INFLUXSERVER.to_string(),  self.influx_server.clone()),
+            (MENU_INFLUXINTERVAL.to_string(), self.influx_interval.to_string()),
+            (MENU_WAKESECRET.to_string(),    self.wake_secret.clone()),
+            (MENU_WEBHOOKURL.to_string(),    self.webhook_url.clone()),
+            (MENU_WEBHOOKMETHOD.to_string(), self.webhook_method.clone()),
+            (MENU_WEBHOOKBODY.to_string(),   self.webhook_template.clone()),
+            (MENU_PINLOCK.to_string(),       self.pin_lock.clone()),
+            (MENU_PINTARGETS.to_string(),    self.pin_targets.clone()),
+            (MENU_PINHASH.to_string(),       self.pin_hash.clone()),
+            (MENU_HTTPAPI.to_string(),       self.http_api_enable.to_string()),
+            (MENU_APITOKEN.to_string(),      self.api_token.clone()),
+            (MENU_MQTTURL.to_string(),       self.mqtt_url.clone()),
+            (MENU_MQTTUSER.to_string(),      self.mqtt_username.clone()),
+            (MENU_MQTTPASS.to_string(),      self.mqtt_password.clone()),
+            (MENU_MQTTTOPIC.to_string(),     self.mqtt_topic.clone()),
+        ];
+        entries.extend(self.button_actions.iter().cloned());
+        entries
     }
 
     pub fn set_default_config(&self) -> Vec::<(String, String)> {
         let mut default_config = Vec::<(String, String)>::new();
+        default_config.push((MENU_PROFILENAME.to_string(), CONFIG.profile_name.to_string()));
         default_config.push((MENU_SSID.to_string(), CONFIG.wifi_ssid.to_string()));
         default_config.push((MENU_PSK.to_string(),  CONFIG.wifi_psk.to_string()));
+        default_config.push((MENU_WIFIAUTH.to_string(), CONFIG.wifi_auth.to_string()));
+        default_config.push((MENU_EAPID.to_string(), CONFIG.eap_identity.to_string()));
+        default_config.push((MENU_EAPUSER.to_string(), CONFIG.eap_username.to_string()));
+        default_config.push((MENU_EAPPASS.to_string(), CONFIG.eap_password.to_string()));
+        default_config.push((MENU_EAPCACERT.to_string(), CONFIG.eap_ca_cert.to_string()));
         default_config.push((MENU_WPS.to_string(), CONFIG.wps_enable.to_string()));
+        default_config.push((MENU_WPSMODE.to_string(), CONFIG.wps_mode.to_string()));
         default_config.push((MENU_PC1.to_string(), CONFIG.target_mac_address1.to_string()));
         default_config.push((MENU_PC2.to_string(), CONFIG.target_mac_address2.to_string()));
         default_config.push((MENU_PC3.to_string(), CONFIG.target_mac_address3.to_string()));
         default_config.push((MENU_PC4.to_string(), CONFIG.target_mac_address4.to_string()));
         default_config.push((MENU_TIMEZONE.to_string(), CONFIG.timezone_offset.to_string()));
+        default_config.push((MENU_GROUPS.to_string(), CONFIG.wake_groups.to_string()));
         default_config.push((MENU_IDLESLEEP.to_string(), CONFIG.idle_in_sleep_time.to_string()));
         default_config.push((MENU_SLEEPMODE.to_string(), CONFIG.sleep_mode.to_string()));
         default_config.push((MENU_DISPLAYOFFTIME.to_string(), CONFIG.display_off_time.to_string()));
+        default_config.push((MENU_PROFILE.to_string(), "1".to_string()));
+        default_config.push((MENU_WIFIPS.to_string(), CONFIG.wifi_power_save.to_string()));
+        default_config.push((MENU_WIFILISTEN.to_string(), CONFIG.wifi_listen_interval.to_string()));
+        default_config.push((MENU_WIFITXPOWER.to_string(), CONFIG.wifi_max_tx_power.to_string()));
+        default_config.push((MENU_HOSTNAME.to_string(), CONFIG.hostname.to_string()));
+        default_config.push((MENU_OTAURL.to_string(), CONFIG.ota_url.to_string()));
+        default_config.push((MENU_LONGPRESS.to_string(), CONFIG.long_press_time.to_string()));
+        default_config.push((MENU_DOUBLEPRESS.to_string(), CONFIG.double_press_time.to_string()));
+        default_config.push((MENU_CHORDTIME.to_string(), CONFIG.chord_time.to_string()));
+        default_config.push((MENU_SYSLOGSERVER.to_string(), CONFIG.syslog_server.to_string()));
+        default_config.push((MENU_SYSLOGLEVEL.to_string(), CONFIG.syslog_level.to_string()));
+        default_config.push((MENU_SHELLPASS.to_string(), CONFIG.shell_password.to_string()));
+        default_config.push((MENU_INFLUXSERVER.to_string(), CONFIG.influx_server.to_string()));
+        default_config.push((MENU_INFLUXINTERVAL.to_string(), CONFIG.influx_interval.to_string()));
+        default_config.push((MENU_WAKESECRET.to_string(), CONFIG.wake_secret.to_string()));
+        default_config.push((MENU_WEBHOOKURL.to_string(), CONFIG.webhook_url.to_string()));
+        default_config.push((MENU_WEBHOOKMETHOD.to_string(), CONFIG.webhook_method.to_string()));
+        default_config.push((MENU_WEBHOOKBODY.to_string(), CONFIG.webhook_template.to_string()));
+        default_config.push((MENU_PINLOCK.to_string(), CONFIG.pin_lock.to_string()));
+        default_config.push((MENU_PINTARGETS.to_string(), CONFIG.pin_targets.to_string()));
+        default_config.push((MENU_PINHASH.to_string(), String::new()));
+        default_config.push((MENU_HTTPAPI.to_string(), CONFIG.http_api_enable.to_string()));
+        default_config.push((MENU_APITOKEN.to_string(), CONFIG.api_token.to_string()));
+        default_config.push((MENU_MQTTURL.to_string(), CONFIG.mqtt_url.to_string()));
+        default_config.push((MENU_MQTTUSER.to_string(), CONFIG.mqtt_username.to_string()));
+        default_config.push((MENU_MQTTPASS.to_string(), CONFIG.mqtt_password.to_string()));
+        default_config.push((MENU_MQTTTOPIC.to_string(), CONFIG.mqtt_topic.to_string()));
+        for b in BINDINGS.iter() {
+            default_config.push((b.key.to_string(), b.default.to_string()));
+        }
+        default_config
+    }
+
+    /// Default settings for a profile that has never been saved.
+    /// The first profile takes its values from `cfg.toml`, the others start empty.
+    pub fn default_profile_config(&self, index: usize) -> Vec::<(String, String)> {
+        if index == 0 {
+            return split_profile_entries(&self.set_default_config()).1;
+        }
+        let mut default_config = Vec::<(String, String)>::new();
+        default_config.push((MENU_PROFILENAME.to_string(), default_profile_name(index)));
+        default_config.push((MENU_SSID.to_string(), String::new()));
+        default_config.push((MENU_PSK.to_string(), String::new()));
+        default_config.push((MENU_WIFIAUTH.to_string(), "psk".to_string()));
+        default_config.push((MENU_EAPID.to_string(), String::new()));
+        default_config.push((MENU_EAPUSER.to_string(), String::new()));
+        default_config.push((MENU_EAPPASS.to_string(), String::new()));
+        default_config.push((MENU_EAPCACERT.to_string(), String::new()));
+        default_config.push((MENU_WPS.to_string(), "false".to_string()));
+        default_config.push((MENU_WPSMODE.to_string(), "pbc".to_string()));
+        default_config.push((MENU_PC1.to_string(), "00:00:00:00:00:00".to_string()));
+        default_config.push((MENU_PC2.to_string(), "00:00:00:00:00:00".to_string()));
+        default_config.push((MENU_PC3.to_string(), "00:00:00:00:00:00".to_string()));
+        default_config.push((MENU_PC4.to_string(), "00:00:00:00:00:00".to_string()));
+        default_config.push((MENU_TIMEZONE.to_string(), CONFIG.timezone_offset.to_string()));
+        default_config.push((MENU_GROUPS.to_string(), String::new()));
         default_config
-    }    
+    }
+}
+
+/// NVS key holding the settings of profile `index` (0 based).
+pub fn profile_nvs_key(index: usize) -> String {
+    format!("profile{}", index + 1)
+}
+
+/// Split (key, value) pairs into shared settings and per-profile settings.
+pub fn split_profile_entries(keyval: &Vec<(String, String)>) -> (Vec<(String, String)>, Vec<(String, String)>) {
+    keyval.iter().cloned().partition(|it| !PROFILE_KEYS.contains(&it.0.as_str()))
+}
+
+/// Profile index selected in a (key, value) list, if it contains one.
+pub fn selected_profile_index(keyval: &Vec<(String, String)>) -> Option<usize> {
+    keyval.iter().find(|it| it.0 == MENU_PROFILE).map(|it| parse_profile_index(&it.1))
+}
+
+// "2" or "2:Office" -> 1
+fn parse_profile_index(value: &str) -> usize {
+    let index = value.split(':').next().unwrap_or("1").trim().parse::<usize>().unwrap_or(1);
+    if index == 0 || index > NUM_OF_PROFILES { 0 } else { index - 1 }
+}
+
+fn default_profile_name(index: usize) -> String {
+    if index == 0 {
+        CONFIG.profile_name.to_string()
+    }
+    else {
+        format!("Profile{}", index + 1)
+    }
+}
+
+fn parse_settings(nvs_value: &str) -> anyhow::Result<HashMap<String, String>> {
+    let settings = NvsConfig::builder()
+    .add_source(File::from_str(nvs_value, FileFormat::Toml))
+    .build()?;
+    Ok(settings.try_deserialize::<HashMap<String, String>>()?)
 }
 
@@ -0,0 +1,140 @@
+// Network diagnostics for the menu
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::net::Ipv4Addr;
+
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::wifi::EspWifi;
+
+use crate::control::{DeviceStatus, WakeResult};
+use crate::wakepacket::TargetDevice;
+
+pub const DIAG_PAGES: usize = 2;
+pub const RSSI_HISTORY_LEN: usize = 60;
+const DHCP_COARSE_TIMER_SECS: u32 = 60;
+
+/// Snapshot of the network state shown on the Diagnostics page.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    pub ssid: String,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    pub rssi: i32,
+    pub ip_addr: Option<Ipv4Addr>,
+    pub netmask: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Option<Ipv4Addr>,
+    pub lease_remaining: Option<u32>,   // seconds
+    pub time_sync: String,     // e.g. `synced 12m ago`
+    pub last_wake: Option<(TargetDevice, WakeResult)>,
+}
+
+impl Diagnostics {
+    /// Text lines of a page. Page 0 (Wi-Fi) leaves room below the text for the RSSI graph.
+    pub fn page_lines(&self, page: usize) -> Vec<String> {
+        match page {
+            0 => vec![
+                format!("SSID:{}", self.ssid),
+                format!("BSSID:{}", format_mac(&self.bssid)),
+                format!("CH:{} RSSI:{}dBm", self.channel, self.rssi),
+            ],
+            _ => vec![
+                format!("IP  :{}", format_addr(self.ip_addr)),
+                format!("MASK:{}", format_addr(self.netmask)),
+                format!("GW  :{}", format_addr(self.gateway)),
+                format!("DNS :{}", format_addr(self.dns)),
+                format!("LEASE:{}", match self.lease_remaining {
+                    Some(secs) => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+                    None => "-".to_string(),
+                }),
+                format!("SNTP:{}", self.time_sync),
+                format!("WAKE:{}", match self.last_wake {
+                    Some((target, result)) => format!("{} {} ({})", target.name(),
+                        if result.success { "OK" } else { "FAILED" }, result.source.as_str()),
+                    None => "-".to_string(),
+                }),
+            ],
+        }
+    }
+}
+
+/// Read the current state from the Wi-Fi driver.
+pub fn collect(wifi_dev: Option<&EspWifi<'static>>, time_sync: String, status: &DeviceStatus) -> Diagnostics {
+    let mut diag = Diagnostics {
+        time_sync,
+        last_wake: status.last_wake,
+        ..Default::default()
+    };
+    unsafe {
+        let mut ap: esp_idf_sys::wifi_ap_record_t = core::mem::zeroed();
+        if esp_idf_sys::esp_wifi_sta_get_ap_info(&mut ap) == esp_idf_sys::ESP_OK as i32 {
+            let len = ap.ssid.iter().position(|&c| c == 0).unwrap_or(ap.ssid.len());
+            diag.ssid = String::from_utf8_lossy(&ap.ssid[..len]).into_owned();
+            diag.bssid = ap.bssid;
+            diag.channel = ap.primary;
+            diag.rssi = ap.rssi as i32;
+        }
+    }
+    if let Some(wifi) = wifi_dev {
+        if let Ok(info) = wifi.sta_netif().get_ip_info() {
+            if info.ip != Ipv4Addr::UNSPECIFIED {
+                diag.ip_addr = Some(info.ip);
+                diag.netmask = Some(prefix_to_netmask(info.subnet.mask.0));
+                diag.gateway = Some(info.subnet.gateway);
+                diag.dns = info.dns;
+                diag.lease_remaining = dhcp_lease_remaining(wifi);
+            }
+        }
+    }
+    diag
+}
+
+// Remaining DHCP lease from the lwIP client state. The `struct dhcp` fields are
+// owned by the TCP/IP thread, so they are read there (`esp_netif_tcpip_exec`).
+fn dhcp_lease_remaining(wifi: &EspWifi<'static>) -> Option<u32> {
+    let mut query = LeaseQuery { netif: wifi.sta_netif().handle(), remaining: None };
+    let err = unsafe {
+        esp_idf_sys::esp_netif_tcpip_exec(Some(read_lease), &mut query as *mut LeaseQuery as *mut core::ffi::c_void)
+    };
+    if err != esp_idf_sys::ESP_OK as i32 {
+        return None;
+    }
+    query.remaining
+}
+
+struct LeaseQuery {
+    netif: *mut esp_idf_sys::esp_netif_t,
+    remaining: Option<u32>,
+}
+
+// Runs in the TCP/IP thread; `ctx` is the `LeaseQuery` of the waiting caller.
+unsafe extern "C" fn read_lease(ctx: *mut core::ffi::c_void) -> esp_idf_sys::esp_err_t {
+    let query = &mut *(ctx as *mut LeaseQuery);
+    let netif = esp_idf_sys::esp_netif_get_netif_impl(query.netif) as *const esp_idf_sys::netif;
+    if netif.is_null() {
+        return esp_idf_sys::ESP_OK as i32;
+    }
+    let dhcp = (*netif).client_data[esp_idf_sys::LWIP_NETIF_CLIENT_DATA_INDEX_DHCP as usize] as *const esp_idf_sys::dhcp;
+    if dhcp.is_null() || (*dhcp).t0_timeout == 0 {
+        return esp_idf_sys::ESP_OK as i32;
+    }
+    let remaining = (*dhcp).t0_timeout.saturating_sub((*dhcp).lease_used) as u32;
+    query.remaining = Some(remaining * DHCP_COARSE_TIMER_SECS);
+    esp_idf_sys::ESP_OK as i32
+}
+
+pub fn prefix_to_netmask(prefix: u8) -> Ipv4Addr {
+    match prefix {
+        0 => Ipv4Addr::UNSPECIFIED,
+        p => Ipv4Addr::from(u32::MAX << (32 - p.min(32) as u32)),
+    }
+}
+
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(":")
+}
+
+fn format_addr(addr: Option<Ipv4Addr>) -> String {
+    addr.map(|a| a.to_string()).unwrap_or("-".to_string())
+}
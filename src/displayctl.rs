@@ -1,5 +1,6 @@
 use log::*;
 use std::{thread, time::Duration, sync::Arc, sync::Mutex};
+use std::collections::VecDeque;
 use esp_idf_hal::i2c;
 use ssd1306::{I2CDisplayInterface, prelude::*, Ssd1306};
 use embedded_graphics::{
@@ -8,19 +9,28 @@ use embedded_graphics::{
     pixelcolor::BinaryColor,
     text::{Text},
     geometry::Point,
+    primitives::{PrimitiveStyle, Rectangle},
     prelude::*,
 };
 use tinybmp::Bmp;
 use chrono::{DateTime, Utc, Local, FixedOffset};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::SystemTime;
 
 use crate::ui::{MenuTable, MenuType, InputTypeChar};
 use crate::keyevent::{KeyEvent};
 use crate::ConfigData;
+use crate::wifi::ScanResult;
+use crate::diag::{Diagnostics, DIAG_PAGES, RSSI_HISTORY_LEN};
+use crate::buttons;
 
 const MAIN_MENU_WIFI : usize = 0;
 const MAIN_MENU_HWADDR : usize = 1;
 const MAIN_MENU_SYSTEM : usize = 2;
+const MAIN_MENU_BUTTONS : usize = 3;
+const MAIN_MENU_ROWS : usize = 3;
+const SUB_MENU_ROWS : usize = 6;
+const MENU_LEVEL_SCAN : usize = 3;
+const MENU_LEVEL_DIAG : usize = 4;
 
 pub enum WiFiStatus {
     Disconnected,
@@ -37,6 +47,8 @@ pub enum MessageTypes {
     Status,
     Error,
     Menu,
+    WpsPin,
+    PinEntry,
 }
 
 type DISPLAYIF<'d> = i2c::I2cDriver<'static>;
@@ -52,7 +64,18 @@ struct DisplayText {
     initial_logo: bool,
     timezone_offset: i32,
     battery_voltage: f32,
+    profile_name: String,
+    wifi_reason: String,
+    time_valid: bool,
+    hostname: String,
     menu_table: MenuTable,
+    scan_request: bool,
+    scanning: bool,
+    scan_list: Vec<ScanResult>,
+    scan_select: usize,
+    diag: Diagnostics,
+    diag_page: usize,
+    rssi_history: VecDeque<i32>,
 }
 
 pub struct DisplayPanel {
@@ -72,7 +95,18 @@ impl DisplayPanel {
                             initial_logo: false,
                             timezone_offset: 0,
                             battery_voltage: 0.0,
+                            profile_name: "".to_string(),
+                            wifi_reason: "".to_string(),
+                            time_valid: false,
+                            hostname: "".to_string(),
                             menu_table: MenuTable::new(),
+                            scan_request: false,
+                            scanning: false,
+                            scan_list: Vec::new(),
+                            scan_select: 0,
+                            diag: Diagnostics::default(),
+                            diag_page: 0,
+                            rssi_history: VecDeque::with_capacity(RSSI_HISTORY_LEN),
                      })) }
     }
 
@@ -177,13 +211,15 @@ impl DisplayPanel {
                 }
                 match lck.msg_type {
                     MessageTypes::Ready => {
-                        let now = SystemTime::now();
-                        if now.duration_since(UNIX_EPOCH).unwrap().as_millis() > 1700000000 {
-                            let dt_utc : DateTime<Utc> = now.into();
+                        if lck.time_valid {
+                            let dt_utc : DateTime<Utc> = SystemTime::now().into();
                             let fixed_offset = FixedOffset::east_opt(lck.timezone_offset * 3600).unwrap();
                             let dt_local = DateTime::<Local>::from_naive_utc_and_offset(dt_utc.naive_utc(), fixed_offset);
                             Text::new(&format!("{}", dt_local.format("%Y-%m-%d %H:%M:%S")), Point::new(1, 10), style_middle).draw(&mut display).unwrap();
                         }
+                        else {
+                            Text::new("--:--", Point::new(1, 10), style_middle).draw(&mut display).unwrap();
+                        }
                         push_img.draw(&mut display).unwrap();
                         Text::new(&lck.main_msg, Point::new(1, 60), style_middle).draw(&mut display).unwrap();
                     },
@@ -210,41 +246,61 @@ impl DisplayPanel {
                         wakeuppc_img.draw(&mut display).unwrap();
                     },
                     MessageTypes::Status => {
+                        if !lck.hostname.is_empty() {
+                            Text::new(&format!("{}.local", lck.hostname), Point::new(1, 8), style_small).draw(&mut display).unwrap();
+                        }
+                        if !lck.profile_name.is_empty() {
+                            Text::new(&format!("Profile:{}", lck.profile_name), Point::new(1, 20), style_middle).draw(&mut display).unwrap();
+                        }
                         Text::new(&lck.main_msg, Point::new(1, 30), style_middle).draw(&mut display).unwrap();
                         Text::new(&lck.second_msg, Point::new(1, 40), style_middle).draw(&mut display).unwrap();
+                        if !lck.wifi_reason.is_empty() {
+                            Text::new(&format!("WiFi:{}", lck.wifi_reason), Point::new(1, 50), style_small).draw(&mut display).unwrap();
+                        }
                     },
                     MessageTypes::Menu => {
                         match lck.menu_table.get_current_level() {
                             0 => {
                                 let tbl = lck.menu_table.get_menu_item_list();
                                 let row = lck.menu_table.get_current_select();
+                                let top = if row >= MAIN_MENU_ROWS { row + 1 - MAIN_MENU_ROWS } else { 0 };
                                 let mut n = 0;
                                 for _m in tbl {
+                                    if n < top || n >= top + MAIN_MENU_ROWS {
+                                        n += 1;
+                                        continue;
+                                    }
                                     if row == n {
-                                        Text::new(&lck.menu_table.get_menu_item_title(n), Point::new(8, 20 + (n * 20) as i32), style_large_inv).draw(&mut display).unwrap();
+                                        Text::new(&lck.menu_table.get_menu_item_title(n), Point::new(8, 20 + ((n - top) * 20) as i32), style_large_inv).draw(&mut display).unwrap();
                                     }
                                     else {
-                                        Text::new(&lck.menu_table.get_menu_item_title(n), Point::new(8, 20 + (n * 20) as i32), style_large).draw(&mut display).unwrap();
+                                        Text::new(&lck.menu_table.get_menu_item_title(n), Point::new(8, 20 + ((n - top) * 20) as i32), style_large).draw(&mut display).unwrap();
                                     }
                                     n += 1;
                                 }
-                                Text::new(">", Point::new(0, 20 + (row * 20) as i32), style_large).draw(&mut display).unwrap();        
+                                Text::new(">", Point::new(0, 20 + ((row - top) * 20) as i32), style_large).draw(&mut display).unwrap();        
                             },
                             1 => {
                                 let sel = lck.menu_table.get_current_select();
                                 let tbl = lck.menu_table.get_menu_list(sel);
                                 let sel_sub = lck.menu_table.get_current_select_sub(sel);
+                                // scroll when the list is longer than the panel
+                                let top = if sel_sub >= SUB_MENU_ROWS { sel_sub + 1 - SUB_MENU_ROWS } else { 0 };
                                 let mut n = 0;
                                 for _m in tbl {
+                                    if n < top || n >= top + SUB_MENU_ROWS {
+                                        n += 1;
+                                        continue;
+                                    }
                                     if sel_sub == n {
-                                        Text::new(&lck.menu_table.get_menu_title(sel, n), Point::new(8, 10 + (n * 10) as i32), style_middle_inv).draw(&mut display).unwrap();
+                                        Text::new(&lck.menu_table.get_menu_title(sel, n), Point::new(8, 10 + ((n - top) * 10) as i32), style_middle_inv).draw(&mut display).unwrap();
                                     }
                                     else {
-                                        Text::new(&lck.menu_table.get_menu_title(sel, n), Point::new(8, 10 + (n * 10) as i32), style_middle).draw(&mut display).unwrap();
+                                        Text::new(&lck.menu_table.get_menu_title(sel, n), Point::new(8, 10 + ((n - top) * 10) as i32), style_middle).draw(&mut display).unwrap();
                                     }
                                     n += 1;
                                 }
-                                Text::new(">", Point::new(0, 10 + (sel_sub * 10) as i32), style_middle).draw(&mut display).unwrap();        
+                                Text::new(">", Point::new(0, 10 + ((sel_sub - top) * 10) as i32), style_middle).draw(&mut display).unwrap();        
                             },
                             2 => {
                                 let sel = lck.menu_table.get_current_select();
@@ -263,6 +319,64 @@ impl DisplayPanel {
                                     },   
                                 }
                             },
+                            MENU_LEVEL_SCAN => {
+                                if lck.scanning {
+                                    Text::new("Scanning...", Point::new(0, 30), style_middle).draw(&mut display).unwrap();
+                                }
+                                else if lck.scan_list.is_empty() {
+                                    Text::new("No network", Point::new(0, 30), style_middle).draw(&mut display).unwrap();
+                                }
+                                else {
+                                    let row = lck.scan_select;
+                                    let top = if row >= SUB_MENU_ROWS { row + 1 - SUB_MENU_ROWS } else { 0 };
+                                    for (n, ap) in lck.scan_list.iter().enumerate().skip(top).take(SUB_MENU_ROWS) {
+                                        let y = 10 + ((n - top) * 10) as i32;
+                                        let ssid : String = ap.ssid.chars().take(13).collect();
+                                        if n == row {
+                                            Text::new(&ssid, Point::new(8, y), style_middle_inv).draw(&mut display).unwrap();
+                                        }
+                                        else {
+                                            Text::new(&ssid, Point::new(8, y), style_middle).draw(&mut display).unwrap();
+                                        }
+                                        Text::new(ap.auth, Point::new(88, y - 1), style_small).draw(&mut display).unwrap();
+                                        // RSSI bars
+                                        let bars = match ap.rssi {
+                                            -55..=0 => 4,
+                                            -65..=-56 => 3,
+                                            -75..=-66 => 2,
+                                            -85..=-76 => 1,
+                                            _ => 0,
+                                        };
+                                        for i in 0..bars {
+                                            Rectangle::new(Point::new(114 + i * 3, y - 1 - (i + 1) * 2), Size::new(2, ((i + 1) * 2) as u32))
+                                                .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                                                .draw(&mut display).unwrap();
+                                        }
+                                    }
+                                    Text::new(">", Point::new(0, 10 + ((row - top) * 10) as i32), style_middle).draw(&mut display).unwrap();
+                                }
+                            },
+                            MENU_LEVEL_DIAG => {
+                                for (n, line) in lck.diag.page_lines(lck.diag_page).iter().enumerate() {
+                                    Text::new(line, Point::new(0, 7 + (n * 8) as i32), style_small).draw(&mut display).unwrap();
+                                }
+                                if lck.diag_page == 0 {
+                                    // RSSI history: -100dBm..-30dBm mapped to 0..32 px
+                                    Rectangle::new(Point::new(0, 30), Size::new(122, 34))
+                                        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+                                        .draw(&mut display).unwrap();
+                                    for (i, rssi) in lck.rssi_history.iter().enumerate() {
+                                        if *rssi == 0 {
+                                            continue;
+                                        }
+                                        let h = (((*rssi).clamp(-100, -30) + 100) * 32 / 70) as u32;
+                                        Rectangle::new(Point::new(1 + (i * 2) as i32, 63 - h as i32), Size::new(2, h))
+                                            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                                            .draw(&mut display).unwrap();
+                                    }
+                                }
+                                Text::new(&format!("{}/{}", lck.diag_page + 1, DIAG_PAGES), Point::new(110, 7), style_small).draw(&mut display).unwrap();
+                            },
                             _ => {},
                         }
                         if lck.menu_table.get_confirming_flag() == true {
@@ -278,6 +392,16 @@ impl DisplayPanel {
                     MessageTypes::Error => {
                         Text::new(&lck.main_msg, Point::new(1, 40), style_large).draw(&mut display).unwrap();
                     },
+                    MessageTypes::PinEntry => {
+                        Text::new("ENTER PIN", Point::new(1, 10), style_middle).draw(&mut display).unwrap();
+                        Text::new(&lck.main_msg, Point::new(1, 35), style_large).draw(&mut display).unwrap();
+                        Text::new("OK:CENTER", Point::new(1, 50), style_middle).draw(&mut display).unwrap();
+                    },
+                    MessageTypes::WpsPin => {
+                        Text::new("WPS PIN", Point::new(1, 10), style_middle).draw(&mut display).unwrap();
+                        Text::new(&lck.main_msg, Point::new(1, 35), style_large).draw(&mut display).unwrap();
+                        Text::new(&lck.second_msg, Point::new(1, 50), style_middle).draw(&mut display).unwrap();
+                    },
                     _ => {},
                 }
                 if lck.msg_type != MessageTypes::Menu {
@@ -438,6 +562,23 @@ impl DisplayPanel {
         lcktxt.wifi_status = status;
     }
 
+    /// Last disconnect reason shown on the Status screen. Empty while connected.
+    pub fn set_wifi_reason(&mut self, reason: &String){
+        let mut lcktxt = self.txt.lock().unwrap();
+        lcktxt.wifi_reason = reason.to_string();
+    }
+
+    /// Whether the clock on the Ready screen can be trusted (see `timesvc`).
+    pub fn set_time_valid(&mut self, valid: bool){
+        let mut lcktxt = self.txt.lock().unwrap();
+        lcktxt.time_valid = valid;
+    }
+
+    pub fn set_hostname(&mut self, hostname: &String){
+        let mut lcktxt = self.txt.lock().unwrap();
+        lcktxt.hostname = hostname.to_string();
+    }
+
     pub fn set_send_pkt(&mut self, count: u32){
         let mut lcktxt = self.txt.lock().unwrap();
         lcktxt.num_send_pkt = count;
@@ -448,6 +589,11 @@ impl DisplayPanel {
         lcktxt.timezone_offset = offset;
     }
 
+    pub fn set_profile_name(&mut self, name: &String){
+        let mut lcktxt = self.txt.lock().unwrap();
+        lcktxt.profile_name = name.to_string();
+    }
+
     pub fn set_initial_logo(&mut self, show: bool){
         let mut lcktxt = self.txt.lock().unwrap();
         lcktxt.initial_logo = show;
@@ -477,6 +623,36 @@ impl DisplayPanel {
     {
         let mut lcktxt = self.txt.lock().unwrap();
         lcktxt.wifi_rssi = rssi;
+        if lcktxt.rssi_history.len() >= RSSI_HISTORY_LEN {
+            lcktxt.rssi_history.pop_front();
+        }
+        lcktxt.rssi_history.push_back(rssi);
+    }
+
+    /// A text, number or MAC address value is being edited (Up/Down step through characters).
+    pub fn is_value_editing(&self) -> bool
+    {
+        let lcktxt = self.txt.lock().unwrap();
+        if lcktxt.msg_type != MessageTypes::Menu || lcktxt.menu_table.get_current_level() != 2 {
+            return false;
+        }
+        let sel = lcktxt.menu_table.get_current_select();
+        let sel_sub = lcktxt.menu_table.get_current_select_sub(sel);
+        matches!(lcktxt.menu_table.get_value_type(sel, sel_sub),
+            InputTypeChar::StringType | InputTypeChar::NumberType | InputTypeChar::HWAddressType)
+    }
+
+    /// True while the Diagnostics page is open; the menu loop then refreshes it.
+    pub fn is_diag_open(&self) -> bool
+    {
+        let lcktxt = self.txt.lock().unwrap();
+        lcktxt.msg_type == MessageTypes::Menu && lcktxt.menu_table.get_current_level() == MENU_LEVEL_DIAG
+    }
+
+    pub fn set_diagnostics(&mut self, diag: Diagnostics)
+    {
+        let mut lcktxt = self.txt.lock().unwrap();
+        lcktxt.diag = diag;
     }
 
     pub fn initialize_menu(&mut self, config_data: &ConfigData){
@@ -486,19 +662,85 @@ impl DisplayPanel {
         lcktxt.menu_table.add_menu_item("WiFi");
         lcktxt.menu_table.add_menu_item("HW Address");
         lcktxt.menu_table.add_menu_item("System");
+        lcktxt.menu_table.add_menu_item("Buttons");
+        lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "SCAN", "SCAN", MenuType::SubMenu, "", InputTypeChar::ScanType, Vec::<String>::new());
         lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "SSID", "SSID", MenuType::SubMenu, &config_data.wifi_ssid.clone(), InputTypeChar::StringType, Vec::<String>::new());
         lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "PSK", "PSK", MenuType::SubMenu, &config_data.wifi_psk.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "AUTH", "WIFIAUTH", MenuType::SubMenu, &config_data.wifi_auth.clone(), InputTypeChar::SelectType, vec!["psk", "eap"].iter().map(|s| s.to_string()).collect());
+        lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "EAP IDENTITY", "EAPID", MenuType::SubMenu, &config_data.eap_identity.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "EAP USER", "EAPUSER", MenuType::SubMenu, &config_data.eap_username.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "EAP PASS", "EAPPASS", MenuType::SubMenu, &config_data.eap_password.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "EAP CA CERT", "EAPCACERT", MenuType::SubMenu, &config_data.eap_ca_cert.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "CONNECT VIA WPS", "WPSCONNECT", MenuType::SubMenu, "START WPS", InputTypeChar::ActionType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_WIFI, "WPS MODE", "WPSMODE", MenuType::SubMenu, &config_data.wps_mode.clone(), InputTypeChar::SelectType, vec!["pbc", "pin"].iter().map(|s| s.to_string()).collect());
         lcktxt.menu_table.add_menu(MAIN_MENU_HWADDR, "PC1", "PC1", MenuType::SubMenu, &config_data.target_mac_address1.clone(), InputTypeChar::HWAddressType, Vec::<String>::new());
         lcktxt.menu_table.add_menu(MAIN_MENU_HWADDR, "PC2", "PC2", MenuType::SubMenu, &config_data.target_mac_address2.clone(), InputTypeChar::HWAddressType, Vec::<String>::new());
         lcktxt.menu_table.add_menu(MAIN_MENU_HWADDR, "PC3", "PC3", MenuType::SubMenu, &config_data.target_mac_address3.clone(), InputTypeChar::HWAddressType, Vec::<String>::new());
         lcktxt.menu_table.add_menu(MAIN_MENU_HWADDR, "PC4", "PC4", MenuType::SubMenu, &config_data.target_mac_address4.clone(), InputTypeChar::HWAddressType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_HWADDR, "GROUPS", "GROUPS", MenuType::SubMenu, &config_data.wake_groups.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "DIAGNOSTICS", "DIAGNOSTICS", MenuType::SubMenu, "", InputTypeChar::DiagType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "PROFILE", "PROFILE", MenuType::SubMenu, &config_data.profile_select_value(), InputTypeChar::SelectType, config_data.profile_select_items());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "PROFILE NAME", "PROFILENAME", MenuType::SubMenu, &config_data.profile_name.clone(), InputTypeChar::StringType, Vec::<String>::new());
         lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "TIME ZONE", "TIMEZONE", MenuType::SubMenu, &format!("{}{:02}", if config_data.timezone_offset >= 0 {'+'} else {'-'}, config_data.timezone_offset.abs()), InputTypeChar::TimezoneType, Vec::<String>::new());
         lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "IDLE TIME", "IDLESLEEP", MenuType::SubMenu, &format!("{}", config_data.idle_in_sleep_time), InputTypeChar::NumberType, Vec::<String>::new());
         lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "SLEEP MODE", "SLEEPMODE", MenuType::SubMenu, &format!("{}", config_data.sleep_mode), InputTypeChar::SelectType, vec!["light", "deep"].iter().map(|s| s.to_string()).collect());
         lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "DISPLAY OFF TIME", "DISPLAYOFFTIME", MenuType::SubMenu, &format!("{}", config_data.display_off_time), InputTypeChar::NumberType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "WIFI POWER SAVE", "WIFIPS", MenuType::SubMenu, &config_data.wifi_power_save.clone(), InputTypeChar::SelectType, vec!["none", "min", "max"].iter().map(|s| s.to_string()).collect());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "LISTEN INTERVAL", "WIFILISTEN", MenuType::SubMenu, &format!("{}", config_data.wifi_listen_interval), InputTypeChar::NumberType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "MAX TX POWER", "WIFITXPOWER", MenuType::SubMenu, &format!("{}", config_data.wifi_max_tx_power), InputTypeChar::NumberType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "HOSTNAME", "HOSTNAME", MenuType::SubMenu, &config_data.hostname.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "LONG PRESS MS", "LONGPRESS", MenuType::SubMenu, &format!("{}", config_data.long_press_time), InputTypeChar::NumberType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "DOUBLE PRESS MS", "DOUBLEPRESS", MenuType::SubMenu, &format!("{}", config_data.double_press_time), InputTypeChar::NumberType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "CHORD MS", "CHORDTIME", MenuType::SubMenu, &format!("{}", config_data.chord_time), InputTypeChar::NumberType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "SYSLOG SERVER", "SYSLOGSERVER", MenuType::SubMenu, &config_data.syslog_server.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "SYSLOG LEVEL", "SYSLOGLEVEL", MenuType::SubMenu, &config_data.syslog_level.clone(), InputTypeChar::SelectType, vec!["error", "warn", "info", "debug"].iter().map(|s| s.to_string()).collect());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "SHELL PASS", "SHELLPASS", MenuType::SubMenu, &config_data.shell_password.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "INFLUX SERVER", "INFLUXSERVER", MenuType::SubMenu, &config_data.influx_server.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "INFLUX INTERVAL", "INFLUXINTERVAL", MenuType::SubMenu, &format!("{}", config_data.influx_interval), InputTypeChar::NumberType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "WAKE SECRET", "WAKESECRET", MenuType::SubMenu, &config_data.wake_secret.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "WEBHOOK URL", "WEBHOOKURL", MenuType::SubMenu, &config_data.webhook_url.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "WEBHOOK METHOD", "WEBHOOKMETHOD", MenuType::SubMenu, &config_data.webhook_method.clone(), InputTypeChar::SelectType, vec!["POST", "PUT", "GET"].iter().map(|s| s.to_string()).collect());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "PIN LOCK", "PINLOCK", MenuType::SubMenu, &config_data.pin_lock.clone(), InputTypeChar::SelectType, vec!["off", "menu", "wake", "both"].iter().map(|s| s.to_string()).collect());
+        // the PIN itself is never shown; a new one replaces the stored hash when saved
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "PIN CODE", "PIN", MenuType::SubMenu, "", InputTypeChar::NumberType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "PIN TARGETS", "PINTARGETS", MenuType::SubMenu, &config_data.pin_targets.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "HTTP API", "HTTPAPI", MenuType::SubMenu, &format!("{}", config_data.http_api_enable), InputTypeChar::SelectType, vec!["true", "false"].iter().map(|s| s.to_string()).collect());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "API TOKEN", "APITOKEN", MenuType::SubMenu, &config_data.api_token.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "MQTT URL", "MQTTURL", MenuType::SubMenu, &config_data.mqtt_url.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "MQTT USER", "MQTTUSER", MenuType::SubMenu, &config_data.mqtt_username.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "MQTT PASS", "MQTTPASS", MenuType::SubMenu, &config_data.mqtt_password.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "MQTT TOPIC", "MQTTTOPIC", MenuType::SubMenu, &config_data.mqtt_topic.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "OTA URL", "OTAURL", MenuType::SubMenu, &config_data.ota_url.clone(), InputTypeChar::StringType, Vec::<String>::new());
+        lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "FIRMWARE UPDATE", "OTAUPDATE", MenuType::SubMenu, "START UPDATE", InputTypeChar::ActionType, Vec::<String>::new());
         lcktxt.menu_table.add_menu(MAIN_MENU_SYSTEM, "RESET CONFIG", "RESETCONFIG", MenuType::SubMenu, "BACK TO DEFAULT", InputTypeChar::ActionType, Vec::<String>::new());
+        let action_items = buttons::action_items(&config_data.wake_groups);
+        for (key, value) in &config_data.button_actions {
+            let title = buttons::BINDINGS.iter().find(|b| b.key == key.as_str()).map(|b| b.title).unwrap_or(key.as_str());
+            let mut items = action_items.clone();
+            // keep a value that is no longer offered (e.g. a removed group) selectable
+            if !items.contains(value) {
+                items.push(value.clone());
+            }
+            lcktxt.menu_table.add_menu(MAIN_MENU_BUTTONS, title, key, MenuType::SubMenu, value, InputTypeChar::SelectType, items);
+        }
    }
 
+    pub fn take_scan_request(&mut self) -> bool
+    {
+        let mut lcktxt = self.txt.lock().unwrap();
+        let ret = lcktxt.scan_request;
+        lcktxt.scan_request = false;
+        ret
+    }
+
+    pub fn set_scan_result(&mut self, list: Vec<ScanResult>)
+    {
+        let mut lcktxt = self.txt.lock().unwrap();
+        lcktxt.scan_list = list;
+        lcktxt.scan_select = 0;
+        lcktxt.scanning = false;
+    }
+
     pub fn reset_menu(&mut self)
     {
         let mut lcktxt = self.txt.lock().unwrap();
@@ -532,10 +774,18 @@ impl DisplayPanel {
                             lcktxt.menu_table.set_current_select_sub(sel_item, sub_sel);
                         }
                         else {
-                            sub_sel = lcktxt.menu_table.get_menu_list(current_level).len() - 1;
+                            sub_sel = lcktxt.menu_table.get_menu_list(sel_item).len() - 1;
                             lcktxt.menu_table.set_current_select_sub(sel_item, sub_sel);
                         }
                     },
+                    MENU_LEVEL_SCAN => {
+                        if lcktxt.scan_select > 0 {
+                            lcktxt.scan_select -= 1;
+                        }
+                    },
+                    MENU_LEVEL_DIAG => {
+                        lcktxt.diag_page = (lcktxt.diag_page + DIAG_PAGES - 1) % DIAG_PAGES;
+                    },
                     // decrement character value in menu
                     2 => {
                         let sel = lcktxt.menu_table.get_current_select();
@@ -557,6 +807,10 @@ impl DisplayPanel {
                         }
                         else {
                             let cursor = lcktxt.menu_table.get_current_cursor(sel, sel_sub);
+                            if value.len() <= cursor {
+                                // empty value
+                                value.push(' ');
+                            }
                             let mut ch = value.chars().nth(cursor).unwrap();
                             ch = lcktxt.menu_table.inc_dec_char(false, ch, lcktxt.menu_table.get_value_type(sel, sel_sub));
                             value.replace_range(cursor..cursor+1, &ch.to_string());
@@ -591,6 +845,14 @@ impl DisplayPanel {
                             lcktxt.menu_table.set_current_select_sub(sel_item, sub_sel);
                         }
                     },
+                    MENU_LEVEL_SCAN => {
+                        if lcktxt.scan_select + 1 < lcktxt.scan_list.len() {
+                            lcktxt.scan_select += 1;
+                        }
+                    },
+                    MENU_LEVEL_DIAG => {
+                        lcktxt.diag_page = (lcktxt.diag_page + 1) % DIAG_PAGES;
+                    },
                     // increment character value in menu
                     2 => {
                         let sel = lcktxt.menu_table.get_current_select();
@@ -612,6 +874,10 @@ impl DisplayPanel {
                         }
                         else {
                             let cursor = lcktxt.menu_table.get_current_cursor(sel, sel_sub);
+                            if value.len() <= cursor {
+                                // empty value
+                                value.push(' ');
+                            }
                             let mut ch = value.chars().nth(cursor).unwrap();
                             ch = lcktxt.menu_table.inc_dec_char(true, ch, lcktxt.menu_table.get_value_type(sel, sel_sub));
                             value.replace_range(cursor..cursor+1, &ch.to_string());
@@ -637,6 +903,10 @@ impl DisplayPanel {
                         current_level -= 1;
                         lcktxt.menu_table.set_current_level(current_level);
                     },
+                    MENU_LEVEL_SCAN | MENU_LEVEL_DIAG => {
+                        // back to the sub menu
+                        lcktxt.menu_table.set_current_level(1);
+                    },
                     2 => {
                         if lcktxt.menu_table.get_confirming_flag() {
                             if lcktxt.menu_table.get_confirmed_flag() {
@@ -684,7 +954,7 @@ impl DisplayPanel {
                             let sel_sub = lcktxt.menu_table.get_current_select_sub(sel);
                             let mut value = lcktxt.menu_table.get_value(sel, sel_sub);
                             let cursor = lcktxt.menu_table.get_current_cursor(sel, sel_sub);
-                            if cursor < value.len() - 1 {
+                            if cursor + 1 < value.len() {
                                 lcktxt.menu_table.set_current_cursor(sel, sel_sub, cursor + 1);
                             }
                             else {
@@ -701,10 +971,46 @@ impl DisplayPanel {
             KeyEvent::CenterKeyUp => {
                 info!("Enter key pressed.");
                 match current_level {
-                    0 | 1 => {
+                    0 => {
                         current_level += 1;
                         lcktxt.menu_table.set_current_level(current_level);
                     },
+                    1 => {
+                        let sel = lcktxt.menu_table.get_current_select();
+                        let sel_sub = lcktxt.menu_table.get_current_select_sub(sel);
+                        if lcktxt.menu_table.get_value_type(sel, sel_sub) == InputTypeChar::ScanType {
+                            // scan runs in the menu loop, which owns the WiFi driver
+                            lcktxt.scan_request = true;
+                            lcktxt.scanning = true;
+                            lcktxt.scan_list.clear();
+                            lcktxt.scan_select = 0;
+                            lcktxt.menu_table.set_current_level(MENU_LEVEL_SCAN);
+                        }
+                        else if lcktxt.menu_table.get_value_type(sel, sel_sub) == InputTypeChar::DiagType {
+                            lcktxt.diag_page = 0;
+                            lcktxt.menu_table.set_current_level(MENU_LEVEL_DIAG);
+                        }
+                        else {
+                            current_level += 1;
+                            lcktxt.menu_table.set_current_level(current_level);
+                        }
+                    },
+                    MENU_LEVEL_SCAN => {
+                        if lcktxt.scanning || lcktxt.scan_list.is_empty() {
+                            return (false, None);
+                        }
+                        // fill in the SSID and move to PSK
+                        let ssid = lcktxt.scan_list[lcktxt.scan_select].ssid.clone();
+                        let sel = lcktxt.menu_table.get_current_select();
+                        if let Some(ssid_idx) = lcktxt.menu_table.find_menu_by_key(sel, "SSID") {
+                            lcktxt.menu_table.set_value(sel, ssid_idx, &ssid);
+                            lcktxt.menu_table.commit_value(sel, ssid_idx);
+                        }
+                        if let Some(psk_idx) = lcktxt.menu_table.find_menu_by_key(sel, "PSK") {
+                            lcktxt.menu_table.set_current_select_sub(sel, psk_idx);
+                        }
+                        lcktxt.menu_table.set_current_level(1);
+                    },
                     2 => {
                         if lcktxt.menu_table.get_confirming_flag() {
                             lcktxt.menu_table.set_confirming_flag(false);
@@ -0,0 +1,55 @@
+// Hostname for DHCP and mDNS
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use anyhow::Result;
+use esp_idf_svc::mdns::EspMdns;
+use log::*;
+
+const HOSTNAME_PREFIX: &str = "wakepcbox";
+const MAX_HOSTNAME_LEN: usize = 32;
+
+/// A service advertised over mDNS, e.g. `("_http", "_tcp", 80)`.
+pub struct MdnsService {
+    pub service_type: &'static str,
+    pub proto: &'static str,
+    pub port: u16,
+}
+
+/// `wakepcbox-xxxxxx` from the last 3 bytes of the station MAC address.
+pub fn default_hostname() -> String {
+    let mut mac = [0u8; 6];
+    unsafe {
+        esp_idf_sys::esp_read_mac(mac.as_mut_ptr(), esp_idf_sys::esp_mac_type_t_ESP_MAC_WIFI_STA);
+    }
+    format!("{}-{:02x}{:02x}{:02x}", HOSTNAME_PREFIX, mac[3], mac[4], mac[5])
+}
+
+/// Configured hostname reduced to a valid DNS label, or the default one if nothing is left.
+pub fn resolve_hostname(configured: &str) -> String {
+    let name = sanitize_hostname(configured);
+    if name.is_empty() { default_hostname() } else { name }
+}
+
+/// Lower case letters, digits and '-', no leading/trailing '-', at most 32 characters.
+pub fn sanitize_hostname(name: &str) -> String {
+    let name : String = name.trim().trim_end_matches(".local").chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .take(MAX_HOSTNAME_LEN)
+        .collect();
+    name.trim_matches('-').to_string()
+}
+
+/// Start the mDNS responder as `<hostname>.local` and advertise `services`.
+/// The returned responder must be kept alive.
+pub fn start_mdns(hostname: &str, services: &[MdnsService]) -> Result<EspMdns> {
+    let mut mdns = EspMdns::take()?;
+    mdns.set_hostname(hostname)?;
+    mdns.set_instance_name("Wake PC Box")?;
+    for service in services {
+        mdns.add_service(None, service.service_type, service.proto, service.port, &[("board", "wakepcbox")])?;
+        info!("[MDNS] Advertising {}.{} on port {}", service.service_type, service.proto, service.port);
+    }
+    info!("[MDNS] Responding as {}.local", hostname);
+    Ok(mdns)
+}
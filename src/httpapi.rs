@@ -0,0 +1,210 @@
+// HTTP REST API for wake and configuration
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use anyhow::Result;
+use embedded_svc::http::server::Request;
+use esp_idf_svc::http::server::{Configuration as HttpConfiguration, EspHttpConnection, EspHttpServer};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::{Read, Write};
+use log::*;
+
+use crate::control::{config_value, json_escape, Control, TriggerSource};
+use crate::provision::parse_form;
+use crate::ota;
+use crate::securewake::constant_time_eq;
+use crate::wakepacket::{self, TargetDevice, ALL_TARGETS};
+
+const MAX_BODY_SIZE: usize = 1024;
+const HTTP_STACK_SIZE: usize = 10240;   // OTA upload runs in the handler
+
+/// Start the HTTP server with the REST API.
+///
+/// - `GET  /api/targets` : target MAC addresses and wake groups
+/// - `POST /api/wake?target=PC1` or `?group=office` : queue a wake
+/// - `GET  /api/status` : battery voltage, RSSI, IP, send count
+/// - `GET  /api/config` / `POST /api/config` (form encoded) : read / update the config
+/// - `POST /api/ota?url=...` : download and install firmware (default: `OTAURL` config)
+/// - `POST /api/ota/upload` : install the firmware image sent as the request body
+/// - `GET  /metrics` : Prometheus metrics (registered by `metrics::register_http`)
+///
+/// Requests that change something (wake, config, OTA) need `Authorization: Bearer <token>`;
+/// without a token they are refused. Secrets and `LOCAL_ONLY_KEYS` cannot be changed at all.
+/// The returned server must be kept alive; other services may register more handlers on it.
+pub fn start(token: &str, control: Control) -> Result<EspHttpServer<'static>> {
+    let mut server = EspHttpServer::new(&HttpConfiguration {
+        uri_match_wildcard: true,
+        stack_size: HTTP_STACK_SIZE,
+        ..Default::default()
+    })?;
+
+    let ctl = control.clone();
+    server.fn_handler::<anyhow::Error, _>("/api/targets", Method::Get, move |req| {
+        let config = ctl.get_config_entries();
+        let targets : Vec<String> = ALL_TARGETS.iter().map(|t| {
+            format!("{{\"name\":\"{}\",\"mac\":\"{}\"}}", t.name(), json_escape(&config_value(&config, t.name())))
+        }).collect();
+        let groups : Vec<String> = wakepacket::parse_groups(&config_value(&config, "GROUPS")).iter().map(|g| {
+            format!("{{\"name\":\"{}\",\"targets\":[{}]}}", json_escape(&g.0), target_names_json(&g.1))
+        }).collect();
+        send_json(req, 200, &format!("{{\"targets\":[{}],\"groups\":[{}]}}", targets.join(","), groups.join(",")))
+    })?;
+
+    let ctl = control.clone();
+    let tok = token.to_string();
+    server.fn_handler::<anyhow::Error, _>("/api/wake", Method::Post, move |mut req| {
+        if let Some(status) = auth_error(&req, &tok) {
+            return send_auth_error(req, status);
+        }
+        let mut params = query_params(req.uri());
+        params.extend(parse_form(&read_body(&mut req)?));
+        // same lookup as the shell and signed UDP: a target name or a wake group
+        let name = params.iter().find(|p| p.0 == "target")
+            .or_else(|| params.iter().find(|p| p.0 == "group"))
+            .map(|p| p.1.clone());
+        let targets = name.and_then(|name| ctl.request_wake(&name, TriggerSource::HttpApi));
+        match targets {
+            Some(targets) => {
+                info!("[API] Wake request: {:?}", targets);
+                send_json(req, 202, &format!("{{\"result\":\"queued\",\"targets\":[{}]}}", target_names_json(&targets)))
+            },
+            None => send_json(req, 400, "{\"error\":\"unknown target or group\"}"),
+        }
+    })?;
+
+    let ctl = control.clone();
+    server.fn_handler::<anyhow::Error, _>("/api/status", Method::Get, move |req| {
+        let status = ctl.get_status();
+        send_json(req, 200, &format!(
+            "{{\"battery_voltage\":{:.2},\"rssi\":{},\"ip\":\"{}\",\"send_count\":{}}}",
+            status.battery_voltage, status.rssi, status.ip_addr, status.send_count))
+    })?;
+
+    let ctl = control.clone();
+    server.fn_handler::<anyhow::Error, _>("/api/config", Method::Get, move |req| {
+        let entries : Vec<String> = ctl.get_public_config_entries().iter().map(|it| {
+            format!("\"{}\":\"{}\"", json_escape(&it.0), json_escape(&it.1))
+        }).collect();
+        send_json(req, 200, &format!("{{{}}}", entries.join(",")))
+    })?;
+
+    let ctl = control.clone();
+    let tok = token.to_string();
+    server.fn_handler::<anyhow::Error, _>("/api/config", Method::Post, move |mut req| {
+        if let Some(status) = auth_error(&req, &tok) {
+            return send_auth_error(req, status);
+        }
+        let changes = parse_form(&read_body(&mut req)?);
+        let updated = match ctl.request_public_config_update(&changes) {
+            Ok(updated) => updated,
+            Err(rejected) => {
+                let keys : Vec<String> = rejected.iter().map(|k| format!("\"{}\"", json_escape(k))).collect();
+                return send_json(req, 400, &format!("{{\"error\":\"invalid value\",\"keys\":[{}]}}", keys.join(",")));
+            },
+        };
+        if updated.is_empty() {
+            return send_json(req, 400, "{\"error\":\"no known writable config keys\"}");
+        }
+        info!("[API] Config update: {:?}", updated);
+        let keys : Vec<String> = updated.iter().map(|k| format!("\"{}\"", json_escape(k))).collect();
+        send_json(req, 200, &format!("{{\"updated\":[{}],\"restart\":true}}", keys.join(",")))
+    })?;
+
+    let ctl = control.clone();
+    let tok = token.to_string();
+    server.fn_handler::<anyhow::Error, _>("/api/ota", Method::Post, move |mut req| {
+        if let Some(status) = auth_error(&req, &tok) {
+            return send_auth_error(req, status);
+        }
+        let mut params = query_params(req.uri());
+        params.extend(parse_form(&read_body(&mut req)?));
+        let url = params.iter().find(|p| p.0 == "url").map(|p| p.1.clone())
+            .unwrap_or(config_value(&ctl.get_config_entries(), "OTAURL"));
+        if url.is_empty() {
+            return send_json(req, 400, "{\"error\":\"no url\"}");
+        }
+        info!("[API] OTA request: {}", url);
+        ctl.push_ota_request(&url);
+        send_json(req, 202, &format!("{{\"result\":\"queued\",\"url\":\"{}\"}}", json_escape(&url)))
+    })?;
+
+    let ctl = control.clone();
+    let tok = token.to_string();
+    server.fn_handler::<anyhow::Error, _>("/api/ota/upload", Method::Post, move |mut req| {
+        if let Some(status) = auth_error(&req, &tok) {
+            return send_auth_error(req, status);
+        }
+        let total = req.content_len().map(|l| l as usize);
+        info!("[API] OTA upload: {:?} bytes", total);
+        ctl.set_ota_progress(Some(ota::progress_text(0, total)));
+        let result = ota::write_image(&mut req, total, |written, total| {
+            ctl.set_ota_progress(Some(ota::progress_text(written, total)));
+        });
+        ctl.set_ota_progress(None);
+        match result {
+            Ok(_) => {
+                send_json(req, 200, "{\"result\":\"ok\",\"restart\":true}")?;
+                ctl.request_restart();
+                Ok(())
+            },
+            Err(e) => {
+                info!("[API] OTA upload failed: {:?}", e);
+                send_json(req, 500, &format!("{{\"error\":\"{}\"}}", json_escape(&e.to_string())))
+            },
+        }
+    })?;
+
+    info!("[API] HTTP server started");
+    Ok(server)
+}
+
+pub fn send_json(req: Request<&mut EspHttpConnection>, status: u16, body: &str) -> Result<()> {
+    req.into_response(status, None, &[("Content-Type", "application/json")])?
+        .write_all(body.as_bytes())?;
+    Ok(())
+}
+
+/// HTTP status to refuse a request with: 403 if no token is configured,
+/// 401 if the request does not carry it.
+fn auth_error(req: &Request<&mut EspHttpConnection>, token: &str) -> Option<u16> {
+    if token.is_empty() {
+        return Some(403);
+    }
+    let given = req.header("Authorization").and_then(|v| v.strip_prefix("Bearer ")).unwrap_or("");
+    if constant_time_eq(given.trim().as_bytes(), token.as_bytes()) { None } else { Some(401) }
+}
+
+fn send_auth_error(req: Request<&mut EspHttpConnection>, status: u16) -> Result<()> {
+    info!("[API] Refused {} {}", req.uri(), status);
+    if status == 403 {
+        send_json(req, 403, "{\"error\":\"API token not set\"}")
+    }
+    else {
+        send_json(req, 401, "{\"error\":\"missing or wrong token\"}")
+    }
+}
+
+pub fn read_body(req: &mut Request<&mut EspHttpConnection>) -> Result<String> {
+    let len = (req.content_len().unwrap_or(0) as usize).min(MAX_BODY_SIZE);
+    let mut buf = vec![0u8; len];
+    let mut read = 0;
+    while read < len {
+        let n = req.read(&mut buf[read..])?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+    Ok(String::from_utf8_lossy(&buf[..read]).into_owned())
+}
+
+pub fn query_params(uri: &str) -> Vec<(String, String)> {
+    match uri.split_once('?') {
+        Some((_, query)) => parse_form(query),
+        None => Vec::new(),
+    }
+}
+
+fn target_names_json(targets: &Vec<TargetDevice>) -> String {
+    targets.iter().map(|t| format!("\"{}\"", t.name())).collect::<Vec<String>>().join(",")
+}
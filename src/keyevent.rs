@@ -1,324 +1,167 @@
 use log::*;
-use std::{thread, time::Duration, sync::Arc, sync::Mutex, sync::atomic::AtomicBool};
+use std::{thread, time::Duration, sync::Arc, sync::Condvar, sync::Mutex};
+use std::collections::VecDeque;
 use esp_idf_hal::{gpio::*};
-use std::sync::atomic::Ordering;
-use std::time::SystemTime;
+use std::time::Instant;
 
-type PINDRIVER4 = Box<PinDriver<'static, esp_idf_hal::gpio::Gpio4, esp_idf_hal::gpio::Input>>; // GPIO4
-type PINDRIVER5 = Box<PinDriver<'static, esp_idf_hal::gpio::Gpio5, esp_idf_hal::gpio::Input>>; // GPIO5
-type PINDRIVER6 = Box<PinDriver<'static, esp_idf_hal::gpio::Gpio6, esp_idf_hal::gpio::Input>>; // GPIO6
-type PINDRIVER9 = Box<PinDriver<'static, esp_idf_hal::gpio::Gpio9, esp_idf_hal::gpio::Input>>; // GPIO9
-type PINDRIVER10 = Box<PinDriver<'static, esp_idf_hal::gpio::Gpio10, esp_idf_hal::gpio::Input>>; // GPIO10
+use crate::autorepeat::AutoRepeat;
+use crate::debounce::Debouncer;
+use crate::gesture::{Gesture, GestureConfig, GestureDetector};
 
-static GPIO4_FLAG: AtomicBool = AtomicBool::new(false);
-static GPIO5_FLAG: AtomicBool = AtomicBool::new(false);
-static GPIO6_FLAG: AtomicBool = AtomicBool::new(false);
-static GPIO9_FLAG: AtomicBool = AtomicBool::new(false);
-static GPIO10_FLAG: AtomicBool = AtomicBool::new(false);
+/// Input pin of one key; any GPIO (`PinDriver::input(pin.downgrade())`).
+pub type KeyPin = PinDriver<'static, AnyIOPin, Input>;
 
-const GARD_TIME: u128 = 20;    // if the time difference is less than 30ms, ignore the interrupt
+const DEBOUNCE_TIME: u64 = 20;  // ms a new level must be stable
 const KEY_SLEEP_TIME: u64 = 10; // scan the key every 10ms
+const KEY_QUEUE_LEN: usize = 32; // oldest events are dropped beyond this
+const GESTURE_QUEUE_LEN: usize = 8; // same for gestures
 
-#[allow(dead_code)]
-pub enum Key {
-    Up,
-    Down,
-    Left,
-    Right,
-    Center,
-}
+pub use wakepcbox_core::keys::{Key, KeyEvent, ALL_KEYS};
 
+/// A key event with its timing. `time` is when the key went down (ms since the
+/// key thread started), `duration` how long it was held (0 for a key down).
 #[derive(Debug, Clone, Copy)]
-pub enum KeyEvent {
-    UpKeyDown,
-    UpKeyUp,
-    DownKeyDown,
-    DownKeyUp,
-    LeftKeyDown,
-    LeftKeyUp,
-    RightKeyDown,
-    RightKeyUp,
-    CenterKeyDown,
-    CenterKeyUp,
+pub struct TimedKeyEvent {
+    pub event: KeyEvent,
+    pub time: u64,
+    pub duration: u32,
 }
 
 struct KeyState {
-    up: bool,
-    up_count: u32,
-    down: bool,
-    down_count: u32,
-    left: bool,
-    left_count: u32,
-    right: bool,
-    right_count: u32,
-    center: bool,
-    center_count: u32,
-    key_envet: Vec<KeyEvent>,
+    pressed: [bool; ALL_KEYS.len()],
+    press_time: [u32; ALL_KEYS.len()],  // ms the last press lasted
+    key_events: VecDeque<TimedKeyEvent>,
+    dropped: u32,
+    gestures: VecDeque<Gesture>,
+    detector: GestureDetector,
+    auto_repeat: bool,
     key_sleep: bool,
 }
 
+// One row of the key table
+struct KeyInput {
+    key: Key,
+    pin: KeyPin,
+    debouncer: Debouncer,
+    repeat: AutoRepeat,
+    pressed_at: u64,
+}
+
+impl KeyState {
+    fn push_event(&mut self, event: TimedKeyEvent) {
+        if self.key_events.len() >= KEY_QUEUE_LEN {
+            self.key_events.pop_front();
+            self.dropped += 1;
+            // nobody reads raw events outside the menu, so log only now and then
+            if self.dropped % KEY_QUEUE_LEN as u32 == 1 {
+                info!("Key event queue full, {} dropped", self.dropped);
+            }
+        }
+        self.key_events.push_back(event);
+    }
+
+    fn push_gestures(&mut self, gestures: Vec<Gesture>) {
+        for gesture in gestures {
+            if self.gestures.len() >= GESTURE_QUEUE_LEN {
+                let dropped = self.gestures.pop_front();
+                info!("Gesture queue full, {:?} dropped", dropped);
+            }
+            self.gestures.push_back(gesture);
+        }
+    }
+}
+
 pub struct KeySwitch {
-    state: Arc<Mutex<KeyState>>
+    state: Arc<Mutex<KeyState>>,
+    signal: Arc<Condvar>,   // notified when events or gestures are queued
 }
 
 #[allow(dead_code)]
 impl KeySwitch {
     pub fn new() -> KeySwitch {
         KeySwitch { state: Arc::new(Mutex::new(
-            KeyState { 
-                up :    false,  up_count:       0,
-                down:   false,  down_count:     0,
-                left:   false,  left_count:     0,
-                right:  false,  right_count:    0,
-                center: false,  center_count:   0,
-                key_envet: Vec::new(),
+            KeyState {
+                pressed: [false; ALL_KEYS.len()],
+                press_time: [0; ALL_KEYS.len()],
+                key_events: VecDeque::with_capacity(KEY_QUEUE_LEN),
+                dropped: 0,
+                gestures: VecDeque::with_capacity(GESTURE_QUEUE_LEN),
+                detector: GestureDetector::new(GestureConfig::default()),
+                auto_repeat: false,
                 key_sleep: false,
-            }))}
-    }
-
-    // UP: GPIO4
-    pub fn gpio4_interrupt_handler() {
-        GPIO4_FLAG.store(true, Ordering::Relaxed);
-    }
-
-    // DOWN: GPIO5
-    pub fn gpio5_interrupt_handler() {
-        GPIO5_FLAG.store(true, Ordering::Relaxed);
-    }
-
-    // RIGHT: GPIO6
-    pub fn gpio6_interrupt_handler() {
-        GPIO6_FLAG.store(true, Ordering::Relaxed);
-    }
-
-    // CENTER: GPIO9
-    pub fn gpio9_interrupt_handler() {
-        GPIO9_FLAG.store(true, Ordering::Relaxed);
-    }
-
-    // LEFT: GPIO10
-    pub fn gpio10_interrupt_handler() {
-        GPIO10_FLAG.store(true, Ordering::Relaxed);
+            })),
+            signal: Arc::new(Condvar::new()),
+        }
     }
 
-    pub fn start(&mut self,
-            mut gpio4_sig : PINDRIVER4,
-            mut gpio5_sig : PINDRIVER5,
-            mut gpio6_sig : PINDRIVER6,
-            mut gpio9_sig : PINDRIVER9,
-            mut gpio10_sig : PINDRIVER10)
+    /// Start the key thread. Keys are sampled every `KEY_SLEEP_TIME` and
+    /// debounced by level (low = pressed, with pull-ups).
+    pub fn start(&mut self, pins: Vec<(Key, KeyPin)>)
     {
         let state = self.state.clone();
+        let signal = self.signal.clone();
         let _th = thread::spawn(move || {
-            info!("Start Switch Read Thread.");            
-            gpio4_sig.set_pull(Pull::Up).unwrap();
-            gpio5_sig.set_pull(Pull::Up).unwrap();
-            gpio6_sig.set_pull(Pull::Up).unwrap();
-            gpio9_sig.set_pull(Pull::Up).unwrap();
-            gpio10_sig.set_pull(Pull::Up).unwrap();
-
-            gpio4_sig.set_interrupt_type(InterruptType::AnyEdge).unwrap();
-            gpio5_sig.set_interrupt_type(InterruptType::AnyEdge).unwrap();
-            gpio6_sig.set_interrupt_type(InterruptType::AnyEdge).unwrap();
-            gpio9_sig.set_interrupt_type(InterruptType::AnyEdge).unwrap();
-            gpio10_sig.set_interrupt_type(InterruptType::AnyEdge).unwrap();
-
-            unsafe {
-                gpio4_sig.subscribe(KeySwitch::gpio4_interrupt_handler).unwrap();
-                gpio5_sig.subscribe(KeySwitch::gpio5_interrupt_handler).unwrap();
-                gpio6_sig.subscribe(KeySwitch::gpio6_interrupt_handler).unwrap();
-                gpio9_sig.subscribe(KeySwitch::gpio9_interrupt_handler).unwrap();
-                gpio10_sig.subscribe(KeySwitch::gpio10_interrupt_handler).unwrap();
-            }        
-
-            gpio4_sig.enable_interrupt().unwrap();
-            gpio5_sig.enable_interrupt().unwrap();
-            gpio6_sig.enable_interrupt().unwrap();
-            gpio9_sig.enable_interrupt().unwrap();
-            gpio10_sig.enable_interrupt().unwrap();
-
-            // caluculate the time difference between the last interrupt and now
-            // if the time differrence is less than 1sec, igonore the interrupt
-            let mut last_interrupt_time_up = SystemTime::now();
-            let mut last_interrupt_time_down = SystemTime::now();
-            let mut last_interrupt_time_right = SystemTime::now();
-            let mut last_interrupt_time_center = SystemTime::now();
-            let mut last_interrupt_time_left = SystemTime::now();
+            info!("Start Switch Read Thread.");
+            let mut inputs : Vec<KeyInput> = pins.into_iter().map(|(key, mut pin)| {
+                pin.set_pull(Pull::Up).unwrap();
+                // start released: a key held at boot (e.g. the one that woke the box) counts as a press
+                let debouncer = Debouncer::new(DEBOUNCE_TIME, false);
+                KeyInput { key, pin, debouncer, repeat: AutoRepeat::default(), pressed_at: 0 }
+            }).collect();
+            let start = Instant::now();
             loop {
                 let mut lck = state.lock().unwrap();
                 if lck.key_sleep == true {
-                    lck.key_envet.clear();
+                    lck.key_events.clear();
+                    lck.gestures.clear();
+                    lck.detector.reset();
                     drop(lck);
                     thread::sleep(Duration::from_millis(KEY_SLEEP_TIME));
                     continue;
                 }
-
-                // UP: GPIO4
-                if GPIO4_FLAG.load(Ordering::Relaxed) {
-                    match last_interrupt_time_up.elapsed(){
-                        Ok(elapsed) => {
-                            if elapsed.as_millis() > GARD_TIME {
-                                if lck.up == false {
-                                    lck.up = true;
-                                    lck.up_count = 0;
-                                    lck.key_envet.push(KeyEvent::UpKeyDown);
-                                    // info!("PUSH_NOTIFICATION_UP_KEY_DOWN");
-                                }
-                                else {
-                                    lck.up = false;
-                                    lck.up_count = match last_interrupt_time_up.elapsed() {
-                                        Ok(elapsed) => elapsed.as_millis() as u32,
-                                        Err(e) => {
-                                            error!("Error: {:?}", e);
-                                            0
-                                        }
-                                    };
-                                    lck.key_envet.push(KeyEvent::UpKeyUp);
-                                    // info!("PUSH_NOTIFICATION_UP_KEY_UP {}", lck.up_count);
-                                }
-                                last_interrupt_time_up = SystemTime::now();
-                            }
-                        },
-                        Err(e) => {
-                            error!("Error: {:?}", e);
+                let now = start.elapsed().as_millis() as u64;
+                let mut notify = false;
+                for input in inputs.iter_mut() {
+                    let index = input.key.index();
+                    if let Some(pressed) = input.debouncer.sample(now, input.pin.is_low()) {
+                        lck.pressed[index] = pressed;
+                        let mut repeated = false;
+                        let gestures = if pressed {
+                            input.pressed_at = now;
+                            input.repeat.press(now);
+                            lck.detector.key_down(input.key, now)
                         }
-                    }
-                    GPIO4_FLAG.store(false, Ordering::Relaxed);
-                    gpio4_sig.enable_interrupt().unwrap();
-                }
-                // DOWN: GPIO5
-                if GPIO5_FLAG.load(Ordering::Relaxed) {
-                    match last_interrupt_time_down.elapsed() {
-                        Ok(elapsed) => {
-                            if elapsed.as_millis() > GARD_TIME {
-                                if lck.down == false {
-                                    lck.down = true;
-                                    lck.down_count = 0;
-                                    lck.key_envet.push(KeyEvent::DownKeyDown);
-                                    // info!("PUSH_NOTIFICATION_DOWN_KEY_DOWN");
-                                }
-                                else {
-                                    lck.down = false;
-                                    lck.down_count = match last_interrupt_time_down.elapsed() {
-                                        Ok(elapsed) => elapsed.as_millis() as u32,
-                                        Err(e) => {
-                                            error!("Error: {:?}", e);
-                                            0
-                                        }
-                                    };
-                                    lck.key_envet.push(KeyEvent::DownKeyUp);
-                                    // info!("PUSH_NOTIFICATION_DOWN_KEY_UP {}", lck.down_count);
-                                }
-                                last_interrupt_time_down = SystemTime::now();
-                            }
-                        },
-                        Err(e) => {
-                            error!("Error: {:?}", e);
+                        else {
+                            lck.press_time[index] = now.saturating_sub(input.pressed_at) as u32;
+                            repeated = input.repeat.release() > 0;
+                            lck.detector.key_up(input.key, now)
+                        };
+                        // after repeats the release itself is not another step
+                        if !repeated {
+                            let duration = if pressed { 0 } else { lck.press_time[index] };
+                            lck.push_event(TimedKeyEvent { event: KeyEvent::new(input.key, pressed), time: input.pressed_at, duration });
+                            notify = true;
                         }
+                        notify |= !gestures.is_empty();
+                        lck.push_gestures(gestures);
                     }
-                    GPIO5_FLAG.store(false, Ordering::Relaxed);
-                    gpio5_sig.enable_interrupt().unwrap();
-                }
-                // RIGHT: GPIO6
-                if GPIO6_FLAG.load(Ordering::Relaxed) {
-                    match last_interrupt_time_right.elapsed() {
-                        Ok(elapsed) => {
-                            if elapsed.as_millis() > GARD_TIME {
-                                if lck.right == false {
-                                    lck.right = true;
-                                    lck.right_count = 0;
-                                    lck.key_envet.push(KeyEvent::RightKeyDown);
-                                    // info!("PUSH_NOTIFICATION_RIGHT_KEY_DOWN");
-                                }
-                                else {
-                                    lck.right = false;
-                                    lck.right_count = match last_interrupt_time_right.elapsed() {
-                                        Ok(elapsed) => elapsed.as_millis() as u32,
-                                        Err(e) => {
-                                            error!("Error: {:?}", e);
-                                            0
-                                        }
-                                    };
-                                    lck.key_envet.push(KeyEvent::RightKeyUp);
-                                    // info!("PUSH_NOTIFICATION_RIGHT_KEY_UP {}", lck.right_count);
-                                }
-                                last_interrupt_time_right = SystemTime::now();
-                            }
-                        },
-                        Err(e) => {
-                            error!("Error: {:?}", e);
+                    // Up/Down held in an editor: repeat the release event the menu acts on
+                    if lck.auto_repeat && lck.pressed[index] && (input.key == Key::Up || input.key == Key::Down) {
+                        if input.repeat.poll(now) {
+                            let duration = now.saturating_sub(input.pressed_at) as u32;
+                            lck.push_event(TimedKeyEvent { event: KeyEvent::new(input.key, false), time: input.pressed_at, duration });
+                            notify = true;
                         }
                     }
-                    GPIO6_FLAG.store(false, Ordering::Relaxed);
-                    gpio6_sig.enable_interrupt().unwrap();
-                }
-                // CENTER: GPIO9
-                if GPIO9_FLAG.load(Ordering::Relaxed) {
-                    match last_interrupt_time_center.elapsed() {
-                        Ok(elapsed) => {
-                            if elapsed.as_millis() > GARD_TIME {
-                                if lck.center == false {
-                                    lck.center = true;
-                                    lck.center_count = 0;
-                                    lck.key_envet.push(KeyEvent::CenterKeyDown);
-                                    // info!("PUSH_NOTIFICATION_CENTER_KEY_DOWN");
-                                }
-                                else {
-                                    lck.center = false;
-                                    lck.center_count = match last_interrupt_time_center.elapsed() {
-                                        Ok(elapsed) => elapsed.as_millis() as u32,
-                                        Err(e) => {
-                                            error!("Error: {:?}", e);
-                                            0
-                                        }
-                                    };
-                                    lck.key_envet.push(KeyEvent::CenterKeyUp);
-                                    // info!("PUSH_NOTIFICATION_CENTER_KEY_UP {}", lck.center_count);
-                                }
-                                last_interrupt_time_center = SystemTime::now();
-                            }
-                        },
-                        Err(e) => {
-                            error!("Error: {:?}", e);
-                        }
-                    }
-                    GPIO9_FLAG.store(false, Ordering::Relaxed);
-                    gpio9_sig.enable_interrupt().unwrap();
-                }
-                // LEFT: GPIO10
-                if GPIO10_FLAG.load(Ordering::Relaxed) {
-                    match last_interrupt_time_left.elapsed() {
-                        Ok(elapsed) => {
-                            if elapsed.as_millis() > GARD_TIME {
-                                if lck.left == false {
-                                    lck.left = true;
-                                    lck.left_count = 0;
-                                    lck.key_envet.push(KeyEvent::LeftKeyDown);
-                                    // info!("PUSH_NOTIFICATION_LEFT_KEY_DOWN");
-                                }
-                                else {
-                                    lck.left = false;
-                                    lck.left_count = match last_interrupt_time_left.elapsed() {
-                                        Ok(elapsed) => elapsed.as_millis() as u32,
-                                        Err(e) => {
-                                            error!("Error: {:?}", e);
-                                            0
-                                        }
-                                    };
-                                    lck.key_envet.push(KeyEvent::LeftKeyUp);
-                                    // info!("PUSH_NOTIFICATION_LEFT_KEY_UP {}", lck.left_count);
-                                }
-                                last_interrupt_time_left = SystemTime::now();
-                            }
-                        },
-                        Err(e) => {
-                            error!("Error: {:?}", e);
-                        }
-                    }
-                    GPIO10_FLAG.store(false, Ordering::Relaxed);
-                    gpio10_sig.enable_interrupt().unwrap();
                 }
+                // long presses and clicks after the double press window
+                let gestures = lck.detector.poll(now);
+                notify |= !gestures.is_empty();
+                lck.push_gestures(gestures);
                 drop(lck);
+                if notify {
+                    signal.notify_all();
+                }
                 thread::sleep(Duration::from_millis(KEY_SLEEP_TIME));
             }
         });
@@ -327,69 +170,51 @@ impl KeySwitch {
     pub fn get_current_button_state(&mut self, button: Key) -> bool
     {
         let lock= self.state.lock().unwrap();
-        match button {
-            Key::Up => {
-                let ret = lock.up;
-                ret
-            },
-            Key::Down => {
-                let ret = lock.down;
-                ret
-            },
-            Key::Left => {
-                let ret = lock.left;
-                ret
-            },
-            Key::Right => {
-                let ret = lock.right;
-                ret
-            },
-            Key::Center => {
-                let ret = lock.center;
-                ret
-            },
-        }
+        lock.pressed[button.index()]
     }
 
+    /// Duration of the last completed press in ms.
     pub fn get_button_press_time(&mut self, button: Key) -> u32
     {
         let lock= self.state.lock().unwrap();
-        match button {
-            Key::Up => {
-                let ret = lock.up_count;
-                ret
-            },
-            Key::Down => {
-                let ret = lock.down_count;
-                ret
-            },
-            Key::Left => {
-                let ret = lock.left_count;
-                ret
-            },
-            Key::Right => {
-                let ret = lock.right_count;
-                ret
-            },
-            Key::Center => {
-                let ret = lock.center_count;
-                ret
-            },
-        }
+        lock.press_time[button.index()]
     }
 
     pub fn clear_all_button_event(&mut self)
     {
         let mut lock= self.state.lock().unwrap();
-        lock.key_envet.clear();
+        lock.key_events.clear();
+        lock.gestures.clear();
+    }
+
+    /// Wait up to `timeout` for gestures (click, long press, double press, chord)
+    /// and take them, oldest first. Raw key events stay queued for `wait_key_events`.
+    pub fn wait_gestures(&mut self, timeout: Duration) -> Vec<Gesture>
+    {
+        let lock= self.state.lock().unwrap();
+        let (mut lock, _) = self.signal.wait_timeout_while(lock, timeout, |s| s.gestures.is_empty()).unwrap();
+        lock.gestures.drain(..).collect()
+    }
+
+    /// Wait up to `timeout` for key events and take them, oldest first.
+    pub fn wait_key_events(&mut self, timeout: Duration) -> Vec<TimedKeyEvent>
+    {
+        let lock= self.state.lock().unwrap();
+        let (mut lock, _) = self.signal.wait_timeout_while(lock, timeout, |s| s.key_events.is_empty()).unwrap();
+        lock.key_events.drain(..).collect()
+    }
+
+    /// Enable auto-repeat of Up/Down while held (in the value editors).
+    pub fn set_auto_repeat(&mut self, enable: bool)
+    {
+        let mut lock= self.state.lock().unwrap();
+        lock.auto_repeat = enable;
     }
 
-    pub fn get_key_event_and_clear(&mut self) -> Vec<KeyEvent>
+    pub fn set_gesture_config(&mut self, config: GestureConfig)
     {
         let mut lock= self.state.lock().unwrap();
-        let ret = lock.key_envet.clone();
-        lock.key_envet.clear();
-        ret
+        lock.detector.set_config(config);
     }
 
     pub fn set_key_sleep(&mut self, sleep: bool)
@@ -11,7 +11,7 @@ use esp_idf_svc::wifi::EspWifi;
 use log::*;
 
 use std::net::Ipv4Addr;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 mod wifi;
 mod displayctl;
@@ -19,10 +19,30 @@ mod keyevent;
 mod wakepacket;
 mod ui;
 mod config;
+mod provision;
+mod httpapi;
+mod mqtt;
+mod wifimgr;
+mod diag;
+mod storage;
+mod hostname;
+mod ota;
+mod syslog;
+mod webhook;
+mod securewake;
+mod metrics;
+mod timesvc;
+mod pinlock;
+
+use wakepcbox_core::{autorepeat, buttons, control, debounce, gesture, shell};
 
 use displayctl::{DisplayPanel, WiFiStatus, MessageTypes};
-use keyevent::{KeySwitch, KeyEvent};
-use config::ConfigData;
+use keyevent::{Key, KeySwitch};
+use gesture::GestureConfig;
+use buttons::{ButtonAction, ButtonMap};
+use pinlock::{PinLock, PinMode, PinResult};
+use config::{ConfigData, NUM_OF_PROFILES};
+use control::{mask_secrets, Control, TriggerSource};
 
 const SLEEP_MODE_LIGHT : &'static str = "light";
 #[allow(dead_code)]
@@ -35,11 +55,14 @@ const GPIO_WAKEUP_INT_PIN_9 : i32 = 9;
 const GPIO_WAKEUP_INT_PIN_10 : i32 = 10;
 
 const GPIO_WAKEUP_INT_PIN : u64 = 16 + 32;
-const MAX_NVS_STR_SIZE : usize = 3072;
+const MAX_NVS_STR_SIZE : usize = 4000;     // longest NVS string, including the terminating NUL
+const PIN_ENTRY_TIMEOUT : Duration = Duration::from_secs(30);
+const MAIN_LOOP_INTERVAL : Duration = Duration::from_secs(1);    // longest wait for a button
+const MENU_POLL_INTERVAL : Duration = Duration::from_millis(100);
 
 fn main() -> anyhow::Result<()> {
     esp_idf_sys::link_patches();
-    esp_idf_svc::log::EspLogger::initialize_default();
+    syslog::initialize();
 
     // Peripherals Initialize
     let peripherals = Peripherals::take().unwrap();
@@ -50,11 +73,11 @@ fn main() -> anyhow::Result<()> {
     let right_key = peripherals.pins.gpio6;
     let enter_key = peripherals.pins.gpio9;
 
-    let upkey_sig = Box::new(PinDriver::input(up_key)?);
-    let downkey_sig = Box::new(PinDriver::input(down_key)?);
-    let leftkey_sig = Box::new(PinDriver::input(left_key)?);
-    let rightkey_sig = Box::new(PinDriver::input(right_key)?);
-    let enterkey_sig = Box::new(PinDriver::input(enter_key)?);
+    let upkey_sig = PinDriver::input(up_key.downgrade())?;
+    let downkey_sig = PinDriver::input(down_key.downgrade())?;
+    let leftkey_sig = PinDriver::input(left_key.downgrade())?;
+    let rightkey_sig = PinDriver::input(right_key.downgrade())?;
+    let enterkey_sig = PinDriver::input(enter_key.downgrade())?;
 
     // Display Initialize
     let i2c = peripherals.i2c0;
@@ -96,11 +119,22 @@ fn main() -> anyhow::Result<()> {
 
 
     let mut keysw = KeySwitch::new();
-    keysw.start(upkey_sig, downkey_sig, rightkey_sig, enterkey_sig, leftkey_sig);
+    keysw.start(vec![
+        (Key::Up, upkey_sig),
+        (Key::Down, downkey_sig),
+        (Key::Left, leftkey_sig),
+        (Key::Right, rightkey_sig),
+        (Key::Center, enterkey_sig),
+    ]);
 
     // Initialize Configuration Data
     let mut config_data = ConfigData::new();
 
+    // Files in flash (e.g. CA certificate for WPA2-Enterprise)
+    if let Err(ref e) = storage::mount() {
+        info!("Storage mount failed {:?}", e);
+    }
+
     // Initialize NVS
     let nvs_default_partition = EspNvsPartition::<NvsDefault>::take().unwrap();
     let mut nvs = match EspNvs::new(nvs_default_partition, "storage", true) {
@@ -112,55 +146,115 @@ fn main() -> anyhow::Result<()> {
 
     // Load config
     let mut nvs_buf : [u8 ; MAX_NVS_STR_SIZE] = [0; MAX_NVS_STR_SIZE];
-    let nvs_value = match nvs.get_str("config", &mut nvs_buf){
-        Ok(value) => { info!("Try to read NVS config"); value },
-        Err(ref e) => { info!("NVS config not found {:?}", e); None }
-    };
-    if nvs_value == None {
-        info!("NVS config not found. Set default config");
-        set_default_config(&mut config_data, &mut nvs);
-        dp.set_main_msg(&"Using default settings.".to_string(), MessageTypes::Status);
-        thread::sleep(Duration::from_millis(1000));
-    }
-    else {
-        info!("NVS config found {:?}", nvs_value);
-        match config_data.load_config(nvs_value) {
-            Ok(_) => { info!("Config load success"); },
-            Err(ref e) => { 
-                info!("Config load failed {:?}", e);
-                dp.set_main_msg(&"Using default settings.".to_string(), MessageTypes::Status);
-                set_default_config(&mut config_data, &mut nvs);
-                thread::sleep(Duration::from_millis(1000));
-            },
-        }    
+    // Only a missing config is replaced with the defaults in NVS. A config that cannot be
+    // read or parsed is left as it is, and the defaults are used until the next save.
+    match nvs.get_str("config", &mut nvs_buf) {
+        Ok(None) => {
+            info!("NVS config not found. Set default config");
+            set_default_config(&mut config_data, &mut nvs);
+            dp.set_main_msg(&"Using default settings.".to_string(), MessageTypes::Status);
+            thread::sleep(Duration::from_millis(1000));
+        },
+        Ok(nvs_value) => {
+            info!("NVS config found ({} bytes)", nvs_value.map(|v| v.len()).unwrap_or(0));
+            match config_data.load_config(nvs_value) {
+                Ok(_) => { info!("Config load success"); },
+                Err(ref e) => {
+                    info!("Config load failed {:?}", e);
+                    dp.set_main_msg(&"Using default settings.".to_string(), MessageTypes::Status);
+                    load_default_config(&mut config_data);
+                    thread::sleep(Duration::from_millis(1000));
+                },
+            }
+        },
+        Err(ref e) => {
+            info!("NVS config read failed {:?}", e);
+            dp.set_main_msg(&"Config read error.".to_string(), MessageTypes::Error);
+            load_default_config(&mut config_data);
+            thread::sleep(Duration::from_millis(1000));
+        },
     }
 
+    // Load profiles
+    load_profiles(&mut config_data, &mut nvs);
+    info!("Profile: {}", config_data.profile_name);
+
     dp.initialize_menu(&config_data);
+    dp.set_profile_name(&config_data.profile_name);
+    let hostname = hostname::resolve_hostname(&config_data.hostname);
+    dp.set_hostname(&hostname);
     info!("SSID: {}", config_data.wifi_ssid);
 
     // Initialize Wakepacket
     let mut wp = wakepacket::WakePacket::new();
-    wp.set_target_mac(wakepacket::TargetDevice::Device1, &config_data.target_mac_address1);
-    wp.set_target_mac(wakepacket::TargetDevice::Device2, &config_data.target_mac_address2);
-    wp.set_target_mac(wakepacket::TargetDevice::Device3, &config_data.target_mac_address3);
-    wp.set_target_mac(wakepacket::TargetDevice::Device4, &config_data.target_mac_address4);
+    for target in wakepacket::ALL_TARGETS {
+        if let Err(ref e) = wp.set_target_mac(target, &target_mac(&config_data, target)) {
+            info!("{:?}", e);
+        }
+    }
     // Set Timezone
     dp.set_timezone_offset(config_data.timezone_offset);
+    keysw.set_gesture_config(GestureConfig {
+        long_press: config_data.long_press_time,
+        double_press: config_data.double_press_time,
+        chord: config_data.chord_time,
+    });
+    let button_map = ButtonMap::new(&config_data.button_actions);
+    // PIN lock; without a PIN set nothing is locked
+    let pin_clock = Instant::now();
+    let pin_mode = if config_data.pin_hash.is_empty() { PinMode::Off } else { PinMode::parse(&config_data.pin_lock) };
+    let mut pin_lock = PinLock::new(pinlock::load_failures(&nvs), 0);
+    let pin_targets = pin_protected_targets(&config_data, pin_mode);
     dp.set_initial_logo(false);
 
+    // Setup portal: no SSID configured and WPS is off
+    if !config_data.wps_enable && config_data.wifi_ssid.is_empty() {
+        dp.set_main_msg(&"Setup Mode".to_string(), MessageTypes::Status);
+        let portal_result = provision::run_portal(
+            peripherals.modem,
+            &config_data,
+            |title, msg| {
+                dp.set_main_msg(&title.to_string(), MessageTypes::Status);
+                dp.set_second_msg(&msg.to_string());
+            },
+        );
+        match portal_result {
+            Ok(new_config) => {
+                match save_config(&mut nvs, &new_config, config_data.profile_index) {
+                    Ok(_) => { info!("[SETUP] Settings saved to NVS"); },
+                    Err(ref e) => { info!("[SETUP] Failed to save settings: {:?}", e); },
+                }
+                dp.set_main_msg(&"Restarting...".to_string(), MessageTypes::Status);
+            },
+            Err(ref e) => {
+                info!("[SETUP] Failed: {:?}", e);
+                dp.set_main_msg(&"Setup Failed".to_string(), MessageTypes::Error);
+            },
+        }
+        thread::sleep(Duration::from_secs(3));
+        unsafe {
+            esp_idf_sys::esp_restart();
+        }
+    }
+
     // Initialize WiFi
+    wifi::set_power_settings(wifi::PowerSettings {
+        power_save: wifi::PowerSaveMode::from_str(&config_data.wifi_power_save),
+        listen_interval: config_data.wifi_listen_interval,
+        max_tx_power: config_data.wifi_max_tx_power,
+    });
     dp.set_wifi_status(WiFiStatus::Connecting);
     let mut wifi_dev: Result<Box<EspWifi<'static>>, anyhow::Error>;
     if config_data.wps_enable && config_data.wifi_ssid.is_empty() {
-        // WPS PBC mode: no SSID configured, use WPS to obtain credentials
+        // WPS mode: no SSID configured, use WPS to obtain credentials
+        let wps_mode = wifi::WpsMode::from_str(&config_data.wps_mode);
         dp.set_main_msg(&"WPS Mode".to_string(), MessageTypes::Status);
         dp.set_second_msg(&"Press WPS button".to_string());
         let wps_result = wifi::wifi_connect_wps(
             peripherals.modem,
-            |title, msg| {
-                dp.set_main_msg(&msg.to_string(), MessageTypes::Status);
-                dp.set_second_msg(&title.to_string());
-            },
+            wps_mode,
+            &hostname,
+            |title, msg| show_wps_status(&mut dp, wps_mode, title, msg),
         );
         match wps_result {
             Ok((wps_wifi, wps_ssid, wps_psk)) => {
@@ -168,9 +262,9 @@ fn main() -> anyhow::Result<()> {
                 // Save WPS credentials to NVS for next boot
                 config_data.wifi_ssid = wps_ssid;
                 config_data.wifi_psk = wps_psk;
+                config_data.wifi_auth = "psk".to_string();
                 config_data.wps_enable = false; // next boot: connect normally with saved credentials
-                let toml_string = convert_config_to_toml_string(&config_data.to_config_entries());
-                match nvs.set_str("config", &toml_string) {
+                match save_config(&mut nvs, &config_data.to_config_entries(), config_data.profile_index) {
                     Ok(_) => { info!("[WPS] Credentials saved to NVS"); },
                     Err(ref e) => { info!("[WPS] Failed to save credentials: {:?}", e); },
                 }
@@ -187,30 +281,38 @@ fn main() -> anyhow::Result<()> {
             },
         }
     } else {
-        // Normal SSID+PSK connection
+        // Normal SSID+PSK (or WPA2-Enterprise) connection
         dp.set_main_msg(&"Connecting WiFi..".to_string(), MessageTypes::Status);
         dp.set_second_msg(&format!("AP:{}", config_data.wifi_ssid));
-        wifi_dev = wifi::wifi_connect(peripherals.modem, &config_data.wifi_ssid, &config_data.wifi_psk);
+        wifi_dev = wifi::wifi_connect(peripherals.modem, &config_data.wifi_ssid, &wifi_auth(&config_data), &hostname);
         match &wifi_dev {
             Ok(_) => { dp.set_wifi_status(WiFiStatus::Connected); },
-            Err(ref e) => { info!("{:?}", e); }
+            Err(ref e) => {
+                info!("WiFi connect failed {:?}", e);
+                dp.set_wifi_status(WiFiStatus::Disconnected);
+                dp.set_main_msg(&"WiFi Failed".to_string(), MessageTypes::Error);
+                thread::sleep(Duration::from_secs(2));
+            }
         }
     }
-      
-    // Get my IP address
-    let mut ip_addr : Ipv4Addr; 
-    let mut retry_count : u32 = 0;
-    loop {
-        ip_addr = wifi_dev.as_ref().unwrap().sta_netif().get_ip_info().unwrap().ip;
-        if ip_addr != Ipv4Addr::new(0, 0, 0, 0) {
-            break;
-        }
-        info!("Waiting for WiFi connection...");
-        thread::sleep(Duration::from_secs(1));
-        retry_count += 1;
-        if retry_count > 30 {
-            info!("WiFi connection timeout");
-            break;
+
+    // Get my IP address. Without a Wi-Fi driver the box still runs (menu, buttons)
+    // so the settings can be fixed; network services stay off.
+    let mut ip_addr = Ipv4Addr::UNSPECIFIED;
+    if let Ok(ref wifi) = wifi_dev {
+        let mut retry_count : u32 = 0;
+        loop {
+            ip_addr = wifi.sta_netif().get_ip_info().map(|i| i.ip).unwrap_or(Ipv4Addr::UNSPECIFIED);
+            if ip_addr != Ipv4Addr::UNSPECIFIED {
+                break;
+            }
+            info!("Waiting for WiFi connection...");
+            thread::sleep(Duration::from_secs(1));
+            retry_count += 1;
+            if retry_count > 30 {
+                info!("WiFi connection timeout");
+                break;
+            }
         }
     }
 
@@ -224,95 +326,262 @@ fn main() -> anyhow::Result<()> {
         sync_mode: SyncMode::Immediate,
     };
     let ntp = EspSntp::new(&sntp_conf).unwrap();
+    // Sync state, possibly restored from before deep sleep
+    let mut time_svc = timesvc::TimeService::new(timesvc::SystemClock, timesvc::load_rtc());
+    dp.set_time_valid(time_svc.is_time_valid());
 
     info!("IP address: {}", ip_addr);
     dp.set_second_msg(&format!("IP: {}", ip_addr));
+    let mut wifi_mgr = wifimgr::WifiManager::new(ip_addr);
+
+    // Remote logging; records since boot are buffered until now
+    syslog::start(&config_data.syslog_server, &config_data.syslog_level, &hostname);
+
+    // Shared state for network control surfaces
+    let control = Control::new();
+    control.set_ip_addr(ip_addr);
+    control.set_config_entries(config_data.to_config_entries());
+
+    // HTTP API
+    let _http_server = if config_data.http_api_enable && wifi_dev.is_ok() {
+        match httpapi::start(&config_data.api_token, control.clone()) {
+            Ok(mut server) => {
+                if let Err(ref e) = metrics::register_http(&mut server, control.clone()) {
+                    info!("Metrics endpoint failed {:?}", e);
+                }
+                Some(server)
+            },
+            Err(ref e) => { info!("HTTP API start failed {:?}", e); None }
+        }
+    }
+    else {
+        None
+    };
+
+    // TCP command shell
+    if !config_data.shell_password.is_empty() && wifi_dev.is_ok() {
+        if let Err(ref e) = shell::start(&config_data.shell_password, control.clone(), syslog::tail) {
+            info!("Shell start failed {:?}", e);
+        }
+    }
+
+    // InfluxDB push
+    if !config_data.influx_server.is_empty() && wifi_dev.is_ok() {
+        if let Err(ref e) = metrics::start_influx(&config_data.influx_server, config_data.influx_interval, &hostname, control.clone()) {
+            info!("InfluxDB push start failed {:?}", e);
+        }
+    }
+
+    // Signed UDP wake
+    if !config_data.wake_secret.is_empty() && wifi_dev.is_ok() {
+        if let Err(ref e) = securewake::start(&config_data.wake_secret, control.clone()) {
+            info!("Secure wake start failed {:?}", e);
+        }
+    }
+
+    // mDNS: <hostname>.local
+    let mut mdns_services = Vec::new();
+    if _http_server.is_some() {
+        mdns_services.push(hostname::MdnsService { service_type: "_http", proto: "_tcp", port: 80 });
+    }
+    let _mdns = if wifi_dev.is_ok() {
+        match hostname::start_mdns(&hostname, &mdns_services) {
+            Ok(mdns) => Some(mdns),
+            Err(ref e) => { info!("mDNS start failed {:?}", e); None }
+        }
+    }
+    else {
+        None
+    };
 
-    // NTP Sync
-    let now = SystemTime::now();
-    if now.duration_since(UNIX_EPOCH).unwrap().as_millis() < 1700000000 {
+    // MQTT
+    let mqtt_client = if !config_data.mqtt_url.is_empty() && wifi_dev.is_ok() {
+        let settings = mqtt::MqttSettings {
+            url: config_data.mqtt_url.clone(),
+            username: config_data.mqtt_username.clone(),
+            password: config_data.mqtt_password.clone(),
+            base_topic: config_data.mqtt_topic.clone(),
+        };
+        match mqtt::Mqtt::start(&settings, control.clone()) {
+            Ok(client) => Some(client),
+            Err(ref e) => { info!("MQTT start failed {:?}", e); None }
+        }
+    }
+    else {
+        None
+    };
+
+    // Webhook notifications after wakes
+    let webhook = if !config_data.webhook_url.is_empty() && wifi_dev.is_ok() {
+        let settings = webhook::WebhookSettings {
+            url: config_data.webhook_url.clone(),
+            method: config_data.webhook_method.clone(),
+            template: config_data.webhook_template.clone(),
+        };
+        match webhook::Webhook::start(&settings) {
+            Ok(webhook) => Some(webhook),
+            Err(ref e) => { info!("Webhook start failed {:?}", e); None }
+        }
+    }
+    else {
+        None
+    };
+
+    // NTP Sync (no wait; the clock shows --:-- until synced)
+    if !time_svc.is_time_valid() {
         info!("NTP Sync Start..");
         dp.set_main_msg(&"NTP Syncing..".to_string(), MessageTypes::Status);
-        // No wait for sync
-        // while ntp.get_sync_status() != SyncStatus::Completed {
-        //     thread::sleep(Duration::from_millis(10));
-        // }
-        // let now = SystemTime::now();
-        // let dt_now : DateTime<Utc> = now.into();
-        // let formatted = format!("{}", dt_now.format("%Y-%m-%d %H:%M:%S"));
-        // info!("NTP Sync Completed: {}", formatted);
-    } 
+    }
+
+    // Reached the Ready state: keep a freshly installed image (otherwise the bootloader rolls back)
+    ota::mark_running_valid();
 
     // Main Loop
     let mut send_count : u32 = 0;
-    let mut loop_count : u32 = 0;
-    let mut start_time = SystemTime::now();
+    // idle time counts from the last activity, however often the loop runs
+    let mut idle_since = Instant::now();
     let mut rssi : i32;
     loop {
         // Get Battery Voltage
         let battery_voltage : f32 =  adc_pin.read().unwrap() as f32 * 2.0 / 1000.0;
         dp.set_battery_voltage(battery_voltage);
+        control.set_battery_voltage(battery_voltage);
         // Get RSSI
         rssi = wifi::get_rssi();
         dp.set_wifi_rssi(rssi);
-        if rssi == 0 {
-            wifi_reconnect(&mut wifi_dev.as_mut().unwrap(), &mut dp);
-        }
-        else {
-            dp.set_wifi_status(WiFiStatus::Connected);
+        control.set_rssi(rssi);
+        // Reconnect / DHCP handling is driven by Wi-Fi events
+        if let Ok(ref mut wifi) = wifi_dev {
+            let state = wifi_mgr.poll(wifi);
+            dp.set_wifi_status(wifi_status(state));
+            dp.set_wifi_reason(&wifi_mgr.get_last_reason_text().unwrap_or_default());
+            control.set_reconnect_count(wifi_mgr.get_reconnect_count());
+            let new_ip = wifi_mgr.get_ip_addr();
+            if new_ip != Ipv4Addr::UNSPECIFIED && new_ip != ip_addr {
+                info!("IP address: {}", new_ip);
+                ip_addr = new_ip;
+                control.set_ip_addr(ip_addr);
+            }
         }
 
-        // If need to sync time
-        match start_time.elapsed() {
-            Ok(elapsed) => {
-                if elapsed.as_secs() > 3600 {
-                    info!("NTP Sync Start..");
-                    dp.set_main_msg(&"NTP Syncing..".to_string(), MessageTypes::Status);
-                    let sync_status = ntp.get_sync_status();
-                    if sync_status != SyncStatus::Completed {
-                        start_time = SystemTime::now();
-                    } 
-                }
-            },
-            Err(e) => {
-                info!("Error: {:?}", e);
+        // Time sync state; SNTP polls by itself and is restarted if it went quiet
+        let synced = ntp.get_sync_status() == SyncStatus::Completed;
+        if time_svc.update(synced) {
+            unsafe {
+                esp_idf_sys::esp_sntp_restart();
             }
         }
+        if synced {
+            // kept in RTC memory, so a crash or reset does not lose the time either
+            timesvc::save_rtc(time_svc.persisted());
+        }
+        dp.set_time_valid(time_svc.is_time_valid());
         // Get Key Event
-        let mut target_device : wakepacket::TargetDevice = wakepacket::TargetDevice::None;
+        let mut sleep_targets : Vec<wakepacket::TargetDevice> = Vec::new();
+        let mut force_sleep = false;
         dp.set_main_msg(&"Push Button..".to_string(), MessageTypes::Ready);
-        let key_envet = keysw.get_key_event_and_clear();
-        for it in key_envet {
-            info!("Key Event: {:?}", it);
-            match it {
-                KeyEvent::UpKeyUp | KeyEvent::UpKeyDown => {
-                    target_device = wakepacket::TargetDevice::Device1;
-                    info!("Up key pressed. Target device1");
-                    dp.set_main_msg(&"PC1".to_string(), MessageTypes::Progress);
-                    break;
-                },
-                KeyEvent::DownKeyUp | KeyEvent::DownKeyDown => {
-                    target_device = wakepacket::TargetDevice::Device2;
-                    info!("Down key pressed. Target device2");
-                    dp.set_main_msg(&"PC2".to_string(), MessageTypes::Progress);
+        if let Some(progress) = control.get_ota_progress() {
+            // image upload through the HTTP API in progress
+            dp.set_display_active(true);
+            dp.set_main_msg(&"Updating..".to_string(), MessageTypes::Status);
+            dp.set_second_msg(&progress);
+            idle_since = Instant::now();
+        }
+        // wait for buttons; returns as soon as a gesture is recognized
+        let gestures = keysw.wait_gestures(MAIN_LOOP_INTERVAL);
+        for it in gestures {
+            info!("Gesture: {:?}", it);
+            let action = button_map.action(&it);
+            match &action {
+                ButtonAction::None => {},
+                ButtonAction::Wake(name) | ButtonAction::Sleep(name) => {
+                    let targets = match control::resolve_targets(&control.get_config_entries(), name) {
+                        Some(targets) => targets,
+                        None => {
+                            info!("Unknown target {}", name);
+                            continue;
+                        },
+                    };
+                    if targets.iter().any(|t| pin_targets.contains(t))
+                        && !unlock_with_pin(&mut dp, &mut keysw, &mut pin_lock, &config_data.pin_hash, &pin_clock, &mut nvs) {
+                        keysw.clear_all_button_event();
+                        idle_since = Instant::now();
+                        break;
+                    }
+                    if matches!(action, ButtonAction::Sleep(_)) {
+                        sleep_targets = targets;
+                    }
+                    else {
+                        // queued like a network request; sent below in this loop
+                        dp.set_main_msg(&name.to_uppercase(), MessageTypes::Progress);
+                        control.push_wake_request(targets, TriggerSource::Button);
+                    }
                     break;
                 },
-                KeyEvent::LeftKeyUp | KeyEvent::LeftKeyDown => {
-                    target_device = wakepacket::TargetDevice::Device3;
-                    info!("Left key pressed. Target device3");
-                    dp.set_main_msg(&"PC3".to_string(), MessageTypes::Progress);
+                ButtonAction::ShowStatus => {
+                    dp.set_display_active(true);
+                    dp.set_main_msg(&format!("IP: {}", ip_addr), MessageTypes::Status);
+                    dp.set_second_msg(&format!("Sent: {}  {:.2}V", send_count, battery_voltage));
+                    thread::sleep(Duration::from_secs(3));
+                    keysw.clear_all_button_event();
+                    idle_since = Instant::now();
                     break;
                 },
-                KeyEvent::RightKeyUp | KeyEvent::RightKeyDown => {
-                    target_device = wakepacket::TargetDevice::Device4;
-                    info!("Right key pressed. Target device4");
-                    dp.set_main_msg(&"PC4".to_string(), MessageTypes::Progress);
+                ButtonAction::ForceSleep => {
+                    force_sleep = true;
                     break;
                 },
-                KeyEvent::CenterKeyUp | KeyEvent::CenterKeyDown => {
-                    info!("Enter key pressed. Show menu");
+                ButtonAction::OpenMenu => {
+                    if pin_mode.for_menu() && !unlock_with_pin(&mut dp, &mut keysw, &mut pin_lock, &config_data.pin_hash, &pin_clock, &mut nvs) {
+                        keysw.clear_all_button_event();
+                        idle_since = Instant::now();
+                        break;
+                    }
+                    info!("Show menu");
                     dp.set_display_active(true);
-                    let config = select_menu(&mut dp, &mut keysw);
+                    let config = select_menu(&mut dp, &mut keysw, wifi_dev.as_mut().ok().map(|w| &mut **w), &control, &time_svc);
+                    if config != None && is_action_selected(config.as_ref().unwrap(), "WPSCONNECT") {
+                        // Other unsaved menu changes are dropped; WPS acts immediately like RESET CONFIG.
+                        match wifi_dev.as_mut() {
+                            Ok(wifi) => {
+                                if let Some(new_ip) = connect_wps(wifi, &mut config_data, &mut nvs, &mut dp) {
+                                    ip_addr = new_ip;
+                                    control.set_ip_addr(ip_addr);
+                                    control.set_config_entries(config_data.to_config_entries());
+                                }
+                            },
+                            Err(ref e) => {
+                                info!("WPS not available {:?}", e);
+                                dp.set_main_msg(&"WPS Failed".to_string(), MessageTypes::Error);
+                            },
+                        }
+                        dp.initialize_menu(&config_data);
+                        thread::sleep(Duration::from_secs(2));
+                        keysw.clear_all_button_event();
+                        idle_since = Instant::now();
+                        break;
+                    }
+                    if config.as_ref().map(|c| !is_going_to_reset(c)).unwrap_or(false)
+                        && !pin_settings_allowed(&mut dp, &mut keysw, &mut pin_lock, &config_data, pin_mode, config.as_ref().unwrap(), &pin_clock, &mut nvs) {
+                        dp.set_main_msg(&"No Save...".to_string(), MessageTypes::Status);
+                        thread::sleep(Duration::from_secs(1));
+                        keysw.clear_all_button_event();
+                        idle_since = Instant::now();
+                        break;
+                    }
+                    if config != None && is_action_selected(config.as_ref().unwrap(), "OTAUPDATE") {
+                        // save the other menu edits (including the URL) first; the box restarts either way
+                        let mut config = with_menu_values(&config_data, &config.unwrap());
+                        config.retain(|it| it.0 != "OTAUPDATE");
+                        let _ = save_config(&mut nvs, &config, config_data.profile_index);
+                        let url = config.iter().find(|it| it.0 == "OTAURL").map(|it| it.1.clone()).unwrap_or_default();
+                        run_ota(&mut dp, &url);
+                        dp.set_main_msg(&"Restarting...".to_string(), MessageTypes::Status);
+                        thread::sleep(Duration::from_secs(1));
+                        unsafe {
+                            esp_idf_sys::esp_restart();
+                        }
+                    }
                     if config != None {
                         if is_going_to_reset(config.as_ref().unwrap()) {
                             info!("Reset config");
@@ -323,9 +592,11 @@ fn main() -> anyhow::Result<()> {
                             thread::sleep(Duration::from_secs(3));
                         }
                         else {
-                            let toml_string = convert_config_to_toml_string(&config.unwrap());
-                            info!("New config: {}", toml_string);
-                            let _ = nvs.set_str("config", &toml_string);
+                            // Profile settings edited in the menu belong to the profile that was active
+                            // when the menu was opened. A new profile selection takes effect after restart.
+                            let config = with_menu_values(&config_data, &config.unwrap());
+                            info!("New config: {}", convert_config_to_toml_string(&mask_secrets(&config)));
+                            let _ = save_config(&mut nvs, &config, config_data.profile_index);
                         }
                         dp.set_main_msg(&"Restarting...".to_string(), MessageTypes::Status);
                         thread::sleep(Duration::from_secs(1));
@@ -338,59 +609,109 @@ fn main() -> anyhow::Result<()> {
                     }
                     thread::sleep(Duration::from_secs(1));
                     keysw.clear_all_button_event();
-                    loop_count = 0;
+                    idle_since = Instant::now();
                     break;
                 }
             }
         }
-        if target_device != wakepacket::TargetDevice::None {
+        // Config changed from the network
+        if let Some(new_config) = control.take_config_update() {
+            info!("New config from network: {}", convert_config_to_toml_string(&mask_secrets(&new_config)));
+            let _ = save_config(&mut nvs, &new_config, config_data.profile_index);
             dp.set_display_active(true);
-            let mut send_retry_count : u32 = 0;
-            loop {
-                thread::sleep(Duration::from_secs(1));
-                let status = wp.send_pkt(target_device);
-                if !status.is_err() {
+            dp.set_main_msg(&"Restarting...".to_string(), MessageTypes::Status);
+            thread::sleep(Duration::from_secs(1));
+            unsafe {
+                esp_idf_sys::esp_restart();
+            }
+        }
+        // Firmware update from the network
+        if let Some(url) = control.take_ota_request() {
+            if run_ota(&mut dp, &url) {
+                control.request_restart();
+            }
+        }
+        if control.take_restart_request() {
+            dp.set_display_active(true);
+            dp.set_main_msg(&"Restarting...".to_string(), MessageTypes::Status);
+            thread::sleep(Duration::from_secs(1));
+            unsafe {
+                esp_idf_sys::esp_restart();
+            }
+        }
+        // Wake targets from buttons and from the network
+        let mut wake_targets : Vec<(wakepacket::TargetDevice, TriggerSource)> = Vec::new();
+        for req in control.take_wake_requests() {
+            for target in req.targets {
+                wake_targets.push((target, req.source));
+            }
+        }
+        if !wake_targets.is_empty() {
+            dp.set_display_active(true);
+            for (target, source) in wake_targets {
+                info!("Wake {} from {}", target.name(), source.as_str());
+                dp.set_main_msg(&target.name().to_string(), MessageTypes::Progress);
+                let success = send_wake_packet(&wp, target, &mut dp);
+                if success {
                     send_count += 1;
                     dp.set_send_pkt(send_count);
-                    dp.set_main_msg(&"Completed.".to_string(), MessageTypes::WakeUp);
-                    thread::sleep(Duration::from_secs(2));
-                    keysw.clear_all_button_event();
-                    break;
+                    control.set_send_count(send_count);
                 }
-                else {
-                    send_retry_count += 1;
-                    info!("Send magic packet failed");
-                    if send_retry_count >= 5 {
-                        dp.set_main_msg(&"Send Failed.".to_string(), MessageTypes::Error);
-                        break;
-                    }
+                control.record_wake(target, source, success);
+                if let Some(ref webhook) = webhook {
+                    webhook.notify(&webhook::WakeEvent {
+                        target: target.name().to_string(),
+                        mac: target_mac(&config_data, target),
+                        source: source.as_str().to_string(),
+                        success,
+                        battery_voltage,
+                        time: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+                    });
                 }
             }
-            loop_count = 0;
+            keysw.clear_all_button_event();
+            idle_since = Instant::now();
         }
-        else {
-            loop_count += 1;
+        // Sleep-On-LAN from buttons
+        if !sleep_targets.is_empty() {
+            dp.set_display_active(true);
+            for target in sleep_targets {
+                info!("Sleep {}", target.name());
+                if wp.send_sleep_pkt(target).is_ok() {
+                    dp.set_main_msg(&format!("{} Sleep", target.name()), MessageTypes::Status);
+                }
+                else {
+                    dp.set_main_msg(&"Send Failed.".to_string(), MessageTypes::Error);
+                }
+                thread::sleep(Duration::from_secs(2));
+            }
+            keysw.clear_all_button_event();
+            idle_since = Instant::now();
         }
-        if config_data.idle_in_sleep_time == 0 {
-            if config_data.display_off_time > 0 && loop_count >= config_data.display_off_time {
-                loop_count = 0;
+        if config_data.idle_in_sleep_time == 0 && !force_sleep {
+            if config_data.display_off_time > 0 && idle_since.elapsed() >= Duration::from_secs(config_data.display_off_time as u64) {
+                idle_since = Instant::now();
                 // display off
                 dp.set_display_active(false);
             }
         }
         else {
             // Sleep
-            if loop_count >= config_data.idle_in_sleep_time {
+            if force_sleep || idle_since.elapsed() >= Duration::from_secs(config_data.idle_in_sleep_time as u64) {
                 info!("Sleep Now...");
                 dp.set_main_msg(&"Sleeping..".to_string(), MessageTypes::Status);
                 dp.set_wifi_status(WiFiStatus::Disconnected);
+                if let Some(ref client) = mqtt_client {
+                    client.set_offline();
+                }
                 thread::sleep(Duration::from_millis(1000));
                 dp.set_display_active(false);
+                wifi_mgr.suspend();
                 unsafe {
                     esp_idf_sys::esp_wifi_stop();
                 }
                 thread::sleep(Duration::from_millis(1000));
-                loop_count = 0;
+                idle_since = Instant::now();
                 unsafe {
                     // light sleep mode
                     if config_data.sleep_mode == SLEEP_MODE_LIGHT {
@@ -406,6 +727,7 @@ fn main() -> anyhow::Result<()> {
                     }
                     else {
                         info!("Deep Sleep Start");
+                        timesvc::save_rtc(time_svc.persisted());
                         esp_idf_sys::esp_deep_sleep_enable_gpio_wakeup(GPIO_WAKEUP_INT_PIN, esp_idf_sys::esp_deepsleep_gpio_wake_up_mode_t_ESP_GPIO_WAKEUP_GPIO_LOW);
                         esp_idf_sys::esp_deep_sleep_start();
                     }
@@ -413,76 +735,383 @@ fn main() -> anyhow::Result<()> {
                     // deep sleep mode (not here)
                     if config_data.sleep_mode == SLEEP_MODE_LIGHT {
                         let _result = esp_idf_sys::esp_light_sleep_start();
-                        // gpio interrupt enable
-                        esp_idf_sys::gpio_set_intr_type(GPIO_WAKEUP_INT_PIN_4, esp_idf_sys::gpio_int_type_t_GPIO_INTR_ANYEDGE);
-                        esp_idf_sys::gpio_set_intr_type(GPIO_WAKEUP_INT_PIN_5, esp_idf_sys::gpio_int_type_t_GPIO_INTR_ANYEDGE);
-                        esp_idf_sys::gpio_set_intr_type(GPIO_WAKEUP_INT_PIN_6, esp_idf_sys::gpio_int_type_t_GPIO_INTR_ANYEDGE);
-                        esp_idf_sys::gpio_set_intr_type(GPIO_WAKEUP_INT_PIN_9, esp_idf_sys::gpio_int_type_t_GPIO_INTR_ANYEDGE);
-                        esp_idf_sys::gpio_set_intr_type(GPIO_WAKEUP_INT_PIN_10, esp_idf_sys::gpio_int_type_t_GPIO_INTR_ANYEDGE);
+                        // keys are polled by the key thread; only the wakeup setting is undone
+                        esp_idf_sys::gpio_wakeup_disable(GPIO_WAKEUP_INT_PIN_4);
+                        esp_idf_sys::gpio_wakeup_disable(GPIO_WAKEUP_INT_PIN_5);
+                        esp_idf_sys::gpio_wakeup_disable(GPIO_WAKEUP_INT_PIN_6);
+                        esp_idf_sys::gpio_wakeup_disable(GPIO_WAKEUP_INT_PIN_9);
+                        esp_idf_sys::gpio_wakeup_disable(GPIO_WAKEUP_INT_PIN_10);
+                        wifi_mgr.resume();
+                        if let Some(ref client) = mqtt_client {
+                            client.set_online();
+                        }
+                        // the time asleep is not idle time
+                        idle_since = Instant::now();
                         continue;
                     }
                 }
             }
         }
+    }
+}
+
+fn wifi_status(state: wifimgr::WifiState) -> WiFiStatus {
+    match state {
+        wifimgr::WifiState::Connected => WiFiStatus::Connected,
+        wifimgr::WifiState::Stopped => WiFiStatus::Disconnected,
+        _ => WiFiStatus::Connecting,
+    }
+}
+
+fn show_wps_status(dp: &mut DisplayPanel, mode: wifi::WpsMode, title: &str, msg: &str) {
+    // In PIN mode the title is the PIN itself until the credentials arrive
+    if mode == wifi::WpsMode::Pin && title.starts_with(|c: char| c.is_ascii_digit()) {
+        dp.set_main_msg(&title.to_string(), MessageTypes::WpsPin);
+        dp.set_second_msg(&msg.to_string());
+    }
+    else {
+        dp.set_main_msg(&msg.to_string(), MessageTypes::Status);
+        dp.set_second_msg(&title.to_string());
+    }
+}
+
+fn send_wake_packet(wp: &wakepacket::WakePacket, target: wakepacket::TargetDevice, dp: &mut DisplayPanel) -> bool {
+    let mut send_retry_count : u32 = 0;
+    loop {
         thread::sleep(Duration::from_secs(1));
+        let status = wp.send_pkt(target);
+        if !status.is_err() {
+            dp.set_main_msg(&"Completed.".to_string(), MessageTypes::WakeUp);
+            thread::sleep(Duration::from_secs(2));
+            return true;
+        }
+        else {
+            send_retry_count += 1;
+            info!("Send magic packet failed");
+            if send_retry_count >= 5 {
+                dp.set_main_msg(&"Send Failed.".to_string(), MessageTypes::Error);
+                return false;
+            }
+        }
     }
 }
 
-fn wifi_reconnect(wifi_dev: &mut EspWifi, dp: &mut DisplayPanel) -> bool{
-    // display on
-    dp.set_wifi_status(WiFiStatus::Connecting);
-    unsafe {
-        esp_idf_sys::esp_wifi_start();
+// Targets whose button wakes (and sleeps) need the PIN.
+fn pin_protected_targets(config_data: &ConfigData, pin_mode: PinMode) -> Vec<wakepacket::TargetDevice> {
+    if !pin_mode.for_wake() {
+        return Vec::new();
     }
-    match wifi_dev.connect() {
-        Ok(_) => { info!("Wifi connected"); true},
-        Err(ref e) => { info!("{:?}", e); false }
+    pinlock::pin_protected_targets(&config_data.pin_targets)
+}
+
+// With a PIN set, changing the PIN settings needs the PIN, unless it was already
+// entered to open the menu. Returns true when the menu edits may be saved.
+fn pin_settings_allowed<T : NvsPartitionId>(dp: &mut DisplayPanel, keysw: &mut KeySwitch, pin_lock: &mut PinLock, config_data: &ConfigData,
+    pin_mode: PinMode, menu_values: &Vec<(String, String)>, pin_clock: &Instant, nvs: &mut EspNvs<T>) -> bool {
+    if config_data.pin_hash.is_empty() || pin_mode.for_menu()
+        || !pinlock::changes_pin_settings(&config_data.to_config_entries(), menu_values) {
+        return true;
     }
+    info!("[PIN] PIN settings changed, asking for the current PIN");
+    unlock_with_pin(dp, keysw, pin_lock, &config_data.pin_hash, pin_clock, nvs)
 }
 
+// Ask for the PIN. Returns true when it was accepted.
+fn unlock_with_pin<T : NvsPartitionId>(dp: &mut DisplayPanel, keysw: &mut KeySwitch, pin_lock: &mut PinLock, pin_hash: &str, pin_clock: &Instant, nvs: &mut EspNvs<T>) -> bool {
+    dp.set_display_active(true);
+    if let Some(secs) = pin_lock.locked_for(pin_clock.elapsed().as_secs()) {
+        dp.set_main_msg(&format!("Locked {}s", secs), MessageTypes::Error);
+        thread::sleep(Duration::from_secs(2));
+        return false;
+    }
+    let pin = match enter_pin(dp, keysw) {
+        Some(pin) => pin,
+        None => return false,
+    };
+    // counted as a failure until accepted, so cutting the power right after a wrong PIN does not help
+    pinlock::save_failures(nvs, pin_lock.failures() + 1);
+    let result = pin_lock.check(&pin, pin_hash, pin_clock.elapsed().as_secs());
+    pinlock::save_failures(nvs, pin_lock.failures());
+    match result {
+        PinResult::Accepted => true,
+        PinResult::Wrong(left) => {
+            info!("[PIN] {} attempts left", left);
+            dp.set_main_msg(&"Wrong PIN".to_string(), MessageTypes::Error);
+            thread::sleep(Duration::from_secs(2));
+            false
+        },
+        PinResult::Locked(secs) => {
+            dp.set_main_msg(&format!("Locked {}s", secs), MessageTypes::Error);
+            thread::sleep(Duration::from_secs(2));
+            false
+        },
+    }
+}
 
+// PIN entry with the keys; None when cancelled or idle for PIN_ENTRY_TIMEOUT.
+fn enter_pin(dp: &mut DisplayPanel, keysw: &mut KeySwitch) -> Option<String> {
+    let mut entry = pinlock::PinEntry::new();
+    let mut last_input = Instant::now();
+    keysw.clear_all_button_event();
+    loop {
+        dp.set_main_msg(&entry.display_text(), MessageTypes::PinEntry);
+        for it in keysw.wait_key_events(MENU_POLL_INTERVAL) {
+            last_input = Instant::now();
+            match entry.input(it.event) {
+                pinlock::EntryState::Editing => {},
+                pinlock::EntryState::Done(pin) => {
+                    keysw.clear_all_button_event();
+                    return Some(pin);
+                },
+                pinlock::EntryState::Cancelled => {
+                    keysw.clear_all_button_event();
+                    return None;
+                },
+            }
+        }
+        if last_input.elapsed() >= PIN_ENTRY_TIMEOUT {
+            return None;
+        }
+    }
+}
 
-fn select_menu(dp: &mut DisplayPanel, keysw: &mut KeySwitch) -> Option<Vec<(String, String)>> {
+fn select_menu(dp: &mut DisplayPanel, keysw: &mut KeySwitch, mut wifi_dev: Option<&mut EspWifi<'static>>, control: &Control, time_svc: &timesvc::TimeService<timesvc::SystemClock>) -> Option<Vec<(String, String)>> {
     dp.reset_menu();
     dp.set_main_msg(&"Menu".to_string(), MessageTypes::Menu);
     thread::sleep(Duration::from_millis(300));
     keysw.clear_all_button_event();
+    let mut diag_refresh : Option<Instant> = None;
     loop {
-        let key_envet = keysw.get_key_event_and_clear();
-        for it in key_envet {
+        // refresh the Diagnostics page once a second while it is open
+        if dp.is_diag_open() {
+            if diag_refresh.map(|t| t.elapsed().as_millis() >= 1000).unwrap_or(true) {
+                diag_refresh = Some(Instant::now());
+                dp.set_wifi_rssi(wifi::get_rssi());
+                dp.set_diagnostics(diag::collect(wifi_dev.as_deref(), time_svc.status_text(), &control.get_status()));
+            }
+        }
+        else {
+            diag_refresh = None;
+        }
+        if dp.take_scan_request() {
+            let scan_result = match wifi_dev.as_deref_mut() {
+                Some(wifi) => wifi::wifi_scan(wifi),
+                None => Err(anyhow::anyhow!("WiFi is not initialized")),
+            };
+            match scan_result {
+                Ok(list) => { dp.set_scan_result(list); },
+                Err(ref e) => {
+                    info!("WiFi scan failed {:?}", e);
+                    dp.set_scan_result(Vec::new());
+                },
+            }
+            keysw.clear_all_button_event();
+        }
+        for it in keysw.wait_key_events(MENU_POLL_INTERVAL) {
             info!("Key Event: {:?}", it);
-            let (exit, data) = dp.key_event_input(it);
+            let (exit, data) = dp.key_event_input(it.event);
             if exit {
+                keysw.set_auto_repeat(false);
                 return data;
             }
         }
-        thread::sleep(Duration::from_millis(10));
+        // holding Up/Down steps through characters in the value editors
+        keysw.set_auto_repeat(dp.is_value_editing());
     }
 }
 
 fn is_going_to_reset(keyval: &Vec<(String, String)>) -> bool {
+    is_action_selected(keyval, "RESETCONFIG")
+}
+
+fn is_action_selected(keyval: &Vec<(String, String)>, key: &str) -> bool {
     for it in keyval {
-        if it.0 == "RESETCONFIG" {
+        if it.0 == key {
             return true;
         }
     }
     false
 }
 
+// Download and install firmware, showing progress. Returns true when the new image is ready to boot.
+fn run_ota(dp: &mut DisplayPanel, url: &str) -> bool {
+    dp.set_display_active(true);
+    if url.is_empty() {
+        info!("[OTA] No URL");
+        dp.set_main_msg(&"No OTA URL".to_string(), MessageTypes::Error);
+        thread::sleep(Duration::from_secs(2));
+        return false;
+    }
+    dp.set_main_msg(&"Updating..".to_string(), MessageTypes::Status);
+    dp.set_second_msg(&"Connecting".to_string());
+    match ota::update_from_url(url, |written, total| dp.set_second_msg(&ota::progress_text(written, total))) {
+        Ok(_) => {
+            dp.set_main_msg(&"Update OK".to_string(), MessageTypes::Status);
+            thread::sleep(Duration::from_secs(2));
+            true
+        },
+        Err(ref e) => {
+            info!("[OTA] Failed: {:?}", e);
+            dp.set_main_msg(&"Update Failed".to_string(), MessageTypes::Error);
+            thread::sleep(Duration::from_secs(2));
+            false
+        },
+    }
+}
+
+fn wifi_auth(config_data: &ConfigData) -> wifi::WifiAuth {
+    if config_data.wifi_auth == "eap" {
+        wifi::WifiAuth::Eap(wifi::EapSettings {
+            identity: config_data.eap_identity.clone(),
+            username: config_data.eap_username.clone(),
+            password: config_data.eap_password.clone(),
+            ca_cert_path: config_data.eap_ca_cert.clone(),
+        })
+    }
+    else {
+        wifi::WifiAuth::Psk(config_data.wifi_psk.clone())
+    }
+}
+
+// Run WPS on the running WiFi driver, save the credentials and stay connected.
+fn connect_wps<T : NvsPartitionId>(wifi_dev: &mut EspWifi<'static>, config_data: &mut ConfigData, nvs: &mut EspNvs<T>, dp: &mut DisplayPanel) -> Option<Ipv4Addr> {
+    let wps_mode = wifi::WpsMode::from_str(&config_data.wps_mode);
+    dp.set_wifi_status(WiFiStatus::Connecting);
+    let wps_result = wifi::wifi_wps(
+        wifi_dev,
+        wps_mode,
+        |title, msg| show_wps_status(dp, wps_mode, title, msg),
+    );
+    match wps_result {
+        Ok((wps_ssid, wps_psk)) => {
+            info!("[WPS] Connected: SSID={}", wps_ssid);
+            config_data.wifi_ssid = wps_ssid;
+            config_data.wifi_psk = wps_psk;
+            config_data.wifi_auth = "psk".to_string();
+            config_data.wps_enable = false;
+            match save_config(nvs, &config_data.to_config_entries(), config_data.profile_index) {
+                Ok(_) => { info!("[WPS] Credentials saved to NVS"); },
+                Err(ref e) => { info!("[WPS] Failed to save credentials: {:?}", e); },
+            }
+            let ip_addr = wifi_dev.sta_netif().get_ip_info().map(|i| i.ip).unwrap_or(Ipv4Addr::UNSPECIFIED);
+            dp.set_wifi_status(WiFiStatus::Connected);
+            dp.set_main_msg(&"WPS Connected".to_string(), MessageTypes::Status);
+            dp.set_second_msg(&format!("IP: {}", ip_addr));
+            Some(ip_addr)
+        },
+        Err(ref e) => {
+            info!("[WPS] Failed: {:?}", e);
+            dp.set_main_msg(&"WPS Failed".to_string(), MessageTypes::Error);
+            // back to the saved network
+            if let Err(ref e) = wifi::wifi_set_client(wifi_dev, &config_data.wifi_ssid, &wifi_auth(&config_data)) {
+                info!("WiFi reconnect failed {:?}", e);
+            }
+            None
+        },
+    }
+}
+
 fn set_default_config<T : NvsPartitionId>(config: &mut ConfigData, nvs: &mut EspNvs<T>){
-    let default_config = config.set_default_config();
-    let toml_cfg = convert_config_to_toml_string(&default_config);
-    match nvs.set_str("config", toml_cfg.as_str()) {
+    let default_config = load_default_config(config);
+    match save_config(nvs, &default_config, 0) {
         Ok(_) => { info!("Set default config"); },
         Err(ref e) => { info!("Set default config failed {:?}", e); }
     }
+}
+
+// Defaults in memory only; NVS is not touched.
+fn load_default_config(config: &mut ConfigData) -> Vec<(String, String)> {
+    let default_config = config.set_default_config();
+    let toml_cfg = convert_config_to_toml_string(&default_config);
     config.load_config(Some(toml_cfg.as_str())).unwrap();
+    default_config
+}
+
+fn load_profiles<T : NvsPartitionId>(config: &mut ConfigData, nvs: &mut EspNvs<T>){
+    let mut nvs_buf : [u8 ; MAX_NVS_STR_SIZE] = [0; MAX_NVS_STR_SIZE];
+    for index in 0..NUM_OF_PROFILES {
+        let key = config::profile_nvs_key(index);
+        let nvs_value = match nvs.get_str(&key, &mut nvs_buf) {
+            Ok(value) => value,
+            Err(ref e) => {
+                // unreadable: keep it in NVS and use the defaults until the next save
+                info!("NVS {} read failed {:?}", key, e);
+                if index == config.profile_index {
+                    let toml_cfg = convert_config_to_toml_string(&config.default_profile_config(index));
+                    config.load_profile(Some(toml_cfg.as_str())).unwrap();
+                }
+                else {
+                    config.set_profile_name(index, None);
+                }
+                continue;
+            },
+        };
+        if index != config.profile_index {
+            config.set_profile_name(index, nvs_value);
+            continue;
+        }
+        match config.load_profile(nvs_value) {
+            Ok(_) => { info!("Profile {} load success", index + 1); },
+            Err(ref e) => {
+                info!("Profile {} load failed {:?}", index + 1, e);
+                // Settings written by older firmware are already loaded from "config",
+                // so keep them as the active profile. Otherwise start from defaults.
+                let profile_config = if config.profile_name.is_empty() {
+                    config.default_profile_config(index)
+                }
+                else {
+                    config::split_profile_entries(&config.to_config_entries()).1
+                };
+                let toml_cfg = convert_config_to_toml_string(&profile_config);
+                match nvs.set_str(&key, toml_cfg.as_str()) {
+                    Ok(_) => { info!("Set profile {} config", index + 1); },
+                    Err(ref e) => { info!("Set profile {} config failed {:?}", index + 1, e); }
+                }
+                config.load_profile(Some(toml_cfg.as_str())).unwrap();
+            },
+        }
+    }
+}
+
+// Shared settings go to "config", profile settings to the NVS entry of `profile_index`.
+fn save_config<T : NvsPartitionId>(nvs: &mut EspNvs<T>, keyval: &Vec<(String, String)>, profile_index: usize) -> anyhow::Result<()> {
+    let (global_config, profile_config) = config::split_profile_entries(keyval);
+    nvs.set_str("config", &convert_config_to_toml_string(&global_config))?;
+    if !profile_config.is_empty() {
+        nvs.set_str(&config::profile_nvs_key(profile_index), &convert_config_to_toml_string(&profile_config))?;
+    }
+    Ok(())
+}
+
+// Current settings with the menu edits applied; keys the menu does not show are kept.
+fn with_menu_values(config_data: &ConfigData, menu_values: &Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut config = config_data.to_config_entries();
+    for (key, value) in menu_values {
+        match config.iter_mut().find(|it| it.0 == *key) {
+            Some(it) => it.1 = value.clone(),
+            None => config.push((key.clone(), value.clone())),
+        }
+    }
+    pinlock::store_menu_pin(&mut config);
+    pinlock::expand_pin_targets(&mut config);
+    config
+}
+
+fn target_mac(config_data: &ConfigData, target: wakepacket::TargetDevice) -> String {
+    match target {
+        wakepacket::TargetDevice::Device1 => config_data.target_mac_address1.clone(),
+        wakepacket::TargetDevice::Device2 => config_data.target_mac_address2.clone(),
+        wakepacket::TargetDevice::Device3 => config_data.target_mac_address3.clone(),
+        wakepacket::TargetDevice::Device4 => config_data.target_mac_address4.clone(),
+        wakepacket::TargetDevice::None => String::new(),
+    }
 }
 
 fn convert_config_to_toml_string(keyval: &Vec<(String, String)>) -> String {
     let mut toml_string = String::new();
     for it in keyval {
-        toml_string.push_str(&format!("{} = \"{}\"\n", it.0, it.1));
+        // TOML basic string: escape backslash and quote (e.g. a JSON webhook template)
+        toml_string.push_str(&format!("{} = \"{}\"\n", it.0, it.1.replace('\\', "\\\\").replace('"', "\\\"")));
     }
     toml_string
 }
@@ -0,0 +1,71 @@
+// Metrics export: Prometheus text format and InfluxDB line protocol over UDP
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::{thread, time::Duration};
+
+use anyhow::Result;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::Write;
+use log::*;
+
+use crate::control::Control;
+
+pub use wakepcbox_core::metrics::*;
+
+const INFLUX_DEFAULT_PORT: u16 = 8089;
+const MIN_PUSH_INTERVAL: u64 = 10;  // seconds
+
+/// Sample the current status plus uptime and free heap of this device.
+pub fn collect(control: &Control) -> Metrics {
+    let uptime_secs = unsafe { esp_idf_sys::esp_timer_get_time() } as u64 / 1_000_000;
+    let free_heap = unsafe { esp_idf_sys::esp_get_free_heap_size() };
+    Metrics::from_status(&control.get_status(), uptime_secs, free_heap)
+}
+
+/// Add `GET /metrics` to the HTTP API server.
+pub fn register_http(server: &mut EspHttpServer<'static>, control: Control) -> Result<()> {
+    server.fn_handler::<anyhow::Error, _>("/metrics", Method::Get, move |req| {
+        let body = prometheus_text(&collect(&control));
+        req.into_response(200, None, &[("Content-Type", "text/plain; version=0.0.4")])?
+            .write_all(body.as_bytes())?;
+        Ok(())
+    })?;
+    info!("[METRICS] /metrics registered");
+    Ok(())
+}
+
+/// Push line protocol to `server` (`host` or `host:port`) every `interval` seconds.
+/// Samples taken while the network is down are skipped.
+pub fn start_influx(server: &str, interval: u64, host: &str, control: Control) -> Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let server = server.trim().to_string();
+    let host = host.to_string();
+    let interval = Duration::from_secs(interval.max(MIN_PUSH_INTERVAL));
+    let _th = thread::spawn(move || {
+        info!("Start InfluxDB Thread. {}", server);
+        let mut addr : Option<SocketAddr> = None;
+        loop {
+            thread::sleep(interval);
+            // resolve lazily: DNS only works while Wi-Fi is up
+            if addr.is_none() {
+                addr = resolve(&server);
+            }
+            if let Some(target) = addr {
+                let lines = influx_lines(&collect(&control), &host);
+                if let Err(e) = socket.send_to(lines.as_bytes(), target) {
+                    info!("[METRICS] InfluxDB send failed: {:?}", e);
+                    addr = None;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+fn resolve(server: &str) -> Option<SocketAddr> {
+    let target = if server.contains(':') { server.to_string() } else { format!("{}:{}", server, INFLUX_DEFAULT_PORT) };
+    target.to_socket_addrs().ok().and_then(|mut it| it.next())
+}
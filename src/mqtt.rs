@@ -0,0 +1,246 @@
+// MQTT client with Home Assistant discovery
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{thread, time::Duration};
+
+use anyhow::Result;
+use esp_idf_svc::mqtt::client::{EspMqttClient, EventPayload, LwtConfiguration, MqttClientConfiguration, QoS};
+use log::*;
+
+use crate::control::{json_escape, Control, TriggerSource, WakeResult};
+use crate::wakepacket::{TargetDevice, ALL_TARGETS};
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+const PAYLOAD_ONLINE: &str = "online";
+const PAYLOAD_OFFLINE: &str = "offline";
+const PAYLOAD_PRESS: &str = "PRESS";   // Home Assistant button press
+const MQTT_LOOP_TIME: u64 = 1000;   // ms
+const SENSOR_INTERVAL: u32 = 60;    // publish battery/RSSI every 60 loops
+
+/// MQTT connection settings, taken from `ConfigData`.
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    pub url: String,        // e.g. mqtt://192.168.1.10:1883
+    pub username: String,
+    pub password: String,
+    pub base_topic: String, // e.g. wakepcbox
+}
+
+pub struct Mqtt {
+    client: Arc<Mutex<EspMqttClient<'static>>>,
+    topics: Arc<Topics>,
+    connected: Arc<AtomicBool>,
+    resumed: Arc<AtomicBool>,
+}
+
+struct Topics {
+    node_id: String,
+    base: String,
+    availability: String,
+}
+
+impl Topics {
+    fn command(&self, target: TargetDevice) -> String {
+        format!("{}/{}/wake", self.base, target.name())
+    }
+
+    fn last_wake(&self, target: TargetDevice) -> String {
+        format!("{}/{}/last_wake", self.base, target.name())
+    }
+}
+
+impl Mqtt {
+    /// Connect to the broker and start the publish thread.
+    /// The ESP-IDF client reconnects by itself (e.g. after light sleep); discovery,
+    /// availability and subscriptions are sent again on every (re)connect, and
+    /// availability after `set_online()` when the session survived the sleep.
+    pub fn start(settings: &MqttSettings, control: Control) -> Result<Mqtt> {
+        let node_id = node_id();
+        let base = format!("{}/{}", settings.base_topic.trim_end_matches('/'), node_id);
+        let topics = Arc::new(Topics {
+            availability: format!("{}/availability", base),
+            node_id,
+            base,
+        });
+
+        let connected = Arc::new(AtomicBool::new(false));
+        let announce = Arc::new(AtomicBool::new(false));
+        let resumed = Arc::new(AtomicBool::new(false));
+
+        let conf = MqttClientConfiguration {
+            client_id: Some(&topics.node_id),
+            username: if settings.username.is_empty() { None } else { Some(&settings.username) },
+            password: if settings.password.is_empty() { None } else { Some(&settings.password) },
+            keep_alive_interval: Some(Duration::from_secs(30)),
+            reconnect_timeout: Some(Duration::from_secs(10)),
+            lwt: Some(LwtConfiguration {
+                topic: &topics.availability,
+                payload: PAYLOAD_OFFLINE.as_bytes(),
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
+            ..Default::default()
+        };
+
+        let cb_topics = topics.clone();
+        let cb_connected = connected.clone();
+        let cb_announce = announce.clone();
+        let cb_control = control.clone();
+        let client = EspMqttClient::new_cb(&settings.url, &conf, move |event| {
+            match event.payload() {
+                EventPayload::Connected(_) => {
+                    info!("[MQTT] Connected");
+                    cb_connected.store(true, Ordering::Release);
+                    cb_announce.store(true, Ordering::Release);
+                },
+                EventPayload::Disconnected => {
+                    info!("[MQTT] Disconnected");
+                    cb_connected.store(false, Ordering::Release);
+                },
+                EventPayload::Received { topic: Some(topic), data, .. } => {
+                    // an empty message is the retained command being cleared
+                    if data != PAYLOAD_PRESS.as_bytes() {
+                        if !data.is_empty() {
+                            info!("[MQTT] Ignored payload on {}", topic);
+                        }
+                        return;
+                    }
+                    for target in ALL_TARGETS {
+                        if topic == cb_topics.command(target) {
+                            info!("[MQTT] Wake request: {}", target.name());
+                            cb_control.push_wake_request(vec![target], TriggerSource::Mqtt);
+                        }
+                    }
+                },
+                EventPayload::Error(e) => {
+                    info!("[MQTT] Error: {:?}", e);
+                },
+                _ => {},
+            }
+        })?;
+        let client = Arc::new(Mutex::new(client));
+
+        let th_client = client.clone();
+        let th_topics = topics.clone();
+        let th_connected = connected.clone();
+        let th_resumed = resumed.clone();
+        let _th = thread::spawn(move || {
+            info!("Start MQTT Thread.");
+            // wake and failure counts last published; one wake can share its second with another
+            let mut published : [Option<(u32, u32)>; ALL_TARGETS.len()] = [None; ALL_TARGETS.len()];
+            let mut loopcount = 0;
+            loop {
+                thread::sleep(Duration::from_millis(MQTT_LOOP_TIME));
+                if !th_connected.load(Ordering::Acquire) {
+                    continue;
+                }
+                let mut client = th_client.lock().unwrap();
+                let status = control.get_status();
+                if announce.swap(false, Ordering::AcqRel) {
+                    if let Err(e) = announce_device(&mut client, &th_topics) {
+                        info!("[MQTT] Announce failed: {:?}", e);
+                        announce.store(true, Ordering::Release);
+                        continue;
+                    }
+                    published = [None; ALL_TARGETS.len()];
+                    loopcount = 0;
+                    th_resumed.store(false, Ordering::Release);
+                }
+                if th_resumed.swap(false, Ordering::AcqRel) {
+                    publish(&mut client, &th_topics.availability, PAYLOAD_ONLINE, true);
+                }
+                if loopcount == 0 {
+                    publish(&mut client, &format!("{}/battery", th_topics.base), &format!("{:.2}", status.battery_voltage), false);
+                    publish(&mut client, &format!("{}/rssi", th_topics.base), &status.rssi.to_string(), false);
+                }
+                loopcount = (loopcount + 1) % SENSOR_INTERVAL;
+                for (i, target) in ALL_TARGETS.iter().enumerate() {
+                    let stats = &status.targets[i];
+                    if let Some(result) = stats.last_wake {
+                        let counts = (stats.wake_count, stats.fail_count);
+                        if published[i] != Some(counts) {
+                            publish(&mut client, &th_topics.last_wake(*target), &last_wake_json(&result), true);
+                            published[i] = Some(counts);
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Mqtt { client, topics, connected, resumed })
+    }
+
+    /// Mark the box unavailable before Wi-Fi is stopped for sleep.
+    pub fn set_offline(&self) {
+        if self.connected.load(Ordering::Acquire) {
+            let mut client = self.client.lock().unwrap();
+            publish(&mut client, &self.topics.availability, PAYLOAD_OFFLINE, true);
+            // let the message leave before Wi-Fi is stopped
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    /// Mark the box available again after sleep. Published once connected; if the
+    /// client had to reconnect, the announce on Connected covers it.
+    pub fn set_online(&self) {
+        self.resumed.store(true, Ordering::Release);
+    }
+}
+
+fn announce_device(client: &mut EspMqttClient<'static>, topics: &Topics) -> Result<()> {
+    let device = format!(
+        "{{\"identifiers\":[\"{}\"],\"name\":\"Wake PC Box\",\"manufacturer\":\"hnz1102\",\"model\":\"WakePCBox\",\"sw_version\":\"{}\"}}",
+        topics.node_id, env!("CARGO_PKG_VERSION"));
+    let availability = format!("\"availability_topic\":\"{}\"", json_escape(&topics.availability));
+    for target in ALL_TARGETS {
+        // clear a retained command first, so it does not wake the PC on every connect
+        client.publish(&topics.command(target), QoS::AtLeastOnce, true, &[])?;
+        client.subscribe(&topics.command(target), QoS::AtLeastOnce)?;
+        let object_id = target.name().to_lowercase();
+        let payload = format!(
+            "{{\"name\":\"Wake {}\",\"unique_id\":\"{}_{}\",\"command_topic\":\"{}\",\"payload_press\":\"PRESS\",\
+             \"json_attributes_topic\":\"{}\",{},\"device\":{}}}",
+            target.name(), topics.node_id, object_id, json_escape(&topics.command(target)),
+            json_escape(&topics.last_wake(target)), availability, device);
+        client.publish(&format!("{}/button/{}/{}/config", DISCOVERY_PREFIX, topics.node_id, object_id),
+            QoS::AtLeastOnce, true, payload.as_bytes())?;
+    }
+    let sensors = [
+        ("battery", "Battery", "voltage", "V"),
+        ("rssi", "RSSI", "signal_strength", "dBm"),
+    ];
+    for (object_id, name, device_class, unit) in sensors {
+        let payload = format!(
+            "{{\"name\":\"{}\",\"unique_id\":\"{}_{}\",\"state_topic\":\"{}/{}\",\"device_class\":\"{}\",\
+             \"unit_of_measurement\":\"{}\",\"entity_category\":\"diagnostic\",{},\"device\":{}}}",
+            name, topics.node_id, object_id, json_escape(&topics.base), object_id, device_class, unit, availability, device);
+        client.publish(&format!("{}/sensor/{}/{}/config", DISCOVERY_PREFIX, topics.node_id, object_id),
+            QoS::AtLeastOnce, true, payload.as_bytes())?;
+    }
+    client.publish(&topics.availability, QoS::AtLeastOnce, true, PAYLOAD_ONLINE.as_bytes())?;
+    info!("[MQTT] Discovery published for {}", topics.node_id);
+    Ok(())
+}
+
+fn publish(client: &mut EspMqttClient<'static>, topic: &str, payload: &str, retain: bool) {
+    if let Err(e) = client.publish(topic, QoS::AtMostOnce, retain, payload.as_bytes()) {
+        info!("[MQTT] Publish {} failed: {:?}", topic, e);
+    }
+}
+
+fn last_wake_json(result: &WakeResult) -> String {
+    format!("{{\"result\":\"{}\",\"source\":\"{}\",\"time\":{}}}",
+        if result.success { "ok" } else { "failed" }, result.source.as_str(), result.time)
+}
+
+// wakepcbox_xxxxxx from the station MAC address
+fn node_id() -> String {
+    let mut mac = [0u8; 6];
+    unsafe {
+        esp_idf_sys::esp_read_mac(mac.as_mut_ptr(), esp_idf_sys::esp_mac_type_t_ESP_MAC_WIFI_STA);
+    }
+    format!("wakepcbox_{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5])
+}
@@ -0,0 +1,111 @@
+// Over-the-air firmware update
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use anyhow::{bail, Result};
+use embedded_svc::http::client::Client;
+use esp_idf_svc::http::client::{Configuration as HttpClientConfiguration, EspHttpConnection};
+use esp_idf_svc::io::Read;
+use esp_idf_svc::ota::{EspOta, SlotState};
+use log::*;
+
+const OTA_BUF_SIZE: usize = 4096;
+const OTA_TIMEOUT_SECS: u64 = 30;
+
+// esp_ota_end checks the image signature only with CONFIG_SECURE_SIGNED_ON_UPDATE
+// (see sdkconfig.defaults). Without it any image would be installed, so updates are refused.
+#[cfg(esp_idf_secure_signed_on_update)]
+const SIGNED_IMAGES: bool = true;
+#[cfg(not(esp_idf_secure_signed_on_update))]
+const SIGNED_IMAGES: bool = false;
+
+/// Download the image at `url` (http or https) into the next OTA slot and make it
+/// the boot partition. `progress` is called with (bytes written, total if known).
+pub fn update_from_url(url: &str, progress: impl FnMut(usize, Option<usize>)) -> Result<()> {
+    info!("[OTA] Download {}", url);
+    let connection = EspHttpConnection::new(&HttpClientConfiguration {
+        buffer_size: Some(OTA_BUF_SIZE),
+        timeout: Some(std::time::Duration::from_secs(OTA_TIMEOUT_SECS)),
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let mut client = Client::wrap(connection);
+    let mut response = client.get(url)?.submit()?;
+    if response.status() != 200 {
+        bail!("HTTP status {}", response.status());
+    }
+    let total = response.header("Content-Length").and_then(|v| v.parse::<usize>().ok());
+    write_image(&mut response, total, progress)
+}
+
+/// Write an image read from `reader` (HTTP download or upload body) into the next
+/// OTA slot. The image and its signature are verified by ESP-IDF before the boot
+/// partition is switched; on any error the slot is left untouched.
+pub fn write_image<R: Read>(reader: &mut R, total: Option<usize>, mut progress: impl FnMut(usize, Option<usize>)) -> Result<()>
+where R::Error: std::fmt::Debug {
+    if !SIGNED_IMAGES {
+        bail!("firmware built without signed image verification, OTA disabled");
+    }
+    let mut ota = EspOta::new()?;
+    let mut update = ota.initiate_update()?;
+    let mut buf = vec![0u8; OTA_BUF_SIZE];
+    let mut written = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) => {
+                update.abort()?;
+                bail!("read failed after {} bytes: {:?}", written, e);
+            },
+        };
+        if n == 0 {
+            break;
+        }
+        if let Err(e) = update.write(&buf[..n]) {
+            update.abort()?;
+            bail!("flash write failed after {} bytes: {:?}", written, e);
+        }
+        written += n;
+        progress(written, total);
+        if total.map(|t| written >= t).unwrap_or(false) {
+            break;
+        }
+    }
+    if written == 0 || total.map(|t| written < t).unwrap_or(false) {
+        update.abort()?;
+        bail!("image truncated ({} of {:?} bytes)", written, total);
+    }
+    // esp_ota_end: checks the image header, checksum, SHA-256 and the RSA signature against
+    // the key of the running app, then sets the boot partition
+    update.complete()?;
+    info!("[OTA] {} bytes written, new image selected for next boot", written);
+    Ok(())
+}
+
+/// Called once the firmware reached the Ready state. A freshly updated image stays
+/// "pending verify" until then; if it resets earlier the bootloader rolls back.
+pub fn mark_running_valid() {
+    let mut ota = match EspOta::new() {
+        Ok(ota) => ota,
+        Err(ref e) => { info!("[OTA] {:?}", e); return; },
+    };
+    match ota.get_running_slot() {
+        Ok(slot) => {
+            if slot.state == SlotState::Unverified {
+                match ota.mark_running_slot_valid() {
+                    Ok(_) => { info!("[OTA] {} marked valid", slot.label); },
+                    Err(ref e) => { info!("[OTA] Mark valid failed {:?}", e); },
+                }
+            }
+        },
+        Err(ref e) => { info!("[OTA] {:?}", e); },
+    }
+}
+
+/// Progress text for the display, e.g. `45% 600KB`.
+pub fn progress_text(written: usize, total: Option<usize>) -> String {
+    match total {
+        Some(total) if total > 0 => format!("{}% {}KB", written * 100 / total, written / 1024),
+        _ => format!("{}KB", written / 1024),
+    }
+}
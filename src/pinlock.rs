@@ -0,0 +1,57 @@
+// PIN lock: PIN hashing with a random salt and failures kept in NVS
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use esp_idf_svc::nvs::{EspNvs, NvsPartitionId};
+use log::*;
+
+pub use wakepcbox_core::pinlock::*;
+
+const NVS_FAILURES_KEY: &str = "pinfail";
+
+/// Hash of `pin` with a random salt, for `PINHASH`.
+pub fn hash_pin(pin: &str) -> String {
+    let mut salt = [0u8; 8];
+    for chunk in salt.chunks_mut(4) {
+        let random = unsafe { esp_idf_sys::esp_random() }.to_le_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    hash_pin_with_salt(pin, &salt)
+}
+
+/// A PIN typed into the menu (`PIN`) is stored only as its hash (`PINHASH`).
+/// An empty entry keeps the current PIN.
+pub fn store_menu_pin(config: &mut Vec<(String, String)>) {
+    let pin = match config.iter().position(|it| it.0 == "PIN") {
+        Some(pos) => config.remove(pos).1,
+        None => return,
+    };
+    let pin = pin.trim();
+    if pin.is_empty() {
+        return;
+    }
+    if pin.len() < PIN_MIN_LEN || pin.len() > PIN_MAX_LEN || !pin.chars().all(|c| c.is_ascii_digit()) {
+        info!("[PIN] Ignored: {} to {} digits", PIN_MIN_LEN, PIN_MAX_LEN);
+        return;
+    }
+    let hash = hash_pin(pin);
+    match config.iter_mut().find(|it| it.0 == "PINHASH") {
+        Some(it) => it.1 = hash,
+        None => config.push(("PINHASH".to_string(), hash)),
+    }
+    info!("[PIN] New PIN set");
+}
+
+// Failed attempts are kept in NVS, so neither deep sleep nor a power cycle resets a lockout
+pub fn load_failures<T: NvsPartitionId>(nvs: &EspNvs<T>) -> u32 {
+    nvs.get_u32(NVS_FAILURES_KEY).ok().flatten().unwrap_or(0)
+}
+
+pub fn save_failures<T: NvsPartitionId>(nvs: &mut EspNvs<T>, failures: u32) {
+    if load_failures(nvs) == failures {
+        return;
+    }
+    if let Err(ref e) = nvs.set_u32(NVS_FAILURES_KEY, failures) {
+        info!("[PIN] Save failures failed {:?}", e);
+    }
+}
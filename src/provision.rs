@@ -0,0 +1,120 @@
+// SoftAP captive portal for first-time setup
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::{thread, time::Duration};
+
+use anyhow::Result;
+use esp_idf_hal::peripheral;
+use esp_idf_svc::http::server::{Configuration as HttpConfiguration, EspHttpServer};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::{Read, Write};
+use log::*;
+
+use crate::config::ConfigData;
+use crate::wifi;
+
+pub use wakepcbox_core::provision::*;
+
+const DNS_PORT: u16 = 53;
+const MAX_FORM_SIZE: usize = 1024;
+
+/// SoftAP SSID, e.g. `WakePCBox-1A2B` (last two bytes of the SoftAP MAC).
+pub fn portal_ssid() -> String {
+    let mut mac = [0u8; 6];
+    unsafe {
+        esp_idf_sys::esp_read_mac(mac.as_mut_ptr(), esp_idf_sys::esp_mac_type_t_ESP_MAC_WIFI_SOFTAP);
+    }
+    format!("WakePCBox-{:02X}{:02X}", mac[4], mac[5])
+}
+
+/// Start a SoftAP with a DNS responder and a setup form, and block until the form is submitted.
+///
+/// Returns the full config entries (current values overridden by the form) so the caller
+/// can persist them the same way as the default config.
+/// `set_status` is a closure called with a display message while waiting.
+pub fn run_portal(
+    modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
+    config_data: &ConfigData,
+    mut set_status: impl FnMut(&str, &str),
+) -> Result<Vec<(String, String)>> {
+    let ssid = portal_ssid();
+    info!("[SETUP] Starting SoftAP {}", ssid);
+    let wifi_dev = wifi::wifi_start_ap(modem, &ssid)?;
+    let ap_ip = wifi_dev.ap_netif().get_ip_info()?.ip;
+    info!("[SETUP] SoftAP IP: {}", ap_ip);
+    set_status(&format!("AP:{}", ssid), &format!("http://{}", ap_ip));
+
+    let _dns = thread::spawn(move || {
+        if let Err(e) = dns_responder(ap_ip) {
+            info!("[SETUP] DNS responder stopped: {:?}", e);
+        }
+    });
+
+    let submitted: Arc<Mutex<Option<Vec<(String, String)>>>> = Arc::new(Mutex::new(None));
+    let mut server = EspHttpServer::new(&HttpConfiguration {
+        uri_match_wildcard: true,
+        ..Default::default()
+    })?;
+
+    let entries = config_data.to_config_entries();
+    let form_page = setup_form(&entries, "");
+    let result = submitted.clone();
+    server.fn_handler::<anyhow::Error, _>("/save", Method::Post, move |mut req| {
+        let len = (req.content_len().unwrap_or(0) as usize).min(MAX_FORM_SIZE);
+        let mut buf = vec![0u8; len];
+        let mut read = 0;
+        while read < len {
+            let n = req.read(&mut buf[read..])?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        let form = parse_form(&String::from_utf8_lossy(&buf[..read]));
+        let new_entries = match apply_form(&entries, &form) {
+            Ok(new_entries) => new_entries,
+            Err(rejected) => {
+                info!("[SETUP] Invalid values: {:?}", rejected);
+                let error = format!("Invalid value: {}", rejected.join(", "));
+                req.into_response(400, None, &[("Content-Type", "text/html")])?
+                    .write_all(setup_form(&merge_form(&entries, &form), &error).as_bytes())?;
+                return Ok(());
+            },
+        };
+        *result.lock().unwrap() = Some(new_entries);
+        req.into_ok_response()?
+            .write_all(page("Saved", "<p>Settings saved. The box is restarting.</p>").as_bytes())?;
+        Ok(())
+    })?;
+    // Every other URL (including OS captive portal probes) gets the setup form.
+    server.fn_handler::<anyhow::Error, _>("/*", Method::Get, move |req| {
+        req.into_ok_response()?.write_all(form_page.as_bytes())?;
+        Ok(())
+    })?;
+
+    loop {
+        if let Some(new_entries) = submitted.lock().unwrap().take() {
+            info!("[SETUP] Settings submitted");
+            set_status("SETUP", "Saved");
+            // give the HTTP response time to reach the client
+            thread::sleep(Duration::from_secs(2));
+            return Ok(new_entries);
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+// Answer every A query with the SoftAP address so clients open the portal.
+fn dns_responder(ap_ip: Ipv4Addr) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DNS_PORT))?;
+    let mut buf = [0u8; 512];
+    loop {
+        let (len, src) = socket.recv_from(&mut buf)?;
+        if let Some(reply) = dns_reply(&buf[..len], ap_ip) {
+            let _ = socket.send_to(&reply, src);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+// Signed wake requests over UDP (HMAC-SHA256 with timestamp and nonce)
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+//
+// The request format and its verification are in wakepcbox_core::securewake.
+
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use log::*;
+
+use crate::control::{Control, TriggerSource};
+use crate::timesvc;
+
+pub use wakepcbox_core::securewake::*;
+
+const MAX_PACKET_SIZE: usize = 256;
+const SECURE_WAKE_STACK_SIZE: usize = 6144;
+
+/// Listen for signed requests on `SECURE_WAKE_PORT` and queue verified wakes.
+/// Verified requests get `OK <targets>` or `ERR unknown target` back; anything
+/// else is dropped without an answer.
+pub fn start(secret: &str, control: Control) -> Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", SECURE_WAKE_PORT))?;
+    let mut verifier = WakeVerifier::new(secret);
+    let _th = thread::Builder::new().stack_size(SECURE_WAKE_STACK_SIZE).spawn(move || {
+        info!("Start Secure Wake Thread. port {}", SECURE_WAKE_PORT);
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) => {
+                    info!("[SECUREWAKE] Receive failed: {:?}", e);
+                    continue;
+                },
+            };
+            if !timesvc::is_time_valid() {
+                info!("[SECUREWAKE] Request from {} ignored: clock not synced", peer);
+                continue;
+            }
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let packet = String::from_utf8_lossy(&buf[..len]);
+            match verifier.verify(&packet, now) {
+                Ok(name) => {
+                    let reply = match control.request_wake(&name, TriggerSource::SignedUdp) {
+                        Some(targets) => {
+                            info!("[SECUREWAKE] Wake request from {}: {:?}", peer, targets);
+                            format!("OK {}", targets.iter().map(|t| t.name()).collect::<Vec<&str>>().join(" "))
+                        },
+                        None => "ERR unknown target".to_string(),
+                    };
+                    let _ = socket.send_to(reply.as_bytes(), peer);
+                },
+                Err(e) => {
+                    info!("[SECUREWAKE] Request from {} rejected: {:?}", peer, e);
+                },
+            }
+        }
+    })?;
+    Ok(())
+}
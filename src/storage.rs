@@ -0,0 +1,28 @@
+// SPIFFS file storage in flash (certificates etc.)
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use anyhow::Result;
+use log::*;
+
+pub const STORAGE_BASE_PATH: &str = "/storage";
+const STORAGE_PARTITION: &str = "storage";
+
+/// Mount the `storage` SPIFFS partition at `/storage`.
+/// Files are written with `espflash`/`parttool` or a SPIFFS image built on the host.
+pub fn mount() -> Result<()> {
+    let base_path = std::ffi::CString::new(STORAGE_BASE_PATH)?;
+    let label = std::ffi::CString::new(STORAGE_PARTITION)?;
+    let conf = esp_idf_sys::esp_vfs_spiffs_conf_t {
+        base_path: base_path.as_ptr(),
+        partition_label: label.as_ptr(),
+        max_files: 4,
+        format_if_mount_failed: false,
+    };
+    unsafe {
+        esp_idf_sys::esp!(esp_idf_sys::esp_vfs_spiffs_register(&conf))?;
+    }
+    // the VFS keeps its own copy of the strings
+    info!("[STORAGE] Mounted {} at {}", STORAGE_PARTITION, STORAGE_BASE_PATH);
+    Ok(())
+}
@@ -0,0 +1,186 @@
+// Syslog (RFC 5424 over UDP) log backend alongside EspLogger, with a short in-memory history
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::collections::VecDeque;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+use std::thread;
+
+use esp_idf_svc::log::EspLogger;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::timesvc;
+
+pub use wakepcbox_core::syslog::*;
+
+const MAX_BUFFERED_RECORDS: usize = 64;
+const HISTORY_LEN: usize = 32;      // lines kept for `tail()`
+const DEFAULT_PORT: u16 = 514;
+const RETRY_INTERVAL: u64 = 2000;   // ms, while the network is down
+
+struct SyslogRecord {
+    level: Level,
+    time: Option<SystemTime>,   // None while the clock is not valid
+    target: String,
+    message: String,
+}
+
+struct SyslogState {
+    // None until configured: records are buffered so boot messages are not lost
+    enabled: Option<bool>,
+    level: LevelFilter,
+    queue: VecDeque<SyslogRecord>,
+    dropped: u32,
+    history: VecDeque<String>,
+}
+
+/// Global logger: every record goes to `EspLogger` (serial) and to the history,
+/// and records at or above the syslog level are queued for the syslog server.
+pub struct SyslogLogger {
+    esp_logger: EspLogger,
+    state: Mutex<SyslogState>,
+    cond: Condvar,
+}
+
+static LOGGER: SyslogLogger = SyslogLogger {
+    esp_logger: EspLogger::new(),
+    state: Mutex::new(SyslogState {
+        enabled: None,
+        level: LevelFilter::Info,
+        queue: VecDeque::new(),
+        dropped: 0,
+        history: VecDeque::new(),
+    }),
+    cond: Condvar::new(),
+};
+
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.esp_logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.esp_logger.enabled(record.metadata()) {
+            return;
+        }
+        self.esp_logger.log(record);
+        if let Ok(mut state) = self.state.lock() {
+            if state.history.len() >= HISTORY_LEN {
+                state.history.pop_front();
+            }
+            state.history.push_back(format!("{} {}: {}", record.level(), record.target(), record.args()));
+            // records from the sender itself would loop
+            if record.target() == module_path!() {
+                return;
+            }
+            if state.enabled == Some(false) || record.level() > state.level {
+                return;
+            }
+            if state.queue.len() >= MAX_BUFFERED_RECORDS {
+                state.queue.pop_front();
+                state.dropped += 1;
+            }
+            state.queue.push_back(SyslogRecord {
+                level: record.level(),
+                time: log_time(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+            self.cond.notify_one();
+        }
+    }
+
+    fn flush(&self) {
+        self.esp_logger.flush();
+    }
+}
+
+/// Install the logger. Call once at boot instead of `EspLogger::initialize_default()`.
+pub fn initialize() {
+    if log::set_logger(&LOGGER).is_ok() {
+        LOGGER.esp_logger.initialize();
+    }
+}
+
+/// Start forwarding to `server` (`host` or `host:port`) at `level` and above.
+/// With an empty server, forwarding is disabled and the boot buffer is dropped.
+pub fn start(server: &str, level: &str, hostname: &str) {
+    let server = server.trim().to_string();
+    {
+        let mut state = LOGGER.state.lock().unwrap();
+        state.enabled = Some(!server.is_empty());
+        state.level = parse_level(level);
+        if server.is_empty() {
+            state.queue.clear();
+            return;
+        }
+        let level = state.level;
+        state.queue.retain(|r| r.level <= level);
+    }
+    let hostname = hostname.to_string();
+    let _th = thread::spawn(move || {
+        log::info!("Start Syslog Thread. {}", server);
+        let socket = match UdpSocket::bind("0.0.0.0:0") {
+            Ok(socket) => socket,
+            Err(e) => {
+                log::info!("Syslog socket failed: {:?}", e);
+                return;
+            },
+        };
+        let mut addr : Option<SocketAddr> = None;
+        loop {
+            let (record, dropped) = {
+                let mut state = LOGGER.state.lock().unwrap();
+                while state.queue.is_empty() {
+                    state = LOGGER.cond.wait(state).unwrap();
+                }
+                (state.queue.pop_front().unwrap(), std::mem::take(&mut state.dropped))
+            };
+            let mut lines = Vec::new();
+            if dropped > 0 {
+                lines.push(format_rfc5424(Level::Warn, log_time(), &hostname, module_path!(),
+                    &format!("{} records dropped while offline", dropped)));
+            }
+            lines.push(format_rfc5424(record.level, record.time, &hostname, &record.target, &record.message));
+            // resolve lazily: DNS only works once Wi-Fi is up
+            if addr.is_none() {
+                addr = resolve(&server);
+            }
+            let sent = match addr {
+                Some(addr) => lines.iter().all(|line| socket.send_to(line.as_bytes(), addr).is_ok()),
+                None => false,
+            };
+            if !sent {
+                // network down: put the record back and retry later
+                let mut state = LOGGER.state.lock().unwrap();
+                if state.queue.len() < MAX_BUFFERED_RECORDS {
+                    state.queue.push_front(record);
+                }
+                else {
+                    state.dropped += 1;
+                }
+                state.dropped += dropped;
+                drop(state);
+                addr = None;
+                thread::sleep(Duration::from_millis(RETRY_INTERVAL));
+            }
+        }
+    });
+}
+
+fn log_time() -> Option<SystemTime> {
+    if timesvc::is_time_valid() { Some(SystemTime::now()) } else { None }
+}
+
+/// The last `count` log lines, oldest first.
+pub fn tail(count: usize) -> Vec<String> {
+    let state = LOGGER.state.lock().unwrap();
+    state.history.iter().skip(state.history.len().saturating_sub(count)).cloned().collect()
+}
+
+fn resolve(server: &str) -> Option<SocketAddr> {
+    let target = if server.contains(':') { server.to_string() } else { format!("{}:{}", server, DEFAULT_PORT) };
+    target.to_socket_addrs().ok().and_then(|mut it| it.next())
+}
@@ -0,0 +1,44 @@
+// Time service on the device: system clock and RTC memory
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use wakepcbox_core::timesvc::*;
+
+const RTC_MAGIC: u32 = 0x5449_4d45;     // "TIME"
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn epoch_secs(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+
+    fn uptime_secs(&self) -> u64 {
+        unsafe { esp_idf_sys::esp_timer_get_time() as u64 / 1_000_000 }
+    }
+}
+
+// RTC slow memory, not initialized at boot: kept across deep sleep and software
+// resets (panic, watchdog, esp_restart). After power-on it holds garbage, which
+// the magic and the checks in `TimeService::new` reject.
+#[link_section = ".rtc_noinit"]
+static mut RTC_TIME: [u64; 3] = [0; 3];     // magic, last_sync, last_known
+
+/// Load the record saved by `save_rtc()` at the last sync or before deep sleep.
+pub fn load_rtc() -> Option<PersistedTime> {
+    let data = unsafe { core::ptr::read_volatile(core::ptr::addr_of!(RTC_TIME)) };
+    if data[0] != RTC_MAGIC as u64 {
+        return None;
+    }
+    Some(PersistedTime { last_sync: data[1], last_known: data[2] })
+}
+
+pub fn save_rtc(persisted: Option<PersistedTime>) {
+    let data = match persisted {
+        Some(p) => [RTC_MAGIC as u64, p.last_sync, p.last_known],
+        None => [0; 3],
+    };
+    unsafe { core::ptr::write_volatile(core::ptr::addr_of_mut!(RTC_TIME), data) };
+}
@@ -14,6 +14,8 @@ pub enum InputTypeChar {
     TimezoneType,
     ActionType,
     SelectType,
+    ScanType,
+    DiagType,
 }
 
 #[derive(Debug, Clone)]
@@ -228,11 +230,19 @@ impl MenuTable {
         self.menu_item[sel].menu[sel_sub].select_item.len()
     }
 
+    // find sub menu index by key
+    pub fn find_menu_by_key(&self, sel: usize, key: &str) -> Option<usize> {
+        self.menu_item[sel].menu.iter().position(|m| m.key == key)
+    }
+
     // get set values from menu table
     pub fn get_all_values(&self) -> Vec<(String, String)> {
         let mut key_values: Vec<(String, String)> = Vec::new();
         for item in &self.menu_item {
             for menu in &item.menu {
+                if menu.value_type == InputTypeChar::ScanType || menu.value_type == InputTypeChar::DiagType {
+                    continue;
+                }
                 if menu.value_type != InputTypeChar::ActionType || menu.action_flag {
                     key_values.push((menu.key.clone() , menu.current_value.clone()));
                 }
@@ -395,6 +405,9 @@ impl MenuTable {
             InputTypeChar::SelectType => {
                 return ch;
             },
+            InputTypeChar::ScanType | InputTypeChar::DiagType => {
+                return ch;
+            },
         }
     }
 }
\ No newline at end of file
@@ -2,16 +2,7 @@ use anyhow::Result;
 use log::*;
 use wake_on_lan;
 
-const NUM_OF_MAX_TARGET: usize = 4;
-
-#[derive (Debug, Clone, Copy, PartialEq)]
-pub enum TargetDevice {
-    None,
-    Device1,
-    Device2,
-    Device3,
-    Device4,
-}
+pub use wakepcbox_core::targets::{find_group, parse_groups, parse_mac, TargetDevice, ALL_TARGETS, NUM_OF_MAX_TARGET};
 
 #[derive (Debug, Clone, Copy)]
 pub struct WakePacket {
@@ -25,22 +16,25 @@ impl WakePacket {
         }
     }
 
-    pub fn set_target_mac(&mut self, target: TargetDevice, mac: &str) {
-        let mut mac_address = [0; 6];
-        mac.split(":").collect::<Vec<&str>>().iter().enumerate().for_each(|(i, v)| {
-            mac_address[i] = u8::from_str_radix(v, 16).unwrap();
-        });
+    /// Set the MAC address of `target`. An invalid address is refused and
+    /// the target keeps its previous (initially all zero) address.
+    pub fn set_target_mac(&mut self, target: TargetDevice, mac: &str) -> Result<()> {
+        let mac_address = match parse_mac(mac) {
+            Some(mac_address) => mac_address,
+            None => anyhow::bail!("invalid MAC address '{}' for {}", mac, target.name()),
+        };
         info!("MAC address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", 
             mac_address[0], mac_address[1], mac_address[2],
             mac_address[3], mac_address[4], mac_address[5]);
     
         match target {
-            TargetDevice::Device1 => self.target_mac[0] = mac_address.clone(),
-            TargetDevice::Device2 => self.target_mac[1] = mac_address.clone(),
-            TargetDevice::Device3 => self.target_mac[2] = mac_address.clone(),
-            TargetDevice::Device4 => self.target_mac[3] = mac_address.clone(),
+            TargetDevice::Device1 => self.target_mac[0] = mac_address,
+            TargetDevice::Device2 => self.target_mac[1] = mac_address,
+            TargetDevice::Device3 => self.target_mac[2] = mac_address,
+            TargetDevice::Device4 => self.target_mac[3] = mac_address,
             TargetDevice::None => {},
         }
+        Ok(())
     }
 
     pub fn send_pkt(&self, target: TargetDevice) -> Result<(), &str>{
@@ -62,4 +56,24 @@ impl WakePacket {
             Err(e) => { info!("Magic packet sent failed: {:?}", e); Err("Magic packet sent failed")},
         }
     }
+
+    /// Sleep-On-LAN: a magic packet for the reversed MAC address, which the
+    /// sleep-on-lan service on the target turns into a suspend.
+    pub fn send_sleep_pkt(&self, target: TargetDevice) -> Result<(), &str>{
+        let mut mac_address : [u8; 6];
+        match target {
+            TargetDevice::Device1 => mac_address = self.target_mac[0],
+            TargetDevice::Device2 => mac_address = self.target_mac[1],
+            TargetDevice::Device3 => mac_address = self.target_mac[2],
+            TargetDevice::Device4 => mac_address = self.target_mac[3],
+            TargetDevice::None => return Err("Target device is not set"),
+        }
+        mac_address.reverse();
+        let magic_packet = wake_on_lan::MagicPacket::new(&mac_address);
+        info!("Send sleep packet to {:?}", target);
+        match magic_packet.send() {
+            Ok(_) => { info!("Sleep packet sent successfully"); Ok(())},
+            Err(e) => { info!("Sleep packet sent failed: {:?}", e); Err("Sleep packet sent failed")},
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,122 @@
+// Outgoing webhook notifications after wake attempts
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::{thread, time::Duration};
+
+use anyhow::{bail, Result};
+use embedded_svc::http::client::Client;
+use esp_idf_svc::http::client::{Configuration as HttpClientConfiguration, EspHttpConnection};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::Write;
+use log::*;
+
+pub use wakepcbox_core::webhook::*;
+
+const MAX_QUEUED_EVENTS: usize = 16;
+const HTTP_TIMEOUT_SECS: u64 = 10;
+const WEBHOOK_STACK_SIZE: usize = 8192;
+
+/// Webhook settings, taken from `ConfigData`.
+#[derive(Debug, Clone)]
+pub struct WebhookSettings {
+    pub url: String,
+    pub method: String,     // POST, PUT or GET (no body)
+    pub template: String,   // empty: DEFAULT_TEMPLATE
+}
+
+struct QueuedEvent {
+    body: String,
+    attempts: u32,
+}
+
+/// Queue of pending notifications, delivered by a background thread.
+/// Events are kept (up to `MAX_QUEUED_EVENTS`) while the network is down.
+pub struct Webhook {
+    queue: Arc<(Mutex<VecDeque<QueuedEvent>>, Condvar)>,
+    template: String,
+}
+
+impl Webhook {
+    pub fn start(settings: &WebhookSettings) -> Result<Webhook> {
+        let method = match settings.method.trim().to_ascii_uppercase().as_str() {
+            "PUT" => Method::Put,
+            "GET" => Method::Get,
+            _ => Method::Post,
+        };
+        let queue = Arc::new((Mutex::new(VecDeque::<QueuedEvent>::new()), Condvar::new()));
+        let th_queue = queue.clone();
+        let url = settings.url.clone();
+        let _th = thread::Builder::new().stack_size(WEBHOOK_STACK_SIZE).spawn(move || {
+            info!("Start Webhook Thread.");
+            let (lock, cond) = &*th_queue;
+            loop {
+                let mut event = {
+                    let mut queue = lock.lock().unwrap();
+                    while queue.is_empty() {
+                        queue = cond.wait(queue).unwrap();
+                    }
+                    queue.pop_front().unwrap()
+                };
+                if let Err(e) = send(&url, method, &event.body) {
+                    event.attempts += 1;
+                    info!("[WEBHOOK] Attempt {} failed: {:?}", event.attempts, e);
+                    let delay = match retry_after(event.attempts) {
+                        Some(delay) => delay,
+                        None => {
+                            info!("[WEBHOOK] Giving up: {}", event.body);
+                            continue;
+                        },
+                    };
+                    // keep the order: put it back in front unless newer events filled the queue
+                    let mut queue = lock.lock().unwrap();
+                    if queue.len() < MAX_QUEUED_EVENTS {
+                        queue.push_front(event);
+                    }
+                    drop(queue);
+                    thread::sleep(delay);
+                }
+            }
+        })?;
+        Ok(Webhook { queue, template: settings.template.clone() })
+    }
+
+    /// Queue a notification for a wake attempt. The oldest one is dropped if the queue is full.
+    pub fn notify(&self, event: &WakeEvent) {
+        let (lock, cond) = &*self.queue;
+        let mut queue = lock.lock().unwrap();
+        if queue.len() >= MAX_QUEUED_EVENTS {
+            info!("[WEBHOOK] Queue full, dropping the oldest event");
+            queue.pop_front();
+        }
+        queue.push_back(QueuedEvent { body: render_template(&self.template, event), attempts: 0 });
+        cond.notify_one();
+    }
+}
+
+fn send(url: &str, method: Method, body: &str) -> Result<()> {
+    let connection = EspHttpConnection::new(&HttpClientConfiguration {
+        timeout: Some(Duration::from_secs(HTTP_TIMEOUT_SECS)),
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let mut client = Client::wrap(connection);
+    let status = if method == Method::Get {
+        client.get(url)?.submit()?.status()
+    }
+    else {
+        let length = body.len().to_string();
+        let headers = [("Content-Type", "application/json"), ("Content-Length", length.as_str())];
+        let mut request = client.request(method, url, &headers)?;
+        request.write_all(body.as_bytes())?;
+        request.flush()?;
+        request.submit()?.status()
+    };
+    if !(200..300).contains(&status) {
+        bail!("HTTP status {}", status);
+    }
+    info!("[WEBHOOK] Delivered ({})", status);
+    Ok(())
+}
@@ -7,12 +7,13 @@
 use std::time::Duration;
 use std::thread;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
 
 use esp_idf_hal::peripheral;
 use esp_idf_svc::{eventloop::EspSystemEventLoop, handle::RawHandle, wifi::EspWifi};
 use esp_idf_sys;
 
-use embedded_svc::wifi::{ClientConfiguration, Configuration};
+use embedded_svc::wifi::{AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration};
 use anyhow::bail;
 use anyhow::Result;
 use std::str::FromStr;
@@ -37,14 +38,41 @@ fn wait_for_dhcp_ip(
     None
 }
 
+/// Restart the station DHCP client, e.g. when association succeeded but no lease arrived.
+pub fn restart_dhcp(wifi: &EspWifi<'_>) {
+    unsafe {
+        let handle = wifi.sta_netif().handle();
+        let _ = esp_idf_sys::esp_netif_dhcpc_stop(handle);
+        let rc = esp_idf_sys::esp_netif_dhcpc_start(handle);
+        if rc != esp_idf_sys::ESP_OK as i32 {
+            info!("[WIFI] esp_netif_dhcpc_start failed: 0x{:x}", rc);
+        }
+    }
+}
+
 // ─── WPS state (shared between C event handler and Rust polling loop) ─────────
 
 static WPS_SUCCESS: AtomicBool = AtomicBool::new(false);
 static WPS_FAILED:  AtomicBool = AtomicBool::new(false);
 static WPS_GOT_CREDS: AtomicBool = AtomicBool::new(false);
+static WPS_GOT_PIN: AtomicBool = AtomicBool::new(false);
 
 static mut WPS_SSID_BUF: [u8; 33] = [0u8; 33];
 static mut WPS_PASS_BUF: [u8; 65] = [0u8; 65];
+static mut WPS_PIN_BUF: [u8; 8] = [0u8; 8];
+
+/// WPS method: push button on the router, or a PIN entered in the router's admin UI.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WpsMode {
+    Pbc,
+    Pin,
+}
+
+impl WpsMode {
+    pub fn from_str(mode: &str) -> WpsMode {
+        if mode.eq_ignore_ascii_case("pin") { WpsMode::Pin } else { WpsMode::Pbc }
+    }
+}
 
 unsafe extern "C" fn wps_event_handler(
     _arg: *mut core::ffi::c_void,
@@ -72,31 +100,128 @@ unsafe extern "C" fn wps_event_handler(
             }
         }
         WPS_SUCCESS.store(true, Ordering::Release);
+    } else if id == wifi_event_t_WIFI_EVENT_STA_WPS_ER_PIN {
+        if !event_data.is_null() {
+            let evt = &*(event_data as *const wifi_event_sta_wps_er_pin_t);
+            let dst = core::ptr::addr_of_mut!(WPS_PIN_BUF) as *mut u8;
+            core::ptr::copy_nonoverlapping(evt.pin_code.as_ptr(), dst, 8);
+            WPS_GOT_PIN.store(true, Ordering::Release);
+        }
     } else if id == wifi_event_t_WIFI_EVENT_STA_WPS_ER_FAILED
            || id == wifi_event_t_WIFI_EVENT_STA_WPS_ER_TIMEOUT {
         WPS_FAILED.store(true, Ordering::Release);
     }
 }
 
+/// How the station authenticates to the access point.
+#[derive(Debug, Clone)]
+pub enum WifiAuth {
+    Psk(String),            // WPA/WPA2/WPA3 personal passphrase
+    Eap(EapSettings),       // WPA2-Enterprise (802.1X)
+}
+
+/// WPA2-Enterprise (PEAP/TTLS) credentials.
+#[derive(Debug, Clone, Default)]
+pub struct EapSettings {
+    pub identity: String,       // outer identity; the username is used when empty
+    pub username: String,
+    pub password: String,
+    pub ca_cert_path: String,   // PEM file in flash, e.g. /storage/ca.pem; empty: no server validation
+}
+
+/// Modem power save mode (`esp_wifi_set_ps`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerSaveMode {
+    None,       // always on: lowest latency, highest current
+    MinModem,   // wake every DTIM (ESP-IDF default)
+    MaxModem,   // wake every listen interval
+}
+
+impl PowerSaveMode {
+    pub fn from_str(mode: &str) -> PowerSaveMode {
+        match mode {
+            "none" => PowerSaveMode::None,
+            "max" => PowerSaveMode::MaxModem,
+            _ => PowerSaveMode::MinModem,
+        }
+    }
+}
+
+/// Power settings applied before every connect (boot, reconnect, WPS).
+#[derive(Debug, Clone, Copy)]
+pub struct PowerSettings {
+    pub power_save: PowerSaveMode,
+    pub listen_interval: u16,   // beacon intervals, used by MaxModem
+    pub max_tx_power: u8,       // dBm, 0: driver default
+}
+
+static POWER_SETTINGS: Mutex<PowerSettings> = Mutex::new(PowerSettings {
+    power_save: PowerSaveMode::MinModem,
+    listen_interval: 3,
+    max_tx_power: 0,
+});
+
+pub fn set_power_settings(settings: PowerSettings) {
+    *POWER_SETTINGS.lock().unwrap() = settings;
+}
+
+// Must be called after start() and before connect(); the listen interval is only
+// taken over on association.
+pub fn apply_power_settings() {
+    let settings = *POWER_SETTINGS.lock().unwrap();
+    unsafe {
+        let mut conf: esp_idf_sys::wifi_config_t = core::mem::zeroed();
+        if esp_idf_sys::esp_wifi_get_config(esp_idf_sys::wifi_interface_t_WIFI_IF_STA, &mut conf) == esp_idf_sys::ESP_OK as i32 {
+            conf.sta.listen_interval = settings.listen_interval;
+            esp_idf_sys::esp_wifi_set_config(esp_idf_sys::wifi_interface_t_WIFI_IF_STA, &mut conf);
+        }
+        let ps = match settings.power_save {
+            PowerSaveMode::None => esp_idf_sys::wifi_ps_type_t_WIFI_PS_NONE,
+            PowerSaveMode::MinModem => esp_idf_sys::wifi_ps_type_t_WIFI_PS_MIN_MODEM,
+            PowerSaveMode::MaxModem => esp_idf_sys::wifi_ps_type_t_WIFI_PS_MAX_MODEM,
+        };
+        esp_idf_sys::esp_wifi_set_ps(ps);
+        if settings.max_tx_power > 0 {
+            // unit is 0.25 dBm, valid range 8..=84
+            let quarter_dbm = (settings.max_tx_power.min(21) as i8 * 4).clamp(8, 84);
+            esp_idf_sys::esp_wifi_set_max_tx_power(quarter_dbm);
+        }
+    }
+    info!("[WIFI] Power save {:?}, listen interval {}, max TX power {} dBm",
+        settings.power_save, settings.listen_interval, settings.max_tx_power);
+}
+
+// The EAP client keeps a pointer to the CA certificate, so it must live forever.
+static CA_CERT: OnceLock<Vec<u8>> = OnceLock::new();
+
 pub fn wifi_connect(
     modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
     ssid: &str,
-    pass: &str,
+    auth: &WifiAuth,
+    hostname: &str,
 ) -> Result<Box<EspWifi<'static>>> {
 
-    if ssid.is_empty() || pass.is_empty() {
-        bail!("SSID or password is empty");
+    if ssid.is_empty() {
+        bail!("SSID is empty");
+    }
+    if let WifiAuth::Psk(pass) = auth {
+        if pass.is_empty() {
+            bail!("password is empty");
+        }
     }
     let sys_event_loop = EspSystemEventLoop::take().unwrap();
     let mut wifi = Box::new(EspWifi::new(modem, sys_event_loop.clone(), None).unwrap());
+    // sent as DHCP option 12; must be set before the DHCP client starts.
+    // Not fatal: the box is still reachable by IP address.
+    if let Err(ref e) = wifi.sta_netif_mut().set_hostname(hostname) {
+        info!("[WIFI] Set hostname failed {:?}", e);
+    }
 
-    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
-        ssid: heapless::String::<32>::from_str(ssid).unwrap(),
-        password: heapless::String::<64>::from_str(pass).unwrap(),
-        ..Default::default()
-    })).unwrap();
+    wifi.set_configuration(&client_configuration(ssid, auth)?)?;
+    set_enterprise(auth)?;
 
     wifi.start().unwrap();
+    apply_power_settings();
     wifi.connect()?;
     let mut timeout = 0;
     loop {
@@ -114,26 +239,155 @@ pub fn wifi_connect(
     Ok(wifi)
 }
 
-/// Connect using WPS PBC (Push Button Configuration).
+/// Switch a running driver to the given network and start connecting.
+pub fn wifi_set_client(wifi: &mut EspWifi<'static>, ssid: &str, auth: &WifiAuth) -> Result<()> {
+    wifi.set_configuration(&client_configuration(ssid, auth)?)?;
+    set_enterprise(auth)?;
+    if !wifi.is_started()? {
+        wifi.start()?;
+    }
+    apply_power_settings();
+    wifi.connect()?;
+    Ok(())
+}
+
+fn client_configuration(ssid: &str, auth: &WifiAuth) -> Result<Configuration> {
+    let ssid = heapless::String::<32>::from_str(ssid)
+        .map_err(|_| anyhow::anyhow!("SSID too long"))?;
+    Ok(match auth {
+        WifiAuth::Psk(pass) => Configuration::Client(ClientConfiguration {
+            ssid,
+            password: heapless::String::<64>::from_str(pass)
+                .map_err(|_| anyhow::anyhow!("passphrase too long"))?,
+            ..Default::default()
+        }),
+        WifiAuth::Eap(_) => Configuration::Client(ClientConfiguration {
+            ssid,
+            auth_method: AuthMethod::WPA2Enterprise,
+            ..Default::default()
+        }),
+    })
+}
+
+// Enable or disable 802.1X on the station. Must be called after set_configuration
+// and before connect.
+fn set_enterprise(auth: &WifiAuth) -> Result<()> {
+    let eap = match auth {
+        WifiAuth::Psk(_) => {
+            unsafe {
+                esp_idf_sys::esp_wifi_sta_enterprise_disable();
+            }
+            return Ok(());
+        },
+        WifiAuth::Eap(eap) => eap,
+    };
+    if eap.username.is_empty() || eap.password.is_empty() {
+        bail!("EAP username or password is empty");
+    }
+    let identity = if eap.identity.is_empty() { &eap.username } else { &eap.identity };
+    unsafe {
+        esp_idf_sys::esp!(esp_idf_sys::esp_eap_client_set_identity(identity.as_ptr(), identity.len() as i32))?;
+        esp_idf_sys::esp!(esp_idf_sys::esp_eap_client_set_username(eap.username.as_ptr(), eap.username.len() as i32))?;
+        esp_idf_sys::esp!(esp_idf_sys::esp_eap_client_set_password(eap.password.as_ptr(), eap.password.len() as i32))?;
+        if eap.ca_cert_path.is_empty() {
+            esp_idf_sys::esp_eap_client_clear_ca_cert();
+        }
+        else {
+            let cert = match CA_CERT.get() {
+                Some(cert) => cert,
+                None => {
+                    let mut pem = std::fs::read(&eap.ca_cert_path)
+                        .map_err(|e| anyhow::anyhow!("cannot read CA certificate {}: {}", eap.ca_cert_path, e))?;
+                    // mbedTLS expects the PEM length to include the terminating NUL
+                    pem.push(0);
+                    CA_CERT.get_or_init(|| pem)
+                },
+            };
+            esp_idf_sys::esp!(esp_idf_sys::esp_eap_client_set_ca_cert(cert.as_ptr(), cert.len() as i32))?;
+            info!("[EAP] CA certificate loaded from {}", eap.ca_cert_path);
+        }
+        esp_idf_sys::esp!(esp_idf_sys::esp_wifi_sta_enterprise_enable())?;
+    }
+    info!("[EAP] WPA2-Enterprise enabled for {}", eap.username);
+    Ok(())
+}
+
+/// Start an open SoftAP (used by the setup portal).
+pub fn wifi_start_ap(
+    modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
+    ssid: &str,
+) -> Result<Box<EspWifi<'static>>> {
+    let sys_event_loop = EspSystemEventLoop::take().unwrap();
+    let mut wifi = Box::new(EspWifi::new(modem, sys_event_loop, None)?);
+
+    wifi.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+        ssid: heapless::String::<32>::from_str(ssid)
+            .map_err(|_| anyhow::anyhow!("AP SSID too long"))?,
+        auth_method: AuthMethod::None,
+        channel: 1,
+        ..Default::default()
+    }))?;
+    wifi.start()?;
+    info!("[AP] SoftAP started: {}", ssid);
+    Ok(wifi)
+}
+
+/// Connect using WPS PBC (Push Button Configuration) or WPS PIN.
 ///
 /// Returns `(EspWifi, ssid, passphrase)` so the caller can persist credentials.
 /// `set_status` is a closure called with a display message during the WPS wait.
+/// In PIN mode the title is the PIN (e.g. `1234 5670`) once it has been generated.
 pub fn wifi_connect_wps(
     modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
-    mut set_status: impl FnMut(&str, &str),
+    mode: WpsMode,
+    hostname: &str,
+    set_status: impl FnMut(&str, &str),
 ) -> Result<(Box<EspWifi<'static>>, String, String)> {
-    info!("[WPS] Starting WPS PBC — press the WPS button on your router within 120 s");
-    set_status("WPS MODE", "Push WPS button");
-
     let sys_event_loop = EspSystemEventLoop::take().unwrap();
     let mut wifi = Box::new(EspWifi::new(modem, sys_event_loop, None).unwrap());
+    wifi.sta_netif_mut().set_hostname(hostname)?;
+    let (ssid, pass) = wifi_wps(&mut wifi, mode, set_status)?;
+    Ok((wifi, ssid, pass))
+}
 
-    wifi.set_configuration(&Configuration::Client(ClientConfiguration::default())).unwrap();
-    wifi.start().unwrap();
+/// Run WPS on an already created `EspWifi` (e.g. started from the menu at runtime).
+///
+/// The current STA connection is dropped first. On success the driver is connected
+/// with the obtained credentials and `(ssid, passphrase)` is returned.
+pub fn wifi_wps(
+    wifi: &mut EspWifi<'static>,
+    mode: WpsMode,
+    mut set_status: impl FnMut(&str, &str),
+) -> Result<(String, String)> {
+    let mut title = String::from("WPS MODE");
+    match mode {
+        WpsMode::Pbc => {
+            info!("[WPS] Starting WPS PBC — press the WPS button on your router within 120 s");
+            set_status(&title, "Push WPS button");
+        },
+        WpsMode::Pin => {
+            info!("[WPS] Starting WPS PIN — enter the PIN in your router within 120 s");
+            title = String::from("WPS PIN");
+            set_status(&title, "Generating PIN");
+        },
+    }
+
+    if wifi.is_started()? && wifi.is_connected()? {
+        let _ = wifi.disconnect();
+    }
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+    // WPS hands out a passphrase; leave enterprise mode if it was on
+    unsafe {
+        esp_idf_sys::esp_wifi_sta_enterprise_disable();
+    }
+    if !wifi.is_started()? {
+        wifi.start()?;
+    }
 
     WPS_SUCCESS.store(false, Ordering::Release);
     WPS_FAILED.store(false, Ordering::Release);
     WPS_GOT_CREDS.store(false, Ordering::Release);
+    WPS_GOT_PIN.store(false, Ordering::Release);
 
     unsafe {
         esp_idf_sys::esp_event_handler_register(
@@ -143,7 +397,10 @@ pub fn wifi_connect_wps(
             core::ptr::null_mut(),
         );
         let cfg = esp_idf_sys::esp_wps_config_t {
-            wps_type: esp_idf_sys::wps_type_WPS_TYPE_PBC,
+            wps_type: match mode {
+                WpsMode::Pbc => esp_idf_sys::wps_type_WPS_TYPE_PBC,
+                WpsMode::Pin => esp_idf_sys::wps_type_WPS_TYPE_PIN,
+            },
             ..Default::default()
         };
         let ret = esp_idf_sys::esp_wifi_wps_enable(&cfg);
@@ -171,10 +428,18 @@ pub fn wifi_connect_wps(
     for elapsed in 0..120u32 {
         thread::sleep(Duration::from_secs(1));
 
+        if WPS_GOT_PIN.swap(false, Ordering::AcqRel) {
+            let pin = unsafe { core::ptr::read(core::ptr::addr_of!(WPS_PIN_BUF)) };
+            let pin = String::from_utf8_lossy(&pin).into_owned();
+            info!("[WPS] PIN: {}", pin);
+            title = format!("{} {}", &pin[..4], &pin[4..]);
+            set_status(&title, &format!("{}s remaining", 120 - elapsed));
+        }
+
         if elapsed % 10 == 0 && elapsed > 0 {
             let remaining = 120 - elapsed;
             info!("[WPS] Waiting… {}s elapsed, {}s remaining", elapsed, remaining);
-            set_status("WPS MODE", &format!("{}s remaining", remaining));
+            set_status(&title, &format!("{}s remaining", remaining));
         }
 
         if WPS_FAILED.load(Ordering::Acquire) {
@@ -245,6 +510,7 @@ pub fn wifi_connect_wps(
                     .map_err(|_| anyhow::anyhow!("WPS passphrase too long"))?,
                 ..Default::default()
             })).unwrap();
+            apply_power_settings();
             wifi.connect()?;
 
             let mut t = 0;
@@ -252,14 +518,6 @@ pub fn wifi_connect_wps(
                 if wifi.is_connected().unwrap() {
                     info!("[WPS] Waiting for connection stabilization...");
                     thread::sleep(Duration::from_secs(3));
-                    unsafe {
-                        let ret = esp_idf_sys::esp_wifi_set_ps(
-                            esp_idf_sys::wifi_ps_type_t_WIFI_PS_NONE,
-                        );
-                        if ret == esp_idf_sys::ESP_OK as i32 {
-                            info!("[WPS] WiFi power management disabled (WIFI_PS_NONE)");
-                        }
-                    }
                     break;
                 }
                 thread::sleep(Duration::from_secs(1));
@@ -268,20 +526,13 @@ pub fn wifi_connect_wps(
             }
 
             // WPS reports connected before DHCP converges; retry if needed.
-            if wait_for_dhcp_ip(&wifi, 15, "WPS").is_none() {
+            if wait_for_dhcp_ip(wifi, 15, "WPS").is_none() {
                 info!("[WPS] DHCP still 0.0.0.0 — restarting DHCP client");
-                unsafe {
-                    let handle = wifi.sta_netif().handle();
-                    let _ = esp_idf_sys::esp_netif_dhcpc_stop(handle);
-                    let rc = esp_idf_sys::esp_netif_dhcpc_start(handle);
-                    if rc != esp_idf_sys::ESP_OK as i32 {
-                        info!("[WPS] esp_netif_dhcpc_start failed: 0x{:x}", rc);
-                    }
-                }
-                let _ = wait_for_dhcp_ip(&wifi, 20, "WPS");
+                restart_dhcp(wifi);
+                let _ = wait_for_dhcp_ip(wifi, 20, "WPS");
             }
 
-            return Ok((wifi, ssid, pass));
+            return Ok((ssid, pass));
         }
     }
 
@@ -296,6 +547,50 @@ pub fn wifi_connect_wps(
     bail!("[WPS] WPS timed out after 120 s");
 }
 
+/// One entry of a Wi-Fi scan, for the SSID pick-list.
+#[derive(Debug, Clone)]
+pub struct ScanResult {
+    pub ssid: String,
+    pub rssi: i32,
+    pub auth: &'static str,
+}
+
+/// Scan for access points. Hidden networks are skipped and each SSID is listed once
+/// (strongest signal first).
+pub fn wifi_scan(wifi: &mut EspWifi<'_>) -> Result<Vec<ScanResult>> {
+    info!("[SCAN] Start");
+    let ap_list = wifi.scan()?;
+    let mut result: Vec<ScanResult> = Vec::new();
+    for ap in ap_list {
+        let ssid = ap.ssid.as_str().to_string();
+        if ssid.is_empty() || result.iter().any(|r| r.ssid == ssid) {
+            continue;
+        }
+        result.push(ScanResult {
+            ssid,
+            rssi: ap.signal_strength as i32,
+            auth: auth_method_name(ap.auth_method),
+        });
+    }
+    result.sort_by(|a, b| b.rssi.cmp(&a.rssi));
+    info!("[SCAN] {} networks found", result.len());
+    Ok(result)
+}
+
+fn auth_method_name(auth: Option<AuthMethod>) -> &'static str {
+    match auth {
+        None | Some(AuthMethod::None) => "OPEN",
+        Some(AuthMethod::WEP) => "WEP",
+        Some(AuthMethod::WPA) => "WPA",
+        Some(AuthMethod::WPAWPA2Personal) => "WPA2",
+        Some(AuthMethod::WPA2Personal) => "WPA2",
+        Some(AuthMethod::WPA2WPA3Personal) => "WPA3",
+        Some(AuthMethod::WPA3Personal) => "WPA3",
+        Some(AuthMethod::WPA2Enterprise) => "EAP",
+        _ => "?",
+    }
+}
+
 pub fn get_rssi() -> i32 {
     unsafe {
         let mut rssi: i32 = 0;
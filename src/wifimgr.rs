@@ -0,0 +1,152 @@
+// Event driven Wi-Fi reconnection with exponential backoff
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2024 Hiroshi Nakajima
+
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use esp_idf_svc::wifi::EspWifi;
+use log::*;
+
+use crate::wifi;
+
+pub use wakepcbox_core::wifimgr::*;
+
+const MAX_PENDING_EVENTS: usize = 16;
+
+// Events from the system event loop, in order of arrival.
+static PENDING_EVENTS: Mutex<Vec<NetEvent>> = Mutex::new(Vec::new());
+
+unsafe extern "C" fn net_event_handler(
+    _arg: *mut core::ffi::c_void,
+    base: esp_idf_sys::esp_event_base_t,
+    event_id: i32,
+    event_data: *mut core::ffi::c_void,
+) {
+    use esp_idf_sys::*;
+    let id = event_id as u32;
+    let event = if base == WIFI_EVENT && id == wifi_event_t_WIFI_EVENT_STA_CONNECTED {
+        Some(NetEvent::StaConnected)
+    } else if base == WIFI_EVENT && id == wifi_event_t_WIFI_EVENT_STA_DISCONNECTED {
+        let reason = if event_data.is_null() {
+            0
+        } else {
+            (*(event_data as *const wifi_event_sta_disconnected_t)).reason as u16
+        };
+        Some(NetEvent::StaDisconnected(reason))
+    } else if base == IP_EVENT && id == ip_event_t_IP_EVENT_STA_GOT_IP && !event_data.is_null() {
+        let evt = &*(event_data as *const ip_event_got_ip_t);
+        Some(NetEvent::GotIp(Ipv4Addr::from(u32::from_be(evt.ip_info.ip.addr))))
+    } else {
+        None
+    };
+    if let Some(event) = event {
+        if let Ok(mut events) = PENDING_EVENTS.lock() {
+            if events.len() >= MAX_PENDING_EVENTS {
+                events.remove(0);
+            }
+            events.push(event);
+        }
+    }
+}
+
+/// Drives `WifiStateMachine` from system event loop events.
+pub struct WifiManager {
+    sm: WifiStateMachine,
+    start: Instant,
+}
+
+impl WifiManager {
+    /// Register the event handlers. `ip_addr` is the address from the boot-time connect, if any.
+    pub fn new(ip_addr: Ipv4Addr) -> WifiManager {
+        unsafe {
+            esp_idf_sys::esp_event_handler_register(
+                esp_idf_sys::WIFI_EVENT,
+                esp_idf_sys::ESP_EVENT_ANY_ID,
+                Some(net_event_handler),
+                core::ptr::null_mut(),
+            );
+            esp_idf_sys::esp_event_handler_register(
+                esp_idf_sys::IP_EVENT,
+                esp_idf_sys::ip_event_t_IP_EVENT_STA_GOT_IP as i32,
+                Some(net_event_handler),
+                core::ptr::null_mut(),
+            );
+        }
+        let mut sm = WifiStateMachine::new(0);
+        if ip_addr != Ipv4Addr::UNSPECIFIED {
+            sm.handle_event(NetEvent::GotIp(ip_addr), 0);
+        }
+        WifiManager { sm, start: Instant::now() }
+    }
+
+    /// Process pending events and run the next action. Call once per main loop.
+    pub fn poll(&mut self, wifi_dev: &mut EspWifi<'static>) -> WifiState {
+        let now = self.now_ms();
+        let events : Vec<NetEvent> = match PENDING_EVENTS.lock() {
+            Ok(mut events) => events.drain(..).collect(),
+            Err(_) => Vec::new(),
+        };
+        for event in events {
+            info!("[WIFI] Event {:?}", event);
+            self.sm.handle_event(event, now);
+        }
+        match self.sm.next_action(now) {
+            WifiAction::Connect => {
+                info!("[WIFI] Connecting");
+                let result = wifi_dev.is_started().and_then(|started| {
+                    if !started {
+                        wifi_dev.start()?;
+                    }
+                    wifi::apply_power_settings();
+                    wifi_dev.connect()
+                });
+                if let Err(ref e) = result {
+                    info!("[WIFI] Connect failed {:?}", e);
+                }
+            },
+            WifiAction::RestartDhcp => {
+                info!("[WIFI] No IP after association — restarting DHCP client");
+                wifi::restart_dhcp(wifi_dev);
+            },
+            WifiAction::None => {},
+        }
+        self.sm.get_state()
+    }
+
+    pub fn suspend(&mut self) {
+        let now = self.now_ms();
+        self.sm.suspend(now);
+    }
+
+    pub fn resume(&mut self) {
+        let now = self.now_ms();
+        // drop events caused by stopping Wi-Fi
+        if let Ok(mut events) = PENDING_EVENTS.lock() {
+            events.clear();
+        }
+        self.sm.resume(now);
+    }
+
+    pub fn get_state(&self) -> WifiState {
+        self.sm.get_state()
+    }
+
+    pub fn get_ip_addr(&self) -> Ipv4Addr {
+        self.sm.get_ip_addr()
+    }
+
+    pub fn get_reconnect_count(&self) -> u32 {
+        self.sm.get_reconnect_count()
+    }
+
+    /// Last disconnect reason as text for the Status screen, e.g. `201 NO_AP_FOUND`.
+    pub fn get_last_reason_text(&self) -> Option<String> {
+        self.sm.get_last_reason().map(|r| format!("{} {}", r, disconnect_reason_name(r)))
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
@@ -0,0 +1,58 @@
+#!/usr/bin/env python3
+# Send a signed wake request to a WakePCBox (see core/src/securewake.rs).
+# SPDX-License-Identifier: MIT
+#
+# usage: python3 tools/signed_wake.py <host> <target|group> [--secret S] [--port 9199]
+#        the secret can also be given in the WAKEPCBOX_SECRET environment variable.
+#        --print only prints the packet (e.g. to replay it and see it rejected).
+
+import argparse
+import hashlib
+import hmac
+import os
+import secrets
+import socket
+import sys
+import time
+
+PROTOCOL_ID = "WPB1"
+
+
+def sign_request(secret: str, timestamp: int, nonce: str, target: str) -> str:
+    text = f"{PROTOCOL_ID} {timestamp} {nonce} {target}"
+    mac = hmac.new(secret.encode(), text.encode(), hashlib.sha256).hexdigest()
+    return f"{text} {mac}"
+
+
+def main() -> int:
+    parser = argparse.ArgumentParser(description="Send a signed wake request")
+    parser.add_argument("host")
+    parser.add_argument("target", help="PC1..PC4, 1..4 or a wake group name")
+    parser.add_argument("--secret", default=os.environ.get("WAKEPCBOX_SECRET", ""))
+    parser.add_argument("--port", type=int, default=9199)
+    parser.add_argument("--timeout", type=float, default=3.0)
+    parser.add_argument("--print", action="store_true", help="print the packet instead of sending it")
+    args = parser.parse_args()
+    if not args.secret:
+        parser.error("no secret (--secret or WAKEPCBOX_SECRET)")
+
+    packet = sign_request(args.secret, int(time.time()), secrets.token_hex(16), args.target)
+    if args.print:
+        print(packet)
+        return 0
+
+    with socket.socket(socket.AF_INET, socket.SOCK_DGRAM) as sock:
+        sock.settimeout(args.timeout)
+        sock.sendto(packet.encode(), (args.host, args.port))
+        try:
+            reply, _ = sock.recvfrom(256)
+        except socket.timeout:
+            # rejected requests get no answer
+            print("no answer (rejected, or the box is asleep)", file=sys.stderr)
+            return 1
+    print(reply.decode(errors="replace"))
+    return 0 if reply.startswith(b"OK") else 1
+
+
+if __name__ == "__main__":
+    sys.exit(main())
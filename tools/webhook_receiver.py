@@ -0,0 +1,33 @@
+#!/usr/bin/env python3
+# Local stand-in for a webhook endpoint: prints every request it receives.
+# SPDX-License-Identifier: MIT
+#
+# usage: python3 tools/webhook_receiver.py [port]
+#        then set webhook_url = "http://<this host>:8080/wake"
+#        start with --fail to answer 503 and watch the box retry.
+
+import sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+FAIL = "--fail" in sys.argv
+
+
+class Handler(BaseHTTPRequestHandler):
+    def handle_any(self):
+        length = int(self.headers.get("Content-Length", 0))
+        body = self.rfile.read(length).decode("utf-8", "replace")
+        print(f"{self.command} {self.path} {body}", flush=True)
+        self.send_response(503 if FAIL else 200)
+        self.end_headers()
+
+    do_GET = do_POST = do_PUT = handle_any
+
+    def log_message(self, *args):
+        pass
+
+
+if __name__ == "__main__":
+    args = [a for a in sys.argv[1:] if not a.startswith("--")]
+    port = int(args[0]) if args else 8080
+    print(f"Listening on port {port}", flush=True)
+    HTTPServer(("", port), Handler).serve_forever()